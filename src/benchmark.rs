@@ -0,0 +1,107 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    demand_model::DemandModel,
+    simulation_world::{SimulationWorld, ORIGINS},
+};
+
+/// What a benchmark run simulates
+#[derive(Clone, Copy, Debug)]
+pub struct BenchmarkConfig {
+    pub ticks: usize,
+    /// How cars arrive at every approach. The random spawns are turned off.
+    pub demand: DemandModel,
+    /// Most cars in the world at once, if there's a limit
+    pub max_cars: Option<usize>,
+    pub seed: u64,
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> BenchmarkConfig {
+        BenchmarkConfig {
+            ticks: 10_000,
+            demand: DemandModel::Poisson { rate: 0.02 },
+            max_cars: Some(200),
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BenchmarkResult {
+    pub wall_time: Duration,
+    pub ticks_per_second: f64,
+    pub average_frame_time: Duration,
+    /// Most cars that were in the world at once
+    pub peak_cars: usize,
+}
+
+impl fmt::Display for BenchmarkResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Wall time:      {:.3} s", self.wall_time.as_secs_f64())?;
+        writeln!(f, "Ticks/second:   {:.0}", self.ticks_per_second)?;
+        writeln!(
+            f,
+            "Frame time:     {:.3} ms",
+            self.average_frame_time.as_secs_f64() * 1000.0
+        )?;
+        write!(f, "Peak cars:      {}", self.peak_cars)
+    }
+}
+
+/// Runs a world without drawing it for `config.ticks` updates and times it
+pub fn run_benchmark(config: BenchmarkConfig) -> BenchmarkResult {
+    let mut world = SimulationWorld::new();
+    world.random_spawns = false;
+    world.max_cars = config.max_cars;
+    world.set_seed(config.seed);
+    for origin in ORIGINS {
+        world.set_demand(origin, config.demand);
+    }
+
+    let mut peak_cars = 0;
+    let start = Instant::now();
+    for _ in 0..config.ticks {
+        world.update();
+        peak_cars = peak_cars.max(world.cars.len());
+    }
+    let wall_time = start.elapsed();
+
+    BenchmarkResult {
+        wall_time,
+        // Nothing ran, so there's no rate to speak of
+        ticks_per_second: if config.ticks == 0 {
+            0.0
+        } else {
+            config.ticks as f64 / wall_time.as_secs_f64()
+        },
+        average_frame_time: wall_time.div_f64(config.ticks.max(1) as f64),
+        peak_cars,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn benchmark_runs_and_reports_throughput() {
+        let result = run_benchmark(BenchmarkConfig {
+            ticks: 500,
+            ..BenchmarkConfig::default()
+        });
+        assert!(result.ticks_per_second > 0.0);
+        assert!(result.peak_cars > 0);
+        assert!(result.to_string().contains("Ticks/second"));
+
+        let nothing = run_benchmark(BenchmarkConfig {
+            ticks: 0,
+            ..BenchmarkConfig::default()
+        });
+        assert_eq!(nothing.ticks_per_second, 0.0);
+        assert!(!nothing.to_string().contains("NaN"));
+    }
+}
@@ -1,15 +1,35 @@
 use piston_window::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
 
 use crate::{
-    traffic_light_controller::{self, SimplifiedCar, TrafficLightController},
-    HEIGHT, WIDTH,
+    controller_strategy::ControllerStrategy,
+    following_model::{FollowingModel, FollowingModelKind},
+    geometry::{intersection_bounds, stop_line, WorldGeometry},
+    obstacle::Obstacle,
+    snapshot::CarSnapshot,
+    traffic_light_controller::{self, SimplifiedCar},
+    WIDTH,
 };
 
 pub const MAX_SPEED: f64 = 5.0;
 pub const ACCELERATION: f64 = 0.15;
-const DECELERATION: f64 = 0.3;
-
-const DISTANCE_THRESHOLD: f64 = 5.0;
+pub const DECELERATION: f64 = 0.3;
+/// Braking used when braking at `DECELERATION` would run into what's ahead
+pub const EMERGENCY_DECELERATION: f64 = DECELERATION * 2.0;
+/// Most the acceleration can change by per frame. High enough that cars can go from full braking to
+/// full acceleration in one frame by default.
+pub const MAX_JERK: f64 = 0.5;
+/// Frames a stopped car waits after it's allowed to go before it starts moving. Cars launch
+/// instantly by default.
+pub const REACTION_FRAMES: f64 = 0.0;
+
+/// How close a car has to get to a path point to count as there. This is as far as a car at
+/// `MAX_SPEED` goes in a frame, so it can't be relied on by itself to catch every point; see
+/// `reached_point`.
+pub const DISTANCE_THRESHOLD: f64 = 5.0;
+/// How close to the edge of the intersection a path point has to be to count as on it
+const INTERSECTION_EDGE_TOLERANCE: f64 = 1.0;
 
 pub const CAR_WIDTH: f64 = 50.0; // 75.0, 50
 const CAR_HEIGHT: f64 = 33.0; // 50.0, 33
@@ -17,9 +37,50 @@ const ARROW_STROKE_WEIGHT: f64 = 2.5; //  5.0, 2.5
 
 pub const LANE_WIDTH: f64 = CAR_HEIGHT * 2.0;
 
-pub const NUM_PATH_POINTS: usize = 25; // Higher = more accurate path but more expensive
+/// Cars stop when the gap between their front bumper and the rear bumper of the car ahead is
+/// smaller than this
+pub const FOLLOWING_GAP: f64 = CAR_WIDTH;
+
+/// Default number of points in a car's path. Higher = more accurate path but more expensive
+pub const NUM_PATH_POINTS: usize = 25;
+
+/// Number of past positions each car remembers
+pub const POSITION_HISTORY_LENGTH: usize = 600;
+
+/// Start and end of a piece of a car's trail and how opaque to draw it
+pub type TrailSegment = ((f64, f64), (f64, f64), f32);
+
+/// Frames a right-turning car has to wait at a red light before it can turn on red
+const RIGHT_ON_RED_DWELL: f64 = 60.0;
+/// How far away oncoming straight cars have to be for a permissive left turn to go
+const PERMISSIVE_LEFT_GAP: f64 = CAR_WIDTH * 4.0;
+/// How far away oncoming right-turning cars have to be for a permissive left turn to go. They
+/// only cross the left turn where both merge into the exit, so they're out of the way sooner.
+const PERMISSIVE_LEFT_RIGHT_TURN_GAP: f64 = CAR_WIDTH * 2.0;
+/// How far away cross traffic has to be for a channelized right turn to merge in front of it
+const SLIP_LANE_MERGE_GAP: f64 = CAR_WIDTH * 3.0;
+
+/// Largest gap allowed between two points of a path on the default canvas. Bigger gaps mean the
+/// path generation is broken.
+pub const MAX_PATH_GAP: f64 = CAR_WIDTH * 3.0;
+
+/// Largest sideways acceleration (pixels/frame^2) a car is allowed while turning
+const MAX_LATERAL_ACCELERATION: f64 = 0.08;
+
+/// How far along its path a car looks for obstacles
+const OBSTACLE_LOOKAHEAD: f64 = CAR_WIDTH * 6.0;
+/// Longest step taken along the path when looking for where the car would hit an obstacle
+const OBSTACLE_STEP: f64 = DISTANCE_THRESHOLD;
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+/// Frames after their light stops being green that drivers who don't comply will still go through
+const RED_RUNNER_WINDOW: f64 = 60.0;
+
+/// Frames a straight car has to be stuck behind another car before it changes lanes
+const LANE_CHANGE_WAIT: f64 = 90.0;
+/// Frames after a lane change before a car can change lanes again
+const LANE_CHANGE_COOLDOWN: f64 = 240.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub enum Origin {
     North,
     South,
@@ -27,7 +88,7 @@ pub enum Origin {
     West,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Serialize, Deserialize)]
 pub enum Direction {
     Left,
     Right,
@@ -45,207 +106,1173 @@ impl Direction {
     }
 }
 
-#[derive(Clone)]
+/// Rules of the road that the world decides for each car every update
+#[derive(Clone, Copy, Debug)]
+pub struct DrivingRules<'a> {
+    /// Caps the speed on the approach until the car has cleared the intersection
+    pub speed_limit: f64,
+    /// Lets right-turning cars go on red after stopping if nothing is coming
+    pub allow_right_on_red: bool,
+    /// Makes cars wait at the light, even on green, while cross traffic is in the intersection
+    pub wait_for_clear_intersection: bool,
+    /// Current frame of the world, used to stamp when cars reach the intersection
+    pub frame: usize,
+    /// Things blocking the road that cars have to stop behind
+    pub obstacles: &'a [Obstacle],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Serialize, Deserialize)]
+pub enum CarKind {
+    Regular,
+    Emergency,
+    /// Twice as long as a car, and stops at its bus stop if it has one
+    Bus,
+}
+
+impl CarKind {
+    /// Length of the vehicle from bumper to bumper
+    pub fn length(self) -> f64 {
+        match self {
+            CarKind::Regular | CarKind::Emergency => CAR_WIDTH,
+            CarKind::Bus => CAR_WIDTH * 2.0,
+        }
+    }
+}
+
+/// Where a bus stops to let passengers on and off before reaching the intersection
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BusStop {
+    /// How far before the stop line the front of the bus stops
+    pub distance: f64,
+    /// Frames the bus waits at the stop
+    pub dwell_frames: f64,
+}
+
+/// Where a car is and where it's headed on its path
+#[derive(Clone, Copy)]
+struct Pose {
+    position: (f64, f64),
+    rotation: f64,
+    target_rotation: f64,
+    path_index: usize,
+}
+
+/// How cars are colored when drawn
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DrawMode {
+    /// Each car's own color
+    Plain,
+    /// Orange when stopped to green at full speed
+    BySpeed,
+    /// White when the car hasn't waited to purple after `WAIT_COLOR_FRAMES` of waiting
+    ByWaitTime,
+}
+
+impl DrawMode {
+    pub fn next(self) -> DrawMode {
+        match self {
+            DrawMode::Plain => DrawMode::BySpeed,
+            DrawMode::BySpeed => DrawMode::ByWaitTime,
+            DrawMode::ByWaitTime => DrawMode::Plain,
+        }
+    }
+}
+
+/// Frames of waiting it takes for a car to be fully colored in `DrawMode::ByWaitTime`
+const WAIT_COLOR_FRAMES: f64 = 600.0;
+
+/// Cars are saved without their path and position history to keep saves small. Call
+/// `restore_path` after loading one.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Car {
     pub id: usize,
     pub origin: Origin,
     direction: Direction,
+    lane: usize,
+    /// Lane a turning car turns into, see `Car::calculate_path_to_lane`
+    receiving_lane: Option<usize>,
+    pub kind: CarKind,
+    /// Fill color used by `DrawMode::Plain`
+    pub color: [f32; 4],
+    max_speed: f64,
+    /// Speed gained per frame
+    acceleration: f64,
+    /// Speed lost per frame when braking normally, like for a red light
+    deceleration: f64,
+    /// Speed lost per frame when braking normally wouldn't stop the car in time
+    emergency_deceleration: f64,
+    /// Most `current_acceleration` can change by per frame
+    max_jerk: f64,
+    /// Frames the car waits before pulling away once it's allowed to go
+    reaction_frames: f64,
+    /// Frames left before the car reacts to being allowed to go
+    reaction_left: f64,
+    /// How the car keeps its distance to the car ahead
+    following_model: FollowingModelKind,
+    /// Speed gained (or lost when negative) this frame
+    current_acceleration: f64,
     position: (f64, f64),
     rotation: f64,
     target_rotation: f64,
     speed: f64,
     stopped: bool,
     automatically_stopped: bool,
+    #[serde(skip)]
     path: Vec<(f64, f64)>,
     path_index: usize,
+    /// Index of the point the car finishes at. The last point of the path unless the car leaves
+    /// partway along it.
+    path_end: usize,
     path_index_on_red_change: Option<usize>,
     path_index_at_intersection: usize,
+    /// Index of the first point past the intersection
+    path_index_at_exit: usize,
+    /// World frame the car reached the stop point on. Decides who goes first when cars on
+    /// crossing paths get there together.
+    intersection_arrival: Option<usize>,
+    num_path_points: usize,
+    /// Frames spent stopped at a red light, used for turning right on red
+    red_dwell: f64,
+    /// Drivers who don't comply go through yellows and reds that only just came on
+    compliant: bool,
+    /// Frames since the car's light was last green or yellow
+    frames_since_go: f64,
+    /// Frames spent stuck behind another car, used for changing lanes
+    blocked_frames: f64,
+    lane_change_cooldown: f64,
     pub finished: bool,
     through_intersection: bool,
+    /// Positions at the end of the most recent updates, oldest first
+    #[serde(skip)]
+    position_history: VecDeque<(f64, f64)>,
+    /// Number of updates since the car spawned
+    frames: usize,
+    /// Frames spent standing still
+    wait_frames: f64,
+    /// Distance actually driven since spawning
+    distance_traveled: f64,
+    /// Number of times the car came to a standstill after moving
+    stop_count: usize,
+    bus_stop: Option<BusStop>,
+    /// Frames spent waiting at the bus stop so far
+    dwell_frames: f64,
+    geometry: WorldGeometry,
 }
 
 impl Car {
-    pub fn new(id: usize, origin: Origin, direction: Direction) -> Car {
-        let rotation: f64 = match origin {
-            Origin::North => 90.0,
-            Origin::South => 270.0,
-            Origin::East => 180.0,
-            Origin::West => 0.0,
-        };
-        let path: Vec<(f64, f64)> = match direction {
-            Direction::Left => generate_left_turn_path(origin),
-            Direction::Right => generate_right_turn_path(origin),
-            Direction::Straight => generate_straight_path(origin),
-        };
-        Car {
-            id,
-            origin,
-            direction,
-            position: get_position(origin, direction),
-            rotation,
-            target_rotation: rotation,
-            speed: 0.0,
-            stopped: false,
-            automatically_stopped: false,
-            path,
-            path_index: 1,
-            path_index_on_red_change: None,
-            path_index_at_intersection: NUM_PATH_POINTS / 3
-                + if direction == Direction::Straight {
-                    1
-                } else {
-                    0
-                },
-            finished: false,
-            through_intersection: false,
-        }
+    /// Creates a new car. Lanes are counted outwards from the lane the direction normally uses,
+    /// and only straight cars can use a lane other than 0. Panics if the lane can't be used, see
+    /// `try_new`.
+    pub fn new(id: usize, origin: Origin, direction: Direction, lane: usize) -> Car {
+        CarBuilder::new(id, origin, direction).lane(lane).build()
+    }
+
+    /// Like `new`, but returns an error instead of panicking if the movement can't be made from
+    /// that lane or the car's path is too short
+    pub fn try_new(
+        id: usize,
+        origin: Origin,
+        direction: Direction,
+        lane: usize,
+    ) -> Result<Car, PathError> {
+        CarBuilder::new(id, origin, direction)
+            .lane(lane)
+            .try_build()
     }
 
-    fn get_distance_to_closest_car(&mut self, cars: &Vec<Car>) -> f64 {
-        let mut closest_distance = f64::MAX;
+    /// Points the car drives through, in world (screen) coordinates
+    pub fn path(&self) -> &[(f64, f64)] {
+        &self.path
+    }
 
-        cars.clone()
-            .iter()
-            .filter(|c| c.origin == self.origin && c.direction == self.direction && c.id != self.id)
-            .for_each(|c| {
-                let (x, y) = self.position;
+    /// Regenerates the path of a car that was loaded from a save
+    pub fn restore_path(&mut self) {
+        self.path = Car::calculate_path_to_lane(
+            &SimplifiedCar::new(self.origin, self.direction),
+            self.lane,
+            self.receiving_lane,
+            self.num_path_points,
+            &self.geometry,
+        );
+    }
+
+    /// Index of the point in `path` the car is currently driving towards
+    pub fn path_index(&self) -> usize {
+        self.path_index
+    }
+
+    pub fn lane(&self) -> usize {
+        self.lane
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Leg of the intersection the car leaves through
+    pub fn destination(&self) -> Origin {
+        exit_leg(self.origin, self.direction)
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn position(&self) -> (f64, f64) {
+        self.position
+    }
+
+    /// Rotation in degrees, with 0 pointing along the positive x axis
+    pub fn rotation(&self) -> f64 {
+        self.rotation
+    }
+
+    /// Number of updates since the car spawned
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Frames the car has spent standing still
+    pub fn wait_frames(&self) -> f64 {
+        self.wait_frames
+    }
+
+    /// Number of times the car has had to stop. Stopping again after starting back up counts as
+    /// another stop, so a car that creeps forward in a queue racks up several.
+    pub fn stop_count(&self) -> usize {
+        self.stop_count
+    }
+
+    /// Distance the car has actually driven since it spawned. Matches the length of the part of
+    /// the path it has covered, since cars never reverse.
+    pub fn distance_traveled(&self) -> f64 {
+        self.distance_traveled
+    }
+
+    /// Frame the car got to the intersection on, if it has
+    pub fn intersection_arrival(&self) -> Option<usize> {
+        self.intersection_arrival
+    }
+
+    /// Returns if the car has gone into the intersection (or committed to going on yellow)
+    pub fn through_intersection(&self) -> bool {
+        self.through_intersection
+    }
+
+    /// Returns the id of, the distance to and the speed of the closest car ahead of this one in the
+    /// same lane. Cars right on top of this one count as ahead.
+    pub fn car_ahead(&self, cars: &[Car]) -> Option<(usize, f64, f64)> {
+        let (x, y) = self.position;
+        cars.iter()
+            .filter(|c| {
+                c.origin == self.origin
+                    && self.geometry.layout.lane_offset(c.direction, c.lane)
+                        == self.geometry.layout.lane_offset(self.direction, self.lane)
+                    && c.id != self.id
+            })
+            .filter(|c| {
                 let (cx, cy) = c.position;
                 match self.origin {
-                    Origin::North => {
-                        if cy < y {
-                            return;
-                        }
-                    }
-                    Origin::South => {
-                        if cy > y {
-                            return;
-                        }
-                    }
-                    Origin::East => {
-                        if cx > x {
-                            return;
-                        }
-                    }
-                    Origin::West => {
-                        if cx < x {
-                            return;
-                        }
-                    }
+                    Origin::North => cy >= y,
+                    Origin::South => cy <= y,
+                    Origin::East => cx <= x,
+                    Origin::West => cx >= x,
                 }
-                let distance = ((x - cx).powi(2) + (y - cy).powi(2)).sqrt();
-                closest_distance = closest_distance.min(distance);
-            });
+            })
+            .map(|c| {
+                let (cx, cy) = c.position;
+                (c.id, ((x - cx).powi(2) + (y - cy).powi(2)).sqrt(), c.speed)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    /// Returns if the car has enough room behind the car ahead of it to keep going
+    fn following_allows(&self, cars: &[Car]) -> bool {
+        self.following_speed(cars, self.max_speed) > 0.0
+    }
 
-        closest_distance
+    /// Distance the car can drive along its path before it runs into the obstacle. Returns `None`
+    /// if it doesn't run into it within `OBSTACLE_LOOKAHEAD`.
+    pub fn distance_to_obstacle(&self, obstacle: &Obstacle) -> Option<f64> {
+        let (x, y) = self.position;
+        let (ox, oy) = obstacle.center();
+        if (ox - x).hypot(oy - y) > OBSTACLE_LOOKAHEAD + self.length() + obstacle.radius() {
+            return None;
+        }
+        if self.intersects_rect(obstacle.vertices) {
+            return Some(0.0);
+        }
+
+        let mut distance = 0.0;
+        let mut previous = self.position;
+        for &point in self
+            .path
+            .iter()
+            .take(self.path_end + 1)
+            .skip(self.path_index)
+        {
+            let segment = (point.0 - previous.0).hypot(point.1 - previous.1);
+            if segment == 0.0 {
+                continue;
+            }
+            let rotation = (point.1 - previous.1)
+                .atan2(point.0 - previous.0)
+                .to_degrees();
+            // Path points are far apart, so check along the segment in small steps
+            let steps = (segment / OBSTACLE_STEP).ceil();
+            for step in 1..=steps as usize {
+                let t = step as f64 / steps;
+                let position = (
+                    previous.0 + (point.0 - previous.0) * t,
+                    previous.1 + (point.1 - previous.1) * t,
+                );
+                let vertices = Car::vertices_with_pos_and_rot(position, rotation, self.length());
+                if Car::intersects_rect_with_two_cars(vertices, obstacle.vertices) {
+                    return Some(distance + segment * t);
+                }
+            }
+            distance += segment;
+            if distance > OBSTACLE_LOOKAHEAD {
+                return None;
+            }
+            previous = point;
+        }
+        None
+    }
+
+    /// Fastest the car's following model lets it go behind the closest obstacle in its way, as if
+    /// the obstacle was a stopped car, or `max_speed` if nothing is in the way
+    fn obstacle_speed(&self, obstacles: &[Obstacle], max_speed: f64) -> f64 {
+        self.obstacle_gap(obstacles).map_or(max_speed, |gap| {
+            self.following_model
+                .desired_speed(gap, self.speed, 0.0, max_speed)
+        })
+    }
+
+    /// Distance to the closest obstacle in the car's way, if there is one
+    fn obstacle_gap(&self, obstacles: &[Obstacle]) -> Option<f64> {
+        obstacles
+            .iter()
+            .filter_map(|obstacle| self.distance_to_obstacle(obstacle))
+            .min_by(f64::total_cmp)
     }
 
-    fn automatically_stop(&mut self, cars: &Vec<Car>) {
+    /// Returns if braking at `deceleration` wouldn't slow the car down to the speed of the car or
+    /// obstacle ahead before running into it, so it has to brake harder
+    fn needs_emergency_braking(&self, cars: &[Car], obstacles: &[Obstacle]) -> bool {
+        // Like `following_speed`, cars in the intersection don't look at the car ahead
+        let leader = if self.through_intersection {
+            None
+        } else {
+            self.leader_gap(cars)
+        };
+        let obstacle = self.obstacle_gap(obstacles).map(|gap| (gap, 0.0));
+        leader
+            .into_iter()
+            .chain(obstacle)
+            .any(|(gap, leader_speed)| {
+                leader_speed < self.speed
+                    && (self.speed.powi(2) - leader_speed.powi(2)) / (2.0 * self.deceleration)
+                        >= gap
+            })
+    }
+
+    /// Bumper to bumper gap to the car ahead along the way the car is facing, negative once they
+    /// overlap, and the speed of that car
+    fn leader_gap(&self, cars: &[Car]) -> Option<(f64, f64)> {
+        let (id, _, leader_speed) = self.car_ahead(cars)?;
+        let leader = cars.iter().find(|c| c.id == id).unwrap();
+        let front = self.front();
+        let rear = leader.rear();
+        let angle = self.rotation.to_radians();
+        let gap = (rear.0 - front.0) * angle.cos() + (rear.1 - front.1) * angle.sin();
+        Some((gap, leader_speed))
+    }
+
+    /// Fastest the car's following model lets it go behind the car ahead of it, or `max_speed`
+    /// if there's no one ahead
+    fn following_speed(&self, cars: &[Car], max_speed: f64) -> f64 {
         if self.through_intersection {
-            return;
+            return max_speed;
         }
 
-        let closest_distance = self.get_distance_to_closest_car(cars);
-        // Make sure cars that are on top of each other don't stop
-        if !self.stopped && closest_distance < CAR_WIDTH * 2.0 && closest_distance > 3.0 {
-            self.stopped = true;
-            self.automatically_stopped = true;
-        } else if self.stopped && self.automatically_stopped && closest_distance > CAR_WIDTH * 2.0 {
+        // Cars spawn and change lanes at least a car length away from everyone, so the car ahead
+        // is never right on top of this one
+        let Some((gap, leader_speed)) = self.leader_gap(cars) else {
+            return max_speed;
+        };
+        self.following_model
+            .desired_speed(gap, self.speed, leader_speed, max_speed)
+    }
+
+    /// Moves a straight car that is stuck behind another car into a neighbouring lane if there's
+    /// room for it there. Returns if the car changed lanes.
+    pub fn try_lane_change(&mut self, cars: &[Car]) -> bool {
+        if self.direction != Direction::Straight
+            || self.lane_change_cooldown > 0.0
+            || self.path_index >= self.path_index_at_intersection
+        {
+            return false;
+        }
+
+        let neighbouring_lanes = [self.lane.checked_sub(1), Some(self.lane + 1)];
+        for lane in neighbouring_lanes.into_iter().flatten() {
+            if lane >= self.geometry.layout.straight_lane_count() {
+                continue;
+            }
+
+            let path = Car::calculate_path(
+                &SimplifiedCar::new(self.origin, self.direction),
+                lane,
+                self.num_path_points,
+                &self.geometry,
+            );
+            // Where the car would be if it was in the other lane
+            let position = (
+                self.position.0 + path[self.path_index].0 - self.path[self.path_index].0,
+                self.position.1 + path[self.path_index].1 - self.path[self.path_index].1,
+            );
+            let vertices = Car::vertices_with_pos_and_rot(position, self.rotation, self.length());
+            let clear = cars.iter().filter(|c| c.id != self.id).all(|c| {
+                !Car::intersects_rect_with_two_cars(vertices, c.vertices())
+                    && (c.position.0 - position.0).hypot(c.position.1 - position.1)
+                        > self.length() + c.length()
+            });
+            if !clear {
+                continue;
+            }
+
+            self.lane = lane;
+            self.path = path;
+            let dx = self.path[self.path_index].0 - self.position.0;
+            let dy = self.path[self.path_index].1 - self.position.1;
+            self.target_rotation = dy.atan2(dx).to_degrees();
             self.stopped = false;
             self.automatically_stopped = false;
+            self.blocked_frames = 0.0;
+            self.lane_change_cooldown = LANE_CHANGE_COOLDOWN;
+            return true;
+        }
+
+        false
+    }
+
+    /// Returns if a car that would cross the path of this car's right turn is in the
+    /// intersection, or is about to go into it on a green light
+    fn right_turn_conflict(&self, cars: &[Car], traffic_light: &dyn ControllerStrategy) -> bool {
+        let exit = exit_leg(self.origin, self.direction);
+        cars.iter()
+            .filter(|c| {
+                c.id != self.id
+                    && c.origin != self.origin
+                    && exit_leg(c.origin, c.direction) == exit
+                    && !c.cleared_intersection()
+            })
+            .any(|c| c.through_intersection || traffic_light.is_green(c.origin, c.direction))
+    }
+
+    /// Returns if a moving car that exits the same way as this car's slip lane is in the
+    /// intersection or too close to it to merge in front of. Cars waiting at a red are ignored.
+    fn merge_conflict(&self, cars: &[Car]) -> bool {
+        let exit = exit_leg(self.origin, self.direction);
+        cars.iter()
+            .filter(|c| {
+                c.id != self.id
+                    && c.origin != self.origin
+                    && exit_leg(c.origin, c.direction) == exit
+                    && !c.cleared_intersection()
+            })
+            .any(|c| {
+                c.through_intersection
+                    || (!c.stopped && c.distance_to_intersection() < SLIP_LANE_MERGE_GAP)
+            })
+    }
+
+    /// Returns if an oncoming car is in the intersection or too close to it for a permissive left
+    /// turn to go. Oncoming left turns don't cross this one, and right turns need a smaller gap
+    /// than straight cars.
+    fn oncoming_traffic(&self, cars: &[Car]) -> bool {
+        let oncoming_origin = exit_leg(self.origin, Direction::Straight);
+        cars.iter()
+            .filter(|c| c.origin == oncoming_origin && !c.cleared_intersection())
+            .any(|c| {
+                let gap = match c.direction {
+                    Direction::Left => return false,
+                    Direction::Straight => PERMISSIVE_LEFT_GAP,
+                    Direction::Right => PERMISSIVE_LEFT_RIGHT_TURN_GAP,
+                };
+                c.through_intersection || c.distance_to_intersection() < gap
+            })
+    }
+
+    /// Returns if the light is green (and not yellow) for this car
+    fn light_is_go(&self, traffic_light: &dyn ControllerStrategy) -> bool {
+        // Cars that couldn't stop on yellow have already committed, so everyone else has to stop
+        traffic_light.is_green(self.origin, self.direction)
+            && !traffic_light.is_yellow(self.origin, self.direction)
+    }
+
+    /// Counts how long a right turn has been stopped at a red light
+    fn update_red_dwell(
+        &mut self,
+        traffic_light: &dyn ControllerStrategy,
+        rules: &DrivingRules,
+        dt: f64,
+    ) {
+        if !self.light_is_go(traffic_light)
+            && rules.allow_right_on_red
+            && self.direction == Direction::Right
+            && self.at_stop_line()
+            // Once it has waited long enough it keeps going instead of starting the wait over
+            && (self.speed <= 0.0 || self.red_dwell >= RIGHT_ON_RED_DWELL)
+        {
+            self.red_dwell += dt;
+        } else {
+            self.red_dwell = 0.0;
         }
     }
 
-    fn stop_for_traffic_light(&mut self, traffic_light: &mut TrafficLightController) {
+    /// Returns if the car is waiting right at its stop line. The last path point before the
+    /// intersection can be just short of the line, so this goes by the line rather than the path.
+    fn at_stop_line(&self) -> bool {
+        self.reaches_intersection() && self.distance_to_stop_line() <= DISTANCE_THRESHOLD
+    }
+
+    /// Returns if the traffic light, pedestrians and cross traffic let the car keep going
+    fn light_allows(
+        &self,
+        cars: &[Car],
+        traffic_light: &dyn ControllerStrategy,
+        rules: &DrivingRules,
+    ) -> bool {
         if self.through_intersection {
-            self.stopped = false;
-            return;
+            return true;
+        }
+        // Channelized right turns don't have a light, they just yield where they merge
+        if self.is_channelized() {
+            let can_go = !self.merge_conflict(cars)
+                && !traffic_light.is_walk(exit_leg(self.origin, self.direction));
+            return can_go
+                || self.stopping_distance() + DISTANCE_THRESHOLD < self.distance_to_stop_line();
+        }
+        if !self.reaches_intersection() {
+            return true;
         }
 
-        let mut can_go = traffic_light.is_green(self.origin, self.direction);
-        // If it's red but I'm not at the intersection, I can keep going
-        if !can_go && self.path_index != self.path_index_at_intersection {
+        let mut can_go = self.light_is_go(traffic_light);
+        // Drivers who don't comply keep going through a yellow or a red that only just came on
+        if !can_go && !self.compliant && self.frames_since_go < RED_RUNNER_WINDOW {
+            can_go = true;
+        }
+        // Right turns can go on red once they've stopped at the light for a bit and nothing's
+        // coming
+        if !can_go
+            && rules.allow_right_on_red
+            && self.direction == Direction::Right
+            && self.red_dwell >= RIGHT_ON_RED_DWELL
+            && !self.right_turn_conflict(cars, traffic_light)
+        {
+            can_go = true;
+        }
+        // Left turns can go on the straight green if there's a gap in oncoming traffic
+        if !can_go
+            && self.direction == Direction::Left
+            && traffic_light.is_permissive_left(self.origin)
+            && !self.oncoming_traffic(cars)
+        {
+            can_go = true;
+        }
+        // Don't block the box: wait for cross traffic to get out of the intersection first
+        if can_go
+            && rules.wait_for_clear_intersection
+            && self.path_index == self.path_index_at_intersection
+            && cars.iter().any(|c| {
+                c.origin != self.origin
+                    && c.origin != exit_leg(self.origin, Direction::Straight)
+                    && c.through_intersection
+                    && c.in_intersection()
+            })
+        {
+            can_go = false;
+        }
+        // Right turns cross the crosswalk of the leg they exit through, so wait for pedestrians
+        if self.direction == Direction::Right
+            && traffic_light.is_walk(exit_leg(self.origin, self.direction))
+        {
+            can_go = false;
+        }
+        // If it's red but I can still stop before the stop line, I can keep going. The margin
+        // keeps a stopped car from creeping up to the line.
+        if !can_go && self.stopping_distance() + DISTANCE_THRESHOLD < self.distance_to_stop_line() {
             can_go = true;
         }
+        can_go
+    }
 
-        // If it's green, reset path index on red change
-        if can_go {
-            self.path_index_on_red_change = None;
+    /// Returns if no car on a crossing path got to the intersection first and is still going
+    /// through it. Ties on the same frame go to the lower id so exactly one of the cars waits.
+    fn arrival_allows(&self, cars: &[Car]) -> bool {
+        if self.through_intersection {
+            return true;
+        }
+        let Some(arrival) = self.intersection_arrival else {
+            return true;
+        };
+        !cars.iter().any(|c| {
+            c.id != self.id
+                && !c.cleared_intersection()
+                && (c.through_intersection || !c.stopped)
+                && c.intersection_arrival
+                    .is_some_and(|other| (other, c.id) < (arrival, self.id))
+                && traffic_light_controller::movements_conflict(
+                    SimplifiedCar::new(self.origin, self.direction),
+                    SimplifiedCar::new(c.origin, c.direction),
+                )
+        })
+    }
+
+    /// Returns if no car that got to the intersection first is still going to cross this car's
+    /// path inside it. The light lets some turns whose paths cross go together, like opposing
+    /// lefts, so cars in the intersection take turns where their paths cross.
+    fn crossing_allows(&self, cars: &[Car]) -> bool {
+        if self.cleared_intersection() || !self.in_intersection() {
+            return true;
+        }
+        let Some(arrival) = self.intersection_arrival else {
+            return true;
+        };
+        !cars.iter().any(|c| {
+            c.id != self.id
+                && !c.cleared_intersection()
+                && c.in_intersection()
+                && c.intersection_arrival
+                    .is_some_and(|other| (other, c.id) < (arrival, self.id))
+                && self.paths_cross(c)
+        })
+    }
+
+    /// Returns if what's left of the two cars' paths through the intersection cross each other
+    fn paths_cross(&self, other: &Car) -> bool {
+        let remaining = |car: &Car| {
+            let end = car.path_index_at_exit.min(car.path_end);
+            std::iter::once(car.position)
+                .chain(car.path.iter().take(end + 1).skip(car.path_index).copied())
+                .collect::<Vec<_>>()
+        };
+        let my_path = remaining(self);
+        let other_path = remaining(other);
+        my_path.windows(2).any(|line| {
+            other_path.windows(2).any(|other_line| {
+                line_intersect((line[0], line[1]), &(other_line[0], other_line[1]))
+            })
+        })
+    }
+
+    /// Returns if the car can keep going. Every reason a car has to stop (the light, pedestrians,
+    /// cross traffic, its bus stop, whoever got to the intersection first, cars crossing its path
+    /// in the intersection and the car ahead) is checked here.
+    pub fn should_proceed(
+        &self,
+        cars: &[Car],
+        traffic_light: &dyn ControllerStrategy,
+        rules: &DrivingRules,
+    ) -> bool {
+        self.light_allows(cars, traffic_light, rules)
+            && self.bus_stop_allows()
+            && self.arrival_allows(cars)
+            && self.crossing_allows(cars)
+            && self.following_allows(cars)
+            && self.obstacle_speed(rules.obstacles, self.max_speed) > 0.0
+    }
+
+    /// Returns false from when the car needs to start braking for its bus stop until it has
+    /// waited there for the stop's dwell time. Doesn't care about the light.
+    fn bus_stop_allows(&self) -> bool {
+        let Some(stop) = self.bus_stop else {
+            return true;
+        };
+        if self.dwell_frames >= stop.dwell_frames || self.through_intersection {
+            return true;
         }
 
-        self.stopped = !can_go;
+        self.distance_to_stop_line() - stop.distance > self.stopping_distance() + DISTANCE_THRESHOLD
+    }
+
+    /// Stops the car dead where it is, without going through the normal braking. Used to pin a
+    /// car in place while debugging.
+    pub fn freeze(&mut self) {
+        self.speed = 0.0;
+        self.current_acceleration = 0.0;
+        self.stopped = true;
     }
 
-    pub fn update(&mut self, cars: &Vec<Car>, traffic_light: &mut TrafficLightController) {
-        // If we have entered the intersection, remove ourselves from the traffic light
-        if !self.through_intersection && self.past_intersection() {
+    /// Advances the car by `dt` frames. Speeds and accelerations are all per frame, so `dt = 1.0`
+    /// is a normal frame.
+    pub fn update(
+        &mut self,
+        cars: &[Car],
+        traffic_light: &mut dyn ControllerStrategy,
+        rules: &DrivingRules,
+        dt: f64,
+    ) {
+        // Finished cars are about to be removed, so they don't move anymore
+        if self.finished {
+            return;
+        }
+        // There's nowhere to drive to
+        if self.path_index > self.path_end {
+            self.finished = true;
+            return;
+        }
+        self.frames += 1;
+        if self.speed == 0.0 {
+            self.wait_frames += dt;
+        }
+
+        // If we have entered the intersection, remove ourselves from the traffic light. Once any
+        // part of the car is in the box it has to clear it, even if its path index hasn't got to
+        // the intersection yet. Only cars the world added to the light take themselves off it.
+        if !self.through_intersection && (self.past_intersection() || self.in_intersection()) {
+            if self.reaches_intersection() {
+                traffic_light.remove_car(SimplifiedCar::new(self.origin, self.direction));
+            }
             self.through_intersection = true;
-            traffic_light.remove_car(SimplifiedCar::new(self.origin, self.direction));
         }
-        // If it's yellow and I'm right at the intersection, remove myself from the traffic light
-        // (to update clearance times)
+        // If it's yellow and I can't stop before the intersection anymore, commit to going
+        // through and remove myself from the traffic light (to update clearance times)
         if traffic_light.is_yellow(self.origin, self.direction)
-            && self.path_index == self.path_index_at_intersection
             && !self.through_intersection
+            && self.reaches_intersection()
+            && self.stopping_distance() >= self.distance_to_stop_line()
         {
             traffic_light.remove_car(SimplifiedCar::new(self.origin, self.direction));
             self.through_intersection = true;
         }
 
-        self.stop_for_traffic_light(traffic_light);
-        self.automatically_stop(cars);
+        if self.intersection_arrival.is_none() && self.path_index >= self.path_index_at_intersection
+        {
+            self.intersection_arrival = Some(rules.frame);
+        }
 
-        if !self.stopped {
-            self.speed += ACCELERATION;
-            if self.speed > MAX_SPEED {
-                self.speed = MAX_SPEED;
-            }
+        self.update_red_dwell(traffic_light, rules, dt);
+        if traffic_light.is_green(self.origin, self.direction) {
+            self.frames_since_go = 0.0;
+        } else {
+            self.frames_since_go += dt;
+        }
+        // If it's green, reset path index on red change
+        if self.light_allows(cars, traffic_light, rules) {
+            self.path_index_on_red_change = None;
+        }
+        self.automatically_stopped = !self.following_allows(cars)
+            || self.obstacle_speed(rules.obstacles, self.max_speed) <= 0.0;
+        self.stopped = !self.should_proceed(cars, traffic_light, rules);
+        // A standing car takes a moment to notice it can go
+        let reacting = !self.stopped && self.speed == 0.0 && self.reaction_left > 0.0;
+        if self.stopped {
+            self.reaction_left = self.reaction_frames;
+        } else if reacting {
+            self.reaction_left -= dt;
+        }
+        if self.speed == 0.0 && !self.bus_stop_allows() {
+            self.dwell_frames += dt;
+        }
+
+        if self.stopped && self.automatically_stopped {
+            self.blocked_frames += dt;
+        } else {
+            self.blocked_frames = 0.0;
+        }
+        self.lane_change_cooldown = (self.lane_change_cooldown - dt).max(0.0);
+        if self.blocked_frames >= LANE_CHANGE_WAIT {
+            self.try_lane_change(cars);
+        }
+
+        let max_speed = if self.cleared_intersection() {
+            self.max_speed
         } else {
-            if self.speed > 0.0 {
-                self.speed -= DECELERATION;
+            self.max_speed.min(rules.speed_limit)
+        }
+        .min(self.curve_speed_limit());
+        let max_speed = self
+            .following_speed(cars, max_speed)
+            .min(self.obstacle_speed(rules.obstacles, max_speed));
+
+        // Slow down to the limit instead of snapping to it
+        let target_acceleration = if self.stopped || reacting || self.speed > max_speed {
+            if self.needs_emergency_braking(cars, rules.obstacles) {
+                -self.emergency_deceleration
             } else {
-                self.speed = 0.0;
+                -self.deceleration
             }
+        } else if self.speed < max_speed {
+            self.acceleration
+        } else {
+            0.0
+        };
+        // Ease the acceleration towards the target so it doesn't jump
+        let max_change = self.max_jerk * dt;
+        self.current_acceleration +=
+            (target_acceleration - self.current_acceleration).clamp(-max_change, max_change);
+
+        let previous_speed = self.speed;
+        self.speed += self.current_acceleration * dt;
+        // Speeding up or slowing down to the limit stops at it instead of going past
+        let passed_limit = (previous_speed <= max_speed && self.speed > max_speed)
+            || (previous_speed > max_speed && self.speed < max_speed);
+        if !self.stopped && passed_limit {
+            self.speed = max_speed;
+        }
+        if self.speed <= 0.0 {
+            self.speed = 0.0;
+            self.current_acceleration = self.current_acceleration.max(0.0);
+        }
+        // Only count coming to a standstill. While braking `stopped` can go back and forth as the
+        // car gets close enough to where it has to stop.
+        if previous_speed > 0.0 && self.speed == 0.0 {
+            self.stop_count += 1;
         }
 
-        // Move towards next point in path
-        let dx = self.rotation.to_radians().cos() * self.speed;
-        let dy = self.rotation.to_radians().sin() * self.speed;
-        self.position.0 += dx;
-        self.position.1 += dy;
+        let mut pose = self.pose();
+        let reached_end = !self.advance_pose(&mut pose, self.speed, dt);
+        self.distance_traveled +=
+            (pose.position.0 - self.position.0).hypot(pose.position.1 - self.position.1);
+        self.position = pose.position;
+        self.rotation = pose.rotation;
+        self.target_rotation = pose.target_rotation;
+        self.path_index = pose.path_index;
+        if reached_end {
+            self.finished = true;
+            return;
+        }
 
-        if self.intersects_point(self.path[self.path_index]) {
-            self.path_index += 1;
-            if self.path_index >= self.path.len() {
-                self.path_index = 0;
-                self.finished = true;
-            }
+        if self.position_history.len() == POSITION_HISTORY_LENGTH {
+            self.position_history.pop_front();
+        }
+        self.position_history.push_back(self.position);
+
+        // self.draw(cars, context, graphics);
+    }
+
+    fn pose(&self) -> Pose {
+        Pose {
+            position: self.position,
+            rotation: self.rotation,
+            target_rotation: self.target_rotation,
+            path_index: self.path_index,
+        }
+    }
+
+    /// Points the pose at the next point in the path. Returns false if there isn't one.
+    fn next_point(&self, pose: &mut Pose) -> bool {
+        pose.path_index += 1;
+        if pose.path_index > self.path_end {
+            // Keep pointing at the last point
+            pose.path_index = self.path_end;
+            return false;
+        }
+
+        let dx = self.path[pose.path_index].0 - pose.position.0;
+        let dy = self.path[pose.path_index].1 - pose.position.1;
+        pose.target_rotation = dy.atan2(dx).to_degrees();
+        true
+    }
 
-            if self.path_index >= 1 {
-                let dx = self.path[self.path_index].0 - self.position.0;
-                let dy = self.path[self.path_index].1 - self.position.1;
-                self.target_rotation = dy.atan2(dx).to_degrees();
+    /// Moves `pose` along the car's path at `speed` for `dt` frames. Returns false if it reached
+    /// the end of the path, in which case the rotation is left as it is.
+    fn advance_pose(&self, pose: &mut Pose, speed: f64, dt: f64) -> bool {
+        // When the move is long enough to reach the next point (high speeds or big dt), snap to
+        // it and keep going with what's left, so points can't be skipped over
+        let mut remaining = speed * dt;
+        loop {
+            let point = self.path[pose.path_index];
+            let distance = (point.0 - pose.position.0).hypot(point.1 - pose.position.1);
+            if remaining < distance {
+                break;
             }
+            pose.position = point;
+            remaining -= distance;
+            if !self.next_point(pose) {
+                return false;
+            }
+        }
+
+        // Move towards next point in path
+        let dx = pose.rotation.to_radians().cos() * remaining;
+        let dy = pose.rotation.to_radians().sin() * remaining;
+        pose.position.0 += dx;
+        pose.position.1 += dy;
+
+        if self.reached_point(pose) && !self.next_point(pose) {
+            return false;
         }
 
         // Rotate towards target rotation
-        let mut diff = self.target_rotation - self.rotation;
+        let mut diff = pose.target_rotation - pose.rotation;
         if diff > 180.0 {
             diff -= 360.0;
         } else if diff < -180.0 {
             diff += 360.0;
         }
-        self.rotation += diff * 0.5;
+        // Closes half of the gap every frame
+        pose.rotation += diff * (1.0 - 0.5_f64.powf(dt));
+        true
+    }
 
-        // self.draw(cars, context, graphics);
+    /// Predicts where the car will be (and its rotation) in `frames` frames if it keeps its
+    /// current speed, ignoring other cars and the lights
+    pub fn predict_pose(&self, frames: usize) -> ((f64, f64), f64) {
+        let mut pose = self.pose();
+        for _ in 0..frames {
+            if !self.advance_pose(&mut pose, self.speed, 1.0) {
+                break;
+            }
+        }
+        (pose.position, pose.rotation)
     }
 
-    fn past_intersection(&self) -> bool {
-        self.path_index > self.path_index_at_intersection
+    /// Text naming the car's id and speed, and where to write it so it sits just above the car
+    pub fn label(&self) -> (String, (f64, f64)) {
+        // Cars can face any way, so go by the corners rather than the length
+        let vertices = self.vertices();
+        let left = vertices.iter().map(|v| v.0).fold(f64::INFINITY, f64::min);
+        let top = vertices.iter().map(|v| v.1).fold(f64::INFINITY, f64::min);
+        (format!("{} {:.1}", self.id, self.speed), (left, top - 4.0))
     }
 
-    fn intersects_point(&self, point: (f64, f64)) -> bool {
-        let dx = self.position.0 - point.0;
-        let dy = self.position.1 - point.1;
+    /// Draws a faint copy of the car where it will be in `frames` frames
+    pub fn draw_ghost(&self, frames: usize, context: &Context, graphics: &mut G2d) {
+        let (position, rotation) = self.predict_pose(frames);
+        let [r, g, b, _] = self.color;
+        Car::draw_with_pos_and_rot(
+            position,
+            rotation,
+            self.length(),
+            self.direction,
+            [r, g, b, 0.25],
+            context,
+            graphics,
+        );
+    }
+
+    /// Fastest the car can go and still slow down in time for every point of the turn ahead of
+    /// it, braking at its normal deceleration. Cars brake before the turn instead of going into it
+    /// at full speed.
+    fn curve_speed_limit(&self) -> f64 {
+        let mut limit = f64::MAX;
+        let mut distance = 0.0;
+        let mut previous = self.position;
+        for i in self.path_index..self.path_index_at_exit.min(self.path_end + 1) {
+            let speed = self.point_curve_speed(i);
+            if speed < f64::MAX {
+                limit = limit.min((speed.powi(2) + 2.0 * self.deceleration * distance).sqrt());
+            }
+            let point = self.path[i];
+            distance += (point.0 - previous.0).hypot(point.1 - previous.1);
+            previous = point;
+        }
+        limit
+    }
+
+    /// Fastest the car can go towards the point at `i` without going over
+    /// `MAX_LATERAL_ACCELERATION` as it turns there. Only the turning third of the path is a curve.
+    fn point_curve_speed(&self, i: usize) -> f64 {
+        if i < self.path_index_at_intersection
+            || i >= self.path_index_at_exit
+            || i == 0
+            || i + 1 >= self.path.len()
+        {
+            return f64::MAX;
+        }
+
+        let (previous, point, next) = (self.path[i - 1], self.path[i], self.path[i + 1]);
+        let angle_before = (point.1 - previous.1).atan2(point.0 - previous.0);
+        let angle_after = (next.1 - point.1).atan2(next.0 - point.0);
+        let mut angle_change = (angle_after - angle_before).abs();
+        if angle_change > std::f64::consts::PI {
+            angle_change = 2.0 * std::f64::consts::PI - angle_change;
+        }
+        if angle_change < 1e-6 {
+            return f64::MAX;
+        }
+
+        // The radius of a circle that turns by `angle_change` over one segment
+        let segment_length = (next.0 - point.0).hypot(next.1 - point.1);
+        let radius = segment_length / angle_change;
+        (MAX_LATERAL_ACCELERATION * radius).sqrt()
+    }
+
+    /// Distance the car needs to come to a full stop from its current speed. This is a bit longer
+    /// than speed^2 / (2 * deceleration) because the speed only changes once per frame.
+    pub fn stopping_distance(&self) -> f64 {
+        // Speed drops by the deceleration every frame, so the distance covered is the sum of an
+        // arithmetic series
+        let frames = (self.speed / self.deceleration).ceil();
+        self.speed * frames - self.deceleration * frames * (frames - 1.0) / 2.0
+    }
+
+    /// Distance along the path from the car to the point where it enters the intersection
+    pub fn distance_to_intersection(&self) -> f64 {
+        if self.past_intersection() {
+            return 0.0;
+        }
+
+        let next_point = self.path[self.path_index];
+        let mut distance = (next_point.0 - self.position.0).hypot(next_point.1 - self.position.1);
+        for i in self.path_index..self.path_index_at_intersection {
+            distance +=
+                (self.path[i + 1].0 - self.path[i].0).hypot(self.path[i + 1].1 - self.path[i].1);
+        }
+        distance
+    }
+
+    /// Distance from the car's position to the line between the path point it last passed and the
+    /// one it's driving towards. Positive when the car is to the right of the path (looking the
+    /// way it drives) and negative when it's to the left. Cars ease into their new rotation at each
+    /// point, so they drift off the path a little in turns.
+    pub fn lateral_error(&self) -> f64 {
+        let from = self.path[self.path_index - 1];
+        let to = self.path[self.path_index];
+        let length = (to.0 - from.0).hypot(to.1 - from.1);
+        if length == 0.0 {
+            return 0.0;
+        }
+        // y points down the screen, so a positive cross product is clockwise, i.e. to the right
+        ((to.0 - from.0) * (self.position.1 - from.1)
+            - (to.1 - from.1) * (self.position.0 - from.0))
+            / length
+    }
+
+    /// Distance from the front of the car to the stop line of its approach, measured along the
+    /// road. Negative once the front of the car is over the line.
+    pub fn distance_to_stop_line(&self) -> f64 {
+        let (line_point, _) = stop_line(&self.geometry, self.origin);
+        let front = self.front();
+        let heading = approach_heading(self.origin);
+        (line_point.0 - front.0) * heading.0 + (line_point.1 - front.1) * heading.1
+    }
+
+    /// Moves the car back along its approach so its front bumper is `distance` behind the stop
+    /// line, standing still as if it had stopped there. Returns false, leaving the car where it
+    /// was, if that's further back than where the car starts.
+    pub fn place_behind_stop_line(&mut self, distance: f64) -> bool {
+        let (line_point, _) = stop_line(&self.geometry, self.origin);
+        let heading = approach_heading(self.origin);
+        let start = self.path[0];
+        // Distance from the start of the path to where the middle of the car goes
+        let along = (line_point.0 - start.0) * heading.0 + (line_point.1 - start.1) * heading.1
+            - self.length() / 2.0
+            - distance;
+        if along < 0.0 {
+            return false;
+        }
+
+        self.position = (start.0 + heading.0 * along, start.1 + heading.1 * along);
+        // Drive towards the first point that's still ahead
+        self.path_index = (1..=self.path_end)
+            .find(|&i| {
+                let point = self.path[i];
+                (point.0 - self.position.0) * heading.0 + (point.1 - self.position.1) * heading.1
+                    > 0.0
+            })
+            .unwrap_or(self.path_end);
+        self.distance_traveled = along;
+        self.speed = 0.0;
+        self.current_acceleration = 0.0;
+        self.stopped = true;
+        self.reaction_left = self.reaction_frames;
+        true
+    }
+
+    /// Estimated number of frames until the car reaches the intersection if it keeps accelerating
+    /// up to its max speed. Returns `None` if the car is already past the intersection.
+    pub fn frames_to_intersection(&self) -> Option<f64> {
+        if self.past_intersection() {
+            return None;
+        }
+
+        let distance = self.distance_to_intersection();
+        if self.speed >= self.max_speed {
+            return Some(distance / self.speed);
+        }
+
+        // Accelerating: d = vt + (1/2)at^2 until the car reaches its max speed
+        let frames_to_max_speed = (self.max_speed - self.speed) / self.acceleration;
+        let accelerating_distance = self.speed * frames_to_max_speed
+            + self.acceleration * frames_to_max_speed.powi(2) / 2.0;
+        if distance <= accelerating_distance {
+            let frames = (-self.speed
+                + (self.speed.powi(2) + 2.0 * self.acceleration * distance).sqrt())
+                / self.acceleration;
+            return Some(frames);
+        }
+        Some(frames_to_max_speed + (distance - accelerating_distance) / self.max_speed)
+    }
+
+    /// Returns if the car hasn't moved more than `DISTANCE_THRESHOLD` over the last
+    /// `window_frames` updates. Cars that haven't been around for that long aren't stuck.
+    pub fn is_stuck(&self, window_frames: usize) -> bool {
+        if window_frames == 0 || self.position_history.len() < window_frames {
+            return false;
+        }
+
+        let (x, y) = self.position;
+        self.position_history
+            .iter()
+            .skip(self.position_history.len() - window_frames)
+            .all(|&(px, py)| (x - px).hypot(y - py) <= DISTANCE_THRESHOLD)
+    }
+
+    /// Returns if any part of the car is inside the intersection
+    pub fn in_intersection(&self) -> bool {
+        let middle = self.geometry.middle();
+        let half_size = self.geometry.intersection_half_size();
+        // A car completely inside the intersection doesn't cross any of its edges
+        self.intersects_rect(intersection_bounds(&self.geometry))
+            || self.vertices().iter().any(|&(x, y)| {
+                (x - middle.0).abs() <= half_size && (y - middle.1).abs() <= half_size
+            })
+    }
+
+    fn past_intersection(&self) -> bool {
+        self.path_index > self.path_index_at_intersection
+    }
+
+    /// Returns if the car still has to go through the intersection's lights. Cars that leave the
+    /// road before it, join it after or use a slip lane never deal with the light.
+    pub fn reaches_intersection(&self) -> bool {
+        !self.through_intersection
+            && !self.is_channelized()
+            && self.path_end > self.path_index_at_intersection
+    }
+
+    /// Returns if the car is turning right through a slip lane
+    fn is_channelized(&self) -> bool {
+        self.direction == Direction::Right && self.geometry.layout.channelized_right
+    }
+
+    /// Returns if the car has made it all the way through the intersection
+    fn cleared_intersection(&self) -> bool {
+        self.path_index >= self.path_index_at_exit
+    }
+
+    /// Returns if the pose has reached the point it's driving towards: it's within
+    /// `DISTANCE_THRESHOLD` of it, or has gone past the line through it square to the way it was
+    /// heading for it. The second part holds however fast the car goes and however far its
+    /// rotation lags behind, so a point is never stepped over.
+    fn reached_point(&self, pose: &Pose) -> bool {
+        let point = self.path[pose.path_index];
+        if Car::intersects_point(pose.position, point) {
+            return true;
+        }
+        let heading = pose.target_rotation.to_radians();
+        (point.0 - pose.position.0) * heading.cos() + (point.1 - pose.position.1) * heading.sin()
+            <= 0.0
+    }
+
+    fn intersects_point(position: (f64, f64), point: (f64, f64)) -> bool {
+        let dx = position.0 - point.0;
+        let dy = position.1 - point.1;
         let distance = (dx * dx + dy * dy).sqrt();
         distance < DISTANCE_THRESHOLD
     }
@@ -265,11 +1292,11 @@ impl Car {
 
         my_lines.iter().any(|&line| {
             for other_line in &other_lines {
-                if line_intersect(line, &other_line) {
+                if line_intersect(line, other_line) {
                     return true;
                 }
             }
-            return false;
+            false
         })
     }
 
@@ -290,25 +1317,59 @@ impl Car {
 
         my_lines.iter().any(|&line| {
             for other_line in &other_lines {
-                if line_intersect(line, &other_line) {
+                if line_intersect(line, other_line) {
                     return true;
                 }
             }
-            return false;
+            false
         })
     }
 
+    /// Returns if two regular sized cars at the given positions and rotations overlap. Use
+    /// `overlaps` for cars that might be longer.
     pub fn cars_intersect(
         position1: (f64, f64),
         rotation1: f64,
         position2: (f64, f64),
         rotation2: f64,
     ) -> bool {
-        let vertices1 = Car::vertices_with_pos_and_rot(position1, rotation1);
-        let vertices2 = Car::vertices_with_pos_and_rot(position2, rotation2);
+        Car::cars_of_length_intersect(
+            (position1, rotation1, CAR_WIDTH),
+            (position2, rotation2, CAR_WIDTH),
+        )
+    }
+
+    /// Like `cars_intersect`, for cars given as their position, rotation and length
+    pub fn cars_of_length_intersect(
+        (position1, rotation1, length1): ((f64, f64), f64, f64),
+        (position2, rotation2, length2): ((f64, f64), f64, f64),
+    ) -> bool {
+        let vertices1 = Car::vertices_with_pos_and_rot(position1, rotation1, length1);
+        let vertices2 = Car::vertices_with_pos_and_rot(position2, rotation2, length2);
         Car::intersects_rect_with_two_cars(vertices1, vertices2)
     }
 
+    /// Returns the points where the outlines of the two cars cross. Slower than `cars_intersect`,
+    /// so only use it when the points themselves are needed.
+    pub fn collision_points(&self, other: &Car) -> Vec<(f64, f64)> {
+        let lines = |vertices: [(f64, f64); 4]| {
+            (0..4)
+                .map(|i| (vertices[i], vertices[(i + 1) % 4]))
+                .collect::<Vec<_>>()
+        };
+        let my_lines = lines(self.vertices());
+        let other_lines = lines(other.vertices());
+
+        my_lines
+            .iter()
+            .flat_map(|&line| {
+                other_lines
+                    .iter()
+                    .filter_map(move |other_line| line_intersection_point(line, other_line))
+            })
+            .collect()
+    }
+
     fn get_vertex(&self, vertex: (f64, f64)) -> (f64, f64) {
         (
             self.position.0 + (vertex.0 * self.rotation.to_radians().cos())
@@ -333,25 +1394,36 @@ impl Car {
         )
     }
 
-    pub fn vertices(&self) -> [(f64, f64); 4] {
-        let half_width = CAR_WIDTH / 2.0;
-        let half_height = CAR_HEIGHT / 2.0;
+    /// Length of the car from bumper to bumper
+    pub fn length(&self) -> f64 {
+        self.kind.length()
+    }
 
-        let front_left = (-half_width, -half_height);
-        let front_right = (half_width, -half_height);
-        let back_left = (-half_width, half_height);
-        let back_right = (half_width, half_height);
+    /// Middle of the front bumper
+    pub fn front(&self) -> (f64, f64) {
+        self.get_vertex((self.length() / 2.0, 0.0))
+    }
 
-        [
-            self.get_vertex(front_left),
-            self.get_vertex(front_right),
-            self.get_vertex(back_right),
-            self.get_vertex(back_left),
-        ]
+    /// Middle of the rear bumper
+    pub fn rear(&self) -> (f64, f64) {
+        self.get_vertex((-self.length() / 2.0, 0.0))
+    }
+
+    pub fn vertices(&self) -> [(f64, f64); 4] {
+        Car::vertices_with_pos_and_rot(self.position, self.rotation, self.length())
+    }
+
+    /// Returns if the outlines of the two cars overlap
+    pub fn overlaps(&self, other: &Car) -> bool {
+        Car::intersects_rect_with_two_cars(self.vertices(), other.vertices())
     }
 
-    fn vertices_with_pos_and_rot(position: (f64, f64), rotation: f64) -> [(f64, f64); 4] {
-        let half_width = CAR_WIDTH / 2.0;
+    fn vertices_with_pos_and_rot(
+        position: (f64, f64),
+        rotation: f64,
+        length: f64,
+    ) -> [(f64, f64); 4] {
+        let half_width = length / 2.0;
         let half_height = CAR_HEIGHT / 2.0;
 
         let front_left = (-half_width, -half_height);
@@ -367,13 +1439,8 @@ impl Car {
         ]
     }
 
-    pub fn draw(&self, cars: &Vec<Car>, context: &Context, graphics: &mut G2d) {
+    pub fn draw(&self, cars: &[Car], mode: DrawMode, context: &Context, graphics: &mut G2d) {
         let alpha = 1.0;
-        let transform = context
-            .transform
-            .trans(self.position.0, self.position.1)
-            .rot_deg(self.rotation);
-
         let fill_color = if cars
             .iter()
             .filter(|c| c.id != self.id)
@@ -381,17 +1448,57 @@ impl Car {
         {
             [1.0, 0.0, 0.0, alpha]
         } else {
-            [1.0, 1.0, 1.0, alpha]
+            match mode {
+                DrawMode::Plain => self.color,
+                DrawMode::BySpeed => lerp_color(
+                    [1.0, 0.6, 0.0, alpha],
+                    [0.2, 0.9, 0.3, alpha],
+                    self.speed / self.max_speed,
+                ),
+                DrawMode::ByWaitTime => lerp_color(
+                    [1.0, 1.0, 1.0, alpha],
+                    [0.6, 0.2, 0.8, alpha],
+                    self.wait_frames / WAIT_COLOR_FRAMES,
+                ),
+            }
         };
+        Car::draw_with_pos_and_rot(
+            self.position,
+            self.rotation,
+            self.length(),
+            self.direction,
+            fill_color,
+            context,
+            graphics,
+        );
+    }
+
+    /// Draws the body and direction arrow of a car of the given length at the given position and
+    /// rotation
+    pub fn draw_with_pos_and_rot(
+        position: (f64, f64),
+        rotation: f64,
+        length: f64,
+        direction: Direction,
+        fill_color: [f32; 4],
+        context: &Context,
+        graphics: &mut G2d,
+    ) {
+        let alpha = fill_color[3];
+        let transform = context
+            .transform
+            .trans(position.0, position.1)
+            .rot_deg(rotation);
+
         rectangle_from_to(
             fill_color,
-            [-CAR_WIDTH / 2.0, -CAR_HEIGHT / 2.0],
-            [CAR_WIDTH / 2.0, CAR_HEIGHT / 2.0],
+            [-length / 2.0, -CAR_HEIGHT / 2.0],
+            [length / 2.0, CAR_HEIGHT / 2.0],
             transform,
             graphics,
         );
 
-        match self.direction {
+        match direction {
             Direction::Straight => Line::new_round([0.0, 0.0, 0.0, alpha], ARROW_STROKE_WEIGHT)
                 .draw_arrow(
                     [-CAR_WIDTH / 2.5, 0.0, CAR_WIDTH / 2.5, 0.0],
@@ -417,165 +1524,736 @@ impl Car {
                     graphics,
                 ),
         }
+    }
+
+    /// Draws the path as a line that fades from green at the start to red at the end. The
+    /// segment leading up to the stop point is drawn in white.
+    pub fn draw_path(&self, context: &Context, graphics: &mut G2d) {
+        let segments = self.path.len().saturating_sub(1);
+        for (i, segment) in self.path.windows(2).enumerate() {
+            let color = if i + 1 == self.path_index_at_intersection {
+                [1.0, 1.0, 1.0, 1.0]
+            } else {
+                lerp_color(
+                    [0.0, 1.0, 0.0, 0.8],
+                    [1.0, 0.0, 0.0, 0.8],
+                    i as f64 / segments as f64,
+                )
+            };
+            line_from_to(
+                color,
+                1.5,
+                [segment[0].0, segment[0].1],
+                [segment[1].0, segment[1].1],
+                context.transform,
+                graphics,
+            );
+        }
+    }
 
-        // self.draw_path(context, graphics);
+    /// Segments of a line through the car's positions over the last `frames` updates, oldest
+    /// first, each with how opaque to draw it so the line fades out towards the oldest. Only goes
+    /// back `POSITION_HISTORY_LENGTH` frames at most.
+    pub fn trail(&self, frames: usize) -> Vec<TrailSegment> {
+        let frames = frames.min(self.position_history.len());
+        let skip = self.position_history.len() - frames;
+        self.position_history
+            .iter()
+            .skip(skip)
+            .zip(self.position_history.iter().skip(skip + 1))
+            .enumerate()
+            .map(|(i, (&from, &to))| (from, to, (i + 1) as f32 / frames as f32))
+            .collect()
     }
 
-    fn draw_path(&self, context: &Context, graphics: &mut G2d) {
-        self.path.iter().for_each(|&point| {
+    /// Draws `trail`, in the car's color
+    pub fn draw_trail(&self, frames: usize, context: &Context, graphics: &mut G2d) {
+        let [r, g, b, _] = self.color;
+        for (from, to, alpha) in self.trail(frames) {
             line_from_to(
-                [1.0, 0.0, 0.0, 1.0],
-                3.0,
-                [point.0 - 2.0, point.1 - 2.0],
-                [point.0 + 2.0, point.1 + 2.0],
+                [r, g, b, alpha * 0.8],
+                1.5,
+                [from.0, from.1],
+                [to.0, to.1],
                 context.transform,
                 graphics,
             );
+        }
+    }
+
+    pub fn snapshot(&self, traffic_light: &dyn ControllerStrategy) -> CarSnapshot {
+        CarSnapshot {
+            id: self.id,
+            origin: self.origin,
+            direction: self.direction,
+            position: self.position,
+            rotation: self.rotation,
+            length: self.length(),
+            color: self.color,
+            speed: self.speed,
+            stopped: self.stopped,
+            light: traffic_light.light_state(self.origin, self.direction),
+        }
+    }
+
+    /// Indices of the first point of the path in the intersection, where a car waits for its
+    /// light, and of the first point after that which is out of the intersection again
+    pub fn intersection_indices(path: &[(f64, f64)], geometry: &WorldGeometry) -> (usize, usize) {
+        let middle = geometry.middle();
+        let half_size = geometry.intersection_half_size();
+        // Points right on the edge count as in when going in, and as out when coming out
+        let within = |point: &(f64, f64), half_size: f64| {
+            (point.0 - middle.0).abs() <= half_size && (point.1 - middle.1).abs() <= half_size
+        };
+        let last_index = path.len().saturating_sub(1);
+        let entry = path
+            .iter()
+            .position(|point| within(point, half_size + INTERSECTION_EDGE_TOLERANCE))
+            .unwrap_or(last_index);
+        // Start looking one past the entry so a point inside the tolerance band can't be both
+        let exit = path
+            .iter()
+            .skip(entry + 1)
+            .position(|point| !within(point, half_size - INTERSECTION_EDGE_TOLERANCE))
+            .map_or(last_index, |offset| entry + 1 + offset);
+        (entry, exit)
+    }
+
+    /// Generates the points a car drives through, evenly spaced along the way
+    pub fn calculate_path(
+        car: &traffic_light_controller::SimplifiedCar,
+        lane: usize,
+        num_points: usize,
+        geometry: &WorldGeometry,
+    ) -> Vec<(f64, f64)> {
+        Car::calculate_path_to_lane(car, lane, None, num_points, geometry)
+    }
+
+    /// Like `calculate_path`, but turns go into `receiving_lane`, counted out from the middle of
+    /// the road they turn into, instead of the lane nearest to them. Straight paths stay in their
+    /// lane.
+    pub fn calculate_path_to_lane(
+        car: &traffic_light_controller::SimplifiedCar,
+        lane: usize,
+        receiving_lane: Option<usize>,
+        num_points: usize,
+        geometry: &WorldGeometry,
+    ) -> Vec<(f64, f64)> {
+        let path = match car.direction {
+            Direction::Left => generate_left_turn_path(
+                car.origin,
+                receiving_lane.unwrap_or(0),
+                num_points,
+                geometry,
+            ),
+            Direction::Right => generate_right_turn_path(
+                car.origin,
+                receiving_lane.unwrap_or(geometry.layout.lane_offset(Direction::Right, 0)),
+                num_points,
+                geometry,
+            ),
+            Direction::Straight => generate_straight_path(car.origin, lane, num_points, geometry),
+        };
+        resample_path(&path)
+    }
+}
+
+/// Builds a car, with anything that isn't set behaving like a regular car
+pub struct CarBuilder {
+    id: usize,
+    origin: Origin,
+    direction: Direction,
+    lane: usize,
+    receiving_lane: Option<usize>,
+    /// Legs of the intersection that exist, if not all of them
+    approaches: Option<HashSet<Origin>>,
+    kind: CarKind,
+    color: Option<[f32; 4]>,
+    bus_stop: Option<BusStop>,
+    span: (f64, f64),
+    max_speed: f64,
+    acceleration: f64,
+    deceleration: f64,
+    emergency_deceleration: f64,
+    max_jerk: f64,
+    reaction_frames: f64,
+    following_model: FollowingModelKind,
+    compliant: bool,
+    num_path_points: usize,
+    geometry: WorldGeometry,
+}
+
+impl CarBuilder {
+    pub fn new(id: usize, origin: Origin, direction: Direction) -> CarBuilder {
+        CarBuilder {
+            id,
+            origin,
+            direction,
+            lane: 0,
+            receiving_lane: None,
+            approaches: None,
+            kind: CarKind::Regular,
+            color: None,
+            bus_stop: None,
+            span: (0.0, 1.0),
+            max_speed: MAX_SPEED,
+            acceleration: ACCELERATION,
+            deceleration: DECELERATION,
+            emergency_deceleration: EMERGENCY_DECELERATION,
+            max_jerk: MAX_JERK,
+            reaction_frames: REACTION_FRAMES,
+            following_model: FollowingModelKind::default(),
+            compliant: true,
+            num_path_points: NUM_PATH_POINTS,
+            geometry: WorldGeometry::default(),
+        }
+    }
+
+    /// Lanes are counted outwards from the lane the direction normally uses, and only straight
+    /// cars can use a lane other than 0.
+    pub fn lane(mut self, lane: usize) -> CarBuilder {
+        self.lane = lane;
+        self
+    }
+
+    /// Lane a turning car turns into, counted out from the middle of the road it turns into like
+    /// the lanes of `IntersectionLayout`. Defaults to the lane nearest to the turn: 0 for left
+    /// turns, and the lane in line with the right turn lane for right turns.
+    pub fn receiving_lane(mut self, receiving_lane: usize) -> CarBuilder {
+        self.receiving_lane = Some(receiving_lane);
+        self
+    }
+
+    /// Legs the intersection has. Building a car that comes from or turns into any other leg
+    /// fails. Defaults to all four.
+    pub fn approaches(mut self, approaches: HashSet<Origin>) -> CarBuilder {
+        self.approaches = Some(approaches);
+        self
+    }
+
+    pub fn kind(mut self, kind: CarKind) -> CarBuilder {
+        self.kind = kind;
+        self
+    }
+
+    /// Stops the car `distance` before its stop line for `dwell_frames` frames, whatever the light
+    /// is. Meant for buses.
+    pub fn bus_stop(mut self, distance: f64, dwell_frames: f64) -> CarBuilder {
+        self.bus_stop = Some(BusStop {
+            distance,
+            dwell_frames,
         });
+        self
     }
 
-    pub fn calculate_waiting_point_index(car: &traffic_light_controller::SimplifiedCar) -> usize {
-        NUM_PATH_POINTS / 3
-            + if car.direction == Direction::Straight {
-                1
-            } else {
-                0
+    /// Only drives the part of the path between the `start` and `end` fractions of the way along
+    /// it, like a car pulling out of or into a driveway. Defaults to the whole path.
+    pub fn span(mut self, start: f64, end: f64) -> CarBuilder {
+        self.span = (start, end);
+        self
+    }
+
+    /// Defaults to white, blue for emergency vehicles or yellow for buses
+    pub fn color(mut self, color: [f32; 4]) -> CarBuilder {
+        self.color = Some(color);
+        self
+    }
+
+    pub fn max_speed(mut self, max_speed: f64) -> CarBuilder {
+        self.max_speed = max_speed;
+        self
+    }
+
+    pub fn acceleration(mut self, acceleration: f64) -> CarBuilder {
+        self.acceleration = acceleration;
+        self
+    }
+
+    pub fn deceleration(mut self, deceleration: f64) -> CarBuilder {
+        self.deceleration = deceleration;
+        self
+    }
+
+    /// Braking used when braking at the normal deceleration would run into what's ahead
+    pub fn emergency_deceleration(mut self, emergency_deceleration: f64) -> CarBuilder {
+        self.emergency_deceleration = emergency_deceleration;
+        self
+    }
+
+    /// Lower values make the car ease into accelerating and braking
+    pub fn max_jerk(mut self, max_jerk: f64) -> CarBuilder {
+        self.max_jerk = max_jerk;
+        self
+    }
+
+    /// Frames the car waits after it's allowed to go before it starts moving again, like a driver
+    /// noticing the light turned green or the car ahead pulled away
+    pub fn reaction_frames(mut self, reaction_frames: f64) -> CarBuilder {
+        self.reaction_frames = reaction_frames;
+        self
+    }
+
+    /// How the car keeps its distance to the car ahead. Defaults to `ConstantGap`.
+    pub fn following_model(mut self, following_model: FollowingModelKind) -> CarBuilder {
+        self.following_model = following_model;
+        self
+    }
+
+    /// Drivers who don't comply go through yellows and reds that came on less than
+    /// `RED_RUNNER_WINDOW` frames ago instead of stopping. Defaults to true.
+    pub fn compliant(mut self, compliant: bool) -> CarBuilder {
+        self.compliant = compliant;
+        self
+    }
+
+    /// Number of points in the car's path. Cars that don't need an accurate path can use less.
+    pub fn num_path_points(mut self, num_path_points: usize) -> CarBuilder {
+        self.num_path_points = num_path_points;
+        self
+    }
+
+    pub fn geometry(mut self, geometry: WorldGeometry) -> CarBuilder {
+        self.geometry = geometry;
+        self
+    }
+
+    /// Checks that the car comes from and goes to legs that exist, from a lane it can use and into
+    /// a lane that exists
+    pub fn check_movement(&self) -> Result<(), MovementError> {
+        if let Some(approaches) = &self.approaches {
+            if !approaches.contains(&self.origin) {
+                return Err(MovementError::MissingOrigin(self.origin));
+            }
+            let exit = exit_leg(self.origin, self.direction);
+            if !approaches.contains(&exit) {
+                return Err(MovementError::MissingExit(exit));
+            }
+        }
+        let lanes = match self.direction {
+            Direction::Straight => self.geometry.layout.straight_lane_count(),
+            // Turns always go from the lane nearest to where they turn
+            Direction::Left | Direction::Right => 1,
+        };
+        if self.lane >= lanes {
+            return Err(MovementError::InvalidLane(self.lane));
+        }
+        if let Some(receiving_lane) = self.receiving_lane {
+            // Turns can go into any lane of the road they turn into
+            let receiving_lanes = match self.direction {
+                Direction::Left | Direction::Right => self.geometry.layout.lanes(),
+                Direction::Straight => 0,
+            };
+            if receiving_lane >= receiving_lanes {
+                return Err(MovementError::InvalidReceivingLane(receiving_lane));
             }
+        }
+        Ok(())
+    }
+
+    /// Panics if the movement can't be made or the car's path is broken or too short. Use
+    /// `try_build` for anything that comes from outside, like a lane someone picked.
+    pub fn build(self) -> Car {
+        self.try_build().unwrap()
+    }
+
+    /// Builds the car, or returns an error if its movement isn't possible, it can't speed up or
+    /// slow down, or its path is broken or too short to drive along
+    pub fn try_build(self) -> Result<Car, PathError> {
+        self.check_movement().map_err(PathError::Movement)?;
+        // Stopping distances divide by these
+        for rate in [
+            self.acceleration,
+            self.deceleration,
+            self.emergency_deceleration,
+        ] {
+            if rate.is_nan() || rate <= 0.0 {
+                return Err(PathError::NonPositiveRate(rate));
+            }
+        }
+
+        let path = Car::calculate_path_to_lane(
+            &SimplifiedCar::new(self.origin, self.direction),
+            self.lane,
+            self.receiving_lane,
+            self.num_path_points,
+            &self.geometry,
+        );
+        // Paths with less points, or across a bigger canvas, have bigger gaps between them
+        let canvas_scale = (self.geometry.width.max(self.geometry.height) / WIDTH as f64).max(1.0);
+        let max_gap = MAX_PATH_GAP
+            * (NUM_PATH_POINTS as f64 / self.num_path_points as f64).max(1.0)
+            * canvas_scale;
+        validate_path(
+            &path,
+            expected_path_length(self.direction, self.num_path_points),
+            max_gap,
+        )?;
+
+        // Cars start by driving towards the point after their first one
+        let last_index = path.len().saturating_sub(1) as f64;
+        let path_start = (self.span.0.clamp(0.0, 1.0) * last_index).round() as usize;
+        let path_end = (self.span.1.clamp(0.0, 1.0) * last_index).round() as usize;
+        if path.len() < 2 || path_end <= path_start {
+            return Err(PathError::TooShort(
+                (path_end + 1).saturating_sub(path_start).min(path.len()),
+            ));
+        }
+        let (position, rotation) = if path_start == 0 {
+            let rotation: f64 = match self.origin {
+                Origin::North => 90.0,
+                Origin::South => 270.0,
+                Origin::East => 180.0,
+                Origin::West => 0.0,
+            };
+            (
+                get_position(self.origin, self.direction, self.lane, &self.geometry),
+                rotation,
+            )
+        } else {
+            let (from, to) = (path[path_start], path[path_start + 1]);
+            (from, (to.1 - from.1).atan2(to.0 - from.0).to_degrees())
+        };
+        let (path_index_at_intersection, path_index_at_exit) =
+            Car::intersection_indices(&path, &self.geometry);
+        Ok(Car {
+            id: self.id,
+            origin: self.origin,
+            direction: self.direction,
+            lane: self.lane,
+            receiving_lane: self.receiving_lane,
+            kind: self.kind,
+            color: self.color.unwrap_or(match self.kind {
+                CarKind::Regular => [1.0, 1.0, 1.0, 1.0],
+                CarKind::Emergency => [0.4, 0.6, 1.0, 1.0],
+                CarKind::Bus => [1.0, 0.8, 0.2, 1.0],
+            }),
+            max_speed: self.max_speed,
+            acceleration: self.acceleration,
+            deceleration: self.deceleration,
+            emergency_deceleration: self.emergency_deceleration,
+            max_jerk: self.max_jerk,
+            reaction_frames: self.reaction_frames,
+            reaction_left: 0.0,
+            following_model: self.following_model,
+            current_acceleration: 0.0,
+            position,
+            rotation,
+            target_rotation: rotation,
+            speed: 0.0,
+            stopped: false,
+            automatically_stopped: false,
+            path,
+            path_index: path_start + 1,
+            path_end,
+            path_index_on_red_change: None,
+            path_index_at_intersection,
+            path_index_at_exit,
+            intersection_arrival: None,
+            num_path_points: self.num_path_points,
+            red_dwell: 0.0,
+            compliant: self.compliant,
+            frames_since_go: RED_RUNNER_WINDOW,
+            blocked_frames: 0.0,
+            lane_change_cooldown: 0.0,
+            finished: false,
+            // Cars that join after the intersection never go through it
+            through_intersection: path_start >= path_index_at_intersection,
+            position_history: VecDeque::with_capacity(POSITION_HISTORY_LENGTH),
+            frames: 0,
+            wait_frames: 0.0,
+            distance_traveled: 0.0,
+            stop_count: 0,
+            bus_stop: self.bus_stop,
+            dwell_frames: 0.0,
+            geometry: self.geometry,
+        })
+    }
+}
+
+/// Blends from color `a` (t = 0) to color `b` (t = 1)
+fn lerp_color(a: [f32; 4], b: [f32; 4], t: f64) -> [f32; 4] {
+    let t = t.clamp(0.0, 1.0) as f32;
+    [
+        a[0] + (b[0] - a[0]) * t,
+        a[1] + (b[1] - a[1]) * t,
+        a[2] + (b[2] - a[2]) * t,
+        a[3] + (b[3] - a[3]) * t,
+    ]
+}
+
+/// Returns the leg of the intersection a car leaves through
+pub fn exit_leg(origin: Origin, direction: Direction) -> Origin {
+    match (origin, direction) {
+        (Origin::North, Direction::Straight) => Origin::South,
+        (Origin::South, Direction::Straight) => Origin::North,
+        (Origin::East, Direction::Straight) => Origin::West,
+        (Origin::West, Direction::Straight) => Origin::East,
+        (Origin::North, Direction::Left) => Origin::East,
+        (Origin::South, Direction::Left) => Origin::West,
+        (Origin::East, Direction::Left) => Origin::South,
+        (Origin::West, Direction::Left) => Origin::North,
+        (Origin::North, Direction::Right) => Origin::West,
+        (Origin::South, Direction::Right) => Origin::East,
+        (Origin::East, Direction::Right) => Origin::North,
+        (Origin::West, Direction::Right) => Origin::South,
+    }
+}
+
+/// Reasons a car can't make the movement it was asked to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovementError {
+    /// The car would come from a leg the intersection doesn't have
+    MissingOrigin(Origin),
+    /// The car would leave through a leg the intersection doesn't have
+    MissingExit(Origin),
+    /// The movement can't be made from that lane
+    InvalidLane(usize),
+    /// The road the car turns into doesn't have that lane
+    InvalidReceivingLane(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathError {
+    /// The car can't make its movement, so it doesn't get a path
+    Movement(MovementError),
+    /// The car's acceleration, deceleration or emergency deceleration isn't above 0
+    NonPositiveRate(f64),
+    /// The path doesn't have as many points as the path generators make
+    WrongLength(usize),
+    /// The path has less than the 2 points a car needs
+    TooShort(usize),
+    /// The points at `index` and `index + 1` are further apart than allowed
+    Gap { index: usize, gap: f64 },
+}
+
+/// Number of points the path generators make for a movement asked for `num_points`. Turns are made
+/// of three equal parts, so they drop the remainder.
+pub fn expected_path_length(direction: Direction, num_points: usize) -> usize {
+    match direction {
+        Direction::Straight => num_points,
+        Direction::Left | Direction::Right => num_points / 3 * 3,
+    }
+}
+
+/// Checks that a path has `expected_length` points and that no two consecutive points are more
+/// than `max_gap` apart
+pub fn validate_path(
+    path: &[(f64, f64)],
+    expected_length: usize,
+    max_gap: f64,
+) -> Result<(), PathError> {
+    if path.len() != expected_length {
+        return Err(PathError::WrongLength(path.len()));
+    }
+
+    for (index, points) in path.windows(2).enumerate() {
+        let gap = (points[0].0 - points[1].0).hypot(points[0].1 - points[1].1);
+        if gap > max_gap {
+            return Err(PathError::Gap { index, gap });
+        }
+    }
+    Ok(())
+}
+
+/// Moves the points of a path so they're evenly spaced along it. The generators space points
+/// evenly within each straight or turn, but the straights and turns have different spacings, so
+/// without this cars would hit points more or less often depending on where they are. Keeps the
+/// number of points and the first and last point.
+fn resample_path(path: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    if path.len() < 3 {
+        return path.to_vec();
     }
 
-    pub fn calculate_path(car: &traffic_light_controller::SimplifiedCar) -> Vec<(f64, f64)> {
-        match car.direction {
-            Direction::Left => generate_left_turn_path(car.origin),
-            Direction::Right => generate_right_turn_path(car.origin),
-            Direction::Straight => generate_straight_path(car.origin),
+    // Distance along the path to each point
+    let mut distances = vec![0.0];
+    for points in path.windows(2) {
+        let gap = (points[1].0 - points[0].0).hypot(points[1].1 - points[0].1);
+        distances.push(distances.last().unwrap() + gap);
+    }
+    let spacing = distances.last().unwrap() / (path.len() - 1) as f64;
+
+    let mut resampled = Vec::with_capacity(path.len());
+    let mut segment = 0;
+    for i in 0..path.len() - 1 {
+        let distance = spacing * i as f64;
+        while segment + 2 < path.len() && distances[segment + 1] < distance {
+            segment += 1;
         }
+        let length = distances[segment + 1] - distances[segment];
+        let t = if length > 0.0 {
+            ((distance - distances[segment]) / length).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let (from, to) = (path[segment], path[segment + 1]);
+        resampled.push((from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t));
+    }
+    resampled.push(*path.last().unwrap());
+    resampled
+}
+
+/// Way cars coming from the origin face until they get to the intersection
+fn approach_heading(origin: Origin) -> (f64, f64) {
+    match origin {
+        Origin::North => (0.0, 1.0),
+        Origin::South => (0.0, -1.0),
+        Origin::East => (-1.0, 0.0),
+        Origin::West => (1.0, 0.0),
     }
 }
 
-fn get_position(origin: Origin, direction: Direction) -> (f64, f64) {
-    let middle = (WIDTH as f64 / 2.0, HEIGHT as f64 / 2.0);
-    let offset = match direction {
-        Direction::Left => 0.0,
-        Direction::Straight => 1.0,
-        Direction::Right => 2.0,
+/// Slides the straight out of the intersection along its road so it starts a point gap after the
+/// end of the turn. The straights are generated as if they came in from the edge, spawn setback
+/// and all, so they start in the wrong place until they're moved.
+fn join_exit_straight(path: &[(f64, f64)], exit: &mut [(f64, f64)]) {
+    let (Some(&turn_end), &[first, second, ..]) = (path.last(), &*exit) else {
+        return;
     };
+    let shift = (
+        turn_end.0 - first.0 + (second.0 - first.0),
+        turn_end.1 - first.1 + (second.1 - first.1),
+    );
+    // Only along the road, so the straight stays in its lane
+    let horizontal = second.0 != first.0;
+    exit.iter_mut().for_each(|point| {
+        if horizontal {
+            point.0 += shift.0;
+        } else {
+            point.1 += shift.1;
+        }
+    });
+}
+
+fn get_position(
+    origin: Origin,
+    direction: Direction,
+    lane: usize,
+    geometry: &WorldGeometry,
+) -> (f64, f64) {
+    let middle = geometry.middle();
+    let lane_width = geometry.layout.lane_width;
+    let offset = geometry.layout.lane_offset(direction, lane) as f64;
     match origin {
         Origin::North => (
-            middle.0 - LANE_WIDTH / 2.0 - offset * LANE_WIDTH,
-            -CAR_WIDTH / 2.0,
+            middle.0 - lane_width / 2.0 - offset * lane_width,
+            -geometry.spawn_setback,
         ),
         Origin::South => (
-            middle.0 + LANE_WIDTH / 2.0 + offset * LANE_WIDTH,
-            HEIGHT as f64 + CAR_WIDTH / 2.0,
+            middle.0 + lane_width / 2.0 + offset * lane_width,
+            geometry.height + geometry.spawn_setback,
         ),
         Origin::East => (
-            WIDTH as f64 + CAR_WIDTH / 2.0,
-            middle.1 - LANE_WIDTH / 2.0 - offset * LANE_WIDTH,
+            geometry.width + geometry.spawn_setback,
+            middle.1 - lane_width / 2.0 - offset * lane_width,
         ),
         Origin::West => (
-            -CAR_WIDTH / 2.0,
-            middle.1 + LANE_WIDTH / 2.0 + offset * LANE_WIDTH,
+            -geometry.spawn_setback,
+            middle.1 + lane_width / 2.0 + offset * lane_width,
         ),
     }
 }
 
 /// Generates the initial straight that all cars have to do before they can turn
-fn generate_straight_path_third(origin: Origin, direction: Direction) -> Vec<(f64, f64)> {
-    let vertical_point_gap = (HEIGHT as f64 / 2.0 - LANE_WIDTH * 3.0 + CAR_WIDTH / 2.0) as f64
-        / (NUM_PATH_POINTS / 3) as f64;
-    let horizontal_point_gap = (WIDTH as f64 / 2.0 - LANE_WIDTH * 3.0 + CAR_WIDTH / 2.0) as f64
-        / (NUM_PATH_POINTS / 3) as f64;
-    let position = get_position(origin, direction);
+fn generate_straight_path_third(
+    origin: Origin,
+    direction: Direction,
+    lane: usize,
+    num_points: usize,
+    geometry: &WorldGeometry,
+) -> Vec<(f64, f64)> {
+    let half_size = geometry.intersection_half_size();
+    let vertical_point_gap =
+        (geometry.height / 2.0 - half_size + geometry.spawn_setback) / (num_points / 3) as f64;
+    let horizontal_point_gap =
+        (geometry.width / 2.0 - half_size + geometry.spawn_setback) / (num_points / 3) as f64;
+    let position = get_position(origin, direction, lane, geometry);
 
     match origin {
-        Origin::North => (0..NUM_PATH_POINTS / 3)
+        Origin::North => (0..num_points / 3)
             .map(|i| (position.0, position.1 + i as f64 * vertical_point_gap))
             .collect(),
-        Origin::South => (0..NUM_PATH_POINTS / 3)
+        Origin::South => (0..num_points / 3)
             .map(|i| (position.0, position.1 - (i as f64 * vertical_point_gap)))
             .collect(),
-        Origin::East => (0..NUM_PATH_POINTS / 3)
+        Origin::East => (0..num_points / 3)
             .map(|i| (position.0 - (i as f64 * horizontal_point_gap), position.1))
             .collect(),
-        Origin::West => (0..NUM_PATH_POINTS / 3)
+        Origin::West => (0..num_points / 3)
             .map(|i| (position.0 + (i as f64 * horizontal_point_gap), position.1))
             .collect(),
     }
 }
 
-fn generate_left_turn_path(origin: Origin) -> Vec<(f64, f64)> {
-    let middle = (WIDTH as f64 / 2.0, HEIGHT as f64 / 2.0);
+/// Turns left from the innermost lane into `receiving_lane`, counting out from the middle of the
+/// road. The turn is a quarter ellipse so it meets both straights head on whichever lane it goes
+/// into.
+fn generate_left_turn_path(
+    origin: Origin,
+    receiving_lane: usize,
+    num_points: usize,
+    geometry: &WorldGeometry,
+) -> Vec<(f64, f64)> {
+    let middle = geometry.middle();
+    let half_size = geometry.intersection_half_size();
+    let lane_width = geometry.layout.lane_width;
+    // How far the turn reaches across the road it comes from and the road it goes into
+    let entry_radius = half_size + lane_width / 2.0;
+    let exit_radius = entry_radius + receiving_lane as f64 * lane_width;
     // Initial straight
-    let mut path = generate_straight_path_third(origin, Direction::Left);
+    let mut path = generate_straight_path_third(origin, Direction::Left, 0, num_points, geometry);
 
     // Turn
     let turn_origin = match origin {
-        Origin::North => (
-            middle.0 + LANE_WIDTH as f64 * 3.0,
-            middle.1 - LANE_WIDTH as f64 * 3.0,
-        ),
-        Origin::South => (
-            middle.0 - LANE_WIDTH as f64 * 3.0,
-            middle.1 + LANE_WIDTH as f64 * 3.0,
-        ),
-        Origin::East => (
-            middle.0 + LANE_WIDTH as f64 * 3.0,
-            middle.1 + LANE_WIDTH as f64 * 3.0,
-        ),
-        Origin::West => (
-            middle.0 - LANE_WIDTH as f64 * 3.0,
-            middle.1 - LANE_WIDTH as f64 * 3.0,
-        ),
+        Origin::North => (middle.0 + half_size, middle.1 - half_size),
+        Origin::South => (middle.0 - half_size, middle.1 + half_size),
+        Origin::East => (middle.0 + half_size, middle.1 + half_size),
+        Origin::West => (middle.0 - half_size, middle.1 - half_size),
     };
     let turn_path = match origin {
-        Origin::North => (0..NUM_PATH_POINTS / 3)
+        Origin::North => (0..num_points / 3)
             .map(|i| {
-                let angle = (i as f64) / (NUM_PATH_POINTS as f64 / 3.0) * std::f64::consts::PI
-                    / 2.0
+                let angle = (i as f64) / (num_points as f64 / 3.0) * std::f64::consts::PI / 2.0
                     - std::f64::consts::PI / 2.0;
                 (
-                    turn_origin.0 - angle.cos() * LANE_WIDTH * 3.5,
-                    turn_origin.1 - angle.sin() * LANE_WIDTH * 3.5,
+                    turn_origin.0 - angle.cos() * entry_radius,
+                    turn_origin.1 - angle.sin() * exit_radius,
                 )
             })
             .collect::<Vec<_>>(),
-        Origin::South => (0..NUM_PATH_POINTS / 3)
+        Origin::South => (0..num_points / 3)
             .map(|i| {
-                let angle = (i as f64) / (NUM_PATH_POINTS as f64 / 3.0) * std::f64::consts::PI
-                    / 2.0
+                let angle = (i as f64) / (num_points as f64 / 3.0) * std::f64::consts::PI / 2.0
                     + std::f64::consts::PI / 2.0;
                 (
-                    turn_origin.0 - angle.cos() * LANE_WIDTH * 3.5,
-                    turn_origin.1 - angle.sin() * LANE_WIDTH * 3.5,
+                    turn_origin.0 - angle.cos() * entry_radius,
+                    turn_origin.1 - angle.sin() * exit_radius,
                 )
             })
             .collect::<Vec<_>>(),
-        Origin::East => (0..NUM_PATH_POINTS / 3)
+        Origin::East => (0..num_points / 3)
             .map(|i| {
-                let angle = (i as f64) / (NUM_PATH_POINTS as f64 / 3.0) * std::f64::consts::PI
-                    / 2.0
+                let angle = (i as f64) / (num_points as f64 / 3.0) * std::f64::consts::PI / 2.0
                     + std::f64::consts::PI / 2.0;
                 (
-                    turn_origin.0 - angle.sin() * LANE_WIDTH * 3.5,
-                    turn_origin.1 + angle.cos() * LANE_WIDTH * 3.5,
+                    turn_origin.0 - angle.sin() * exit_radius,
+                    turn_origin.1 + angle.cos() * entry_radius,
                 )
             })
             .collect::<Vec<_>>(),
-        Origin::West => (0..NUM_PATH_POINTS / 3)
+        Origin::West => (0..num_points / 3)
             .map(|i| {
-                let angle = (i as f64) / (NUM_PATH_POINTS as f64 / 3.0) * std::f64::consts::PI
-                    / 2.0
+                let angle = (i as f64) / (num_points as f64 / 3.0) * std::f64::consts::PI / 2.0
                     + std::f64::consts::PI / 2.0;
                 (
-                    turn_origin.0 + angle.sin() * LANE_WIDTH * 3.5,
-                    turn_origin.1 - angle.cos() * LANE_WIDTH * 3.5,
+                    turn_origin.0 + angle.sin() * exit_radius,
+                    turn_origin.1 - angle.cos() * entry_radius,
                 )
             })
             .collect::<Vec<_>>(),
     };
     path.extend(turn_path.iter().rev().collect::<Vec<_>>());
 
-    // Direction::Left so that the turning car goes into the nearest lane
+    // Direction::Left so the lane counts out from the middle of the road
     let mut last_third_path = generate_straight_path_third(
         match origin {
             Origin::North => Origin::West,
@@ -584,75 +2262,82 @@ fn generate_left_turn_path(origin: Origin) -> Vec<(f64, f64)> {
             Origin::West => Origin::South,
         },
         Direction::Left,
+        receiving_lane,
+        num_points,
+        geometry,
     );
-    last_third_path.iter_mut().for_each(|point| match origin {
-        Origin::North => point.0 += middle.0 + LANE_WIDTH * 4.0,
-        Origin::South => point.0 -= middle.0 + LANE_WIDTH * 4.0,
-        Origin::East => point.1 += middle.1 + LANE_WIDTH * 4.0,
-        Origin::West => point.1 -= middle.1 + LANE_WIDTH * 4.0,
-    });
+    join_exit_straight(&path, &mut last_third_path);
 
     path.extend(last_third_path);
     path
 }
 
-fn generate_right_turn_path(origin: Origin) -> Vec<(f64, f64)> {
-    let middle = (WIDTH as f64 / 2.0, HEIGHT as f64 / 2.0);
+/// Turns right from the outermost lane into `receiving_lane`, counting out from the middle of the
+/// road. Like left turns, the turn is a quarter ellipse.
+fn generate_right_turn_path(
+    origin: Origin,
+    receiving_lane: usize,
+    num_points: usize,
+    geometry: &WorldGeometry,
+) -> Vec<(f64, f64)> {
+    let middle = geometry.middle();
+    let half_size = geometry.intersection_half_size();
+    let lane_width = geometry.layout.lane_width;
+    // The turn goes around the corner of the intersection from the middle of the right turn lane
+    let entry_offset = geometry.layout.lane_offset(Direction::Right, 0);
+    let entry_radius = half_size - (entry_offset as f64 + 0.5) * lane_width;
+    let exit_radius = half_size - (receiving_lane as f64 + 0.5) * lane_width;
     // Initial straight
-    let mut path = generate_straight_path_third(origin, Direction::Right);
+    let mut path = generate_straight_path_third(origin, Direction::Right, 0, num_points, geometry);
 
     // Turn
     let turn_origin = match origin {
-        Origin::North => (middle.0 - LANE_WIDTH * 3.0, middle.1 - LANE_WIDTH * 3.0),
-        Origin::South => (middle.0 + LANE_WIDTH * 3.0, middle.1 + LANE_WIDTH * 3.0),
-        Origin::East => (middle.0 + LANE_WIDTH * 3.0, middle.1 - LANE_WIDTH * 3.0),
-        Origin::West => (middle.0 - LANE_WIDTH * 3.0, middle.1 + LANE_WIDTH * 3.0),
+        Origin::North => (middle.0 - half_size, middle.1 - half_size),
+        Origin::South => (middle.0 + half_size, middle.1 + half_size),
+        Origin::East => (middle.0 + half_size, middle.1 - half_size),
+        Origin::West => (middle.0 - half_size, middle.1 + half_size),
     };
     let turn_path = match origin {
-        Origin::North => (0..NUM_PATH_POINTS / 3)
+        Origin::North => (0..num_points / 3)
             .map(|i| {
-                let angle =
-                    (i as f64) / (NUM_PATH_POINTS as f64 / 3.0) * std::f64::consts::PI / 2.0;
+                let angle = (i as f64) / (num_points as f64 / 3.0) * std::f64::consts::PI / 2.0;
                 (
-                    turn_origin.0 + angle.cos() * LANE_WIDTH / 2.0,
-                    turn_origin.1 + angle.sin() * LANE_WIDTH / 2.0,
+                    turn_origin.0 + angle.cos() * entry_radius,
+                    turn_origin.1 + angle.sin() * exit_radius,
                 )
             })
             .collect::<Vec<_>>(),
-        Origin::South => (0..NUM_PATH_POINTS / 3)
+        Origin::South => (0..num_points / 3)
             .map(|i| {
-                let angle =
-                    (i as f64) / (NUM_PATH_POINTS as f64 / 3.0) * std::f64::consts::PI / 2.0;
+                let angle = (i as f64) / (num_points as f64 / 3.0) * std::f64::consts::PI / 2.0;
                 (
-                    turn_origin.0 - angle.cos() * LANE_WIDTH / 2.0,
-                    turn_origin.1 - angle.sin() * LANE_WIDTH / 2.0,
+                    turn_origin.0 - angle.cos() * entry_radius,
+                    turn_origin.1 - angle.sin() * exit_radius,
                 )
             })
             .collect::<Vec<_>>(),
-        Origin::East => (0..NUM_PATH_POINTS / 3)
+        Origin::East => (0..num_points / 3)
             .map(|i| {
-                let angle =
-                    (i as f64) / (NUM_PATH_POINTS as f64 / 3.0) * std::f64::consts::PI / 2.0;
+                let angle = (i as f64) / (num_points as f64 / 3.0) * std::f64::consts::PI / 2.0;
                 (
-                    turn_origin.0 - angle.sin() * LANE_WIDTH / 2.0,
-                    turn_origin.1 + angle.cos() * LANE_WIDTH / 2.0,
+                    turn_origin.0 - angle.sin() * exit_radius,
+                    turn_origin.1 + angle.cos() * entry_radius,
                 )
             })
             .collect::<Vec<_>>(),
-        Origin::West => (0..NUM_PATH_POINTS / 3)
+        Origin::West => (0..num_points / 3)
             .map(|i| {
-                let angle =
-                    (i as f64) / (NUM_PATH_POINTS as f64 / 3.0) * std::f64::consts::PI / 2.0;
+                let angle = (i as f64) / (num_points as f64 / 3.0) * std::f64::consts::PI / 2.0;
                 (
-                    turn_origin.0 + angle.sin() * LANE_WIDTH / 2.0,
-                    turn_origin.1 - angle.cos() * LANE_WIDTH / 2.0,
+                    turn_origin.0 + angle.sin() * exit_radius,
+                    turn_origin.1 - angle.cos() * entry_radius,
                 )
             })
             .collect::<Vec<_>>(),
     };
     path.extend(turn_path);
 
-    // Direction::Right so that the turning car goes into the nearest lane
+    // Direction::Left takes the lane as the number of lanes out from the middle of the road
     let mut last_third_path = generate_straight_path_third(
         match origin {
             Origin::North => Origin::East,
@@ -660,49 +2345,52 @@ fn generate_right_turn_path(origin: Origin) -> Vec<(f64, f64)> {
             Origin::East => Origin::South,
             Origin::West => Origin::North,
         },
-        Direction::Right,
+        Direction::Left,
+        receiving_lane,
+        num_points,
+        geometry,
     );
-    last_third_path.iter_mut().for_each(|point| match origin {
-        Origin::North => point.0 -= middle.0 + LANE_WIDTH * 4.0,
-        Origin::South => point.0 += middle.0 + LANE_WIDTH * 4.0,
-        Origin::East => point.1 -= middle.1 + LANE_WIDTH * 4.0,
-        Origin::West => point.1 += middle.1 + LANE_WIDTH * 4.0,
-    });
+    join_exit_straight(&path, &mut last_third_path);
 
     path.extend(last_third_path);
     path
 }
 
-fn generate_straight_path(origin: Origin) -> Vec<(f64, f64)> {
-    let vertical_point_gap = (HEIGHT as f64 + CAR_WIDTH / 2.0) as f64 / NUM_PATH_POINTS as f64;
-    let horizontal_point_gap = (WIDTH as f64 + CAR_WIDTH / 2.0) as f64 / NUM_PATH_POINTS as f64;
+fn generate_straight_path(
+    origin: Origin,
+    lane: usize,
+    num_points: usize,
+    geometry: &WorldGeometry,
+) -> Vec<(f64, f64)> {
+    let vertical_point_gap = (geometry.height + geometry.spawn_setback) / num_points as f64;
+    let horizontal_point_gap = (geometry.width + geometry.spawn_setback) / num_points as f64;
 
-    let position = get_position(origin, Direction::Straight);
+    let position = get_position(origin, Direction::Straight, lane, geometry);
     match origin {
         Origin::North => {
             let mut path = Vec::new();
-            for i in 0..NUM_PATH_POINTS {
+            for i in 0..num_points {
                 path.push((position.0, position.1 + (i as f64 * vertical_point_gap)));
             }
             path
         }
         Origin::South => {
             let mut path = Vec::new();
-            for i in 0..NUM_PATH_POINTS {
+            for i in 0..num_points {
                 path.push((position.0, position.1 - (i as f64 * vertical_point_gap)));
             }
             path
         }
         Origin::East => {
             let mut path = Vec::new();
-            for i in 0..NUM_PATH_POINTS {
+            for i in 0..num_points {
                 path.push((position.0 - (i as f64 * horizontal_point_gap), position.1));
             }
             path
         }
         Origin::West => {
             let mut path = Vec::new();
-            for i in 0..NUM_PATH_POINTS {
+            for i in 0..num_points {
                 path.push((position.0 + (i as f64 * horizontal_point_gap), position.1));
             }
             path
@@ -721,3 +2409,1620 @@ fn line_intersect(line: ((f64, f64), (f64, f64)), other_line: &((f64, f64), (f64
     let d = other_line.1;
     ccw(a, c, d) != ccw(b, c, d) && ccw(a, b, c) != ccw(a, b, d)
 }
+
+/// Returns where the two line segments cross, if they do. Parallel segments never count as
+/// crossing, even if they overlap.
+fn line_intersection_point(
+    line: ((f64, f64), (f64, f64)),
+    other_line: &((f64, f64), (f64, f64)),
+) -> Option<(f64, f64)> {
+    let (a, b) = line;
+    let (c, d) = *other_line;
+    let r = (b.0 - a.0, b.1 - a.1);
+    let s = (d.0 - c.0, d.1 - c.1);
+    let denominator = r.0 * s.1 - r.1 * s.0;
+    if denominator == 0.0 {
+        return None;
+    }
+
+    // Fractions along each segment where they meet
+    let t = ((c.0 - a.0) * s.1 - (c.1 - a.1) * s.0) / denominator;
+    let u = ((c.0 - a.0) * r.1 - (c.1 - a.1) * r.0) / denominator;
+    if (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u) {
+        Some((a.0 + t * r.0, a.1 + t * r.1))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        controller_strategy::ControllerSave, geometry::IntersectionLayout,
+        traffic_light::TrafficLightState, traffic_light_controller::TrafficLightController,
+    };
+    use std::collections::HashMap;
+
+    const ORIGINS: [Origin; 4] = [Origin::North, Origin::East, Origin::South, Origin::West];
+    const DIRECTIONS: [Direction; 3] = [Direction::Left, Direction::Straight, Direction::Right];
+
+    fn rules() -> DrivingRules<'static> {
+        DrivingRules {
+            speed_limit: MAX_SPEED,
+            allow_right_on_red: false,
+            wait_for_clear_intersection: false,
+            frame: 0,
+            obstacles: &[],
+        }
+    }
+
+    /// Lights that stay however the test sets them. Movements without a state of their own use
+    /// `state`.
+    struct TestLights {
+        state: TrafficLightState,
+        movements: HashMap<(Origin, Direction), TrafficLightState>,
+        walk: bool,
+    }
+
+    impl TestLights {
+        fn new(state: TrafficLightState) -> TestLights {
+            TestLights {
+                state,
+                movements: HashMap::new(),
+                walk: false,
+            }
+        }
+
+        fn with(
+            mut self,
+            origin: Origin,
+            direction: Direction,
+            state: TrafficLightState,
+        ) -> TestLights {
+            self.movements.insert((origin, direction), state);
+            self
+        }
+    }
+
+    impl ControllerStrategy for TestLights {
+        fn advance(&mut self, _dt: std::time::Duration) {}
+
+        fn update(&mut self) {}
+
+        fn draw(&self, _context: &Context, _graphics: &mut G2d) {}
+
+        fn add_car(&mut self, _car: SimplifiedCar) {}
+
+        fn remove_car(&mut self, _car: SimplifiedCar) {}
+
+        fn is_green(&self, origin: Origin, direction: Direction) -> bool {
+            self.light_state(origin, direction) != TrafficLightState::Red
+        }
+
+        fn is_yellow(&self, origin: Origin, direction: Direction) -> bool {
+            self.light_state(origin, direction) == TrafficLightState::Yellow
+        }
+
+        fn is_walk(&self, _origin: Origin) -> bool {
+            self.walk
+        }
+
+        fn light_state(&self, origin: Origin, direction: Direction) -> TrafficLightState {
+            self.movements
+                .get(&(origin, direction))
+                .copied()
+                .unwrap_or(self.state)
+        }
+
+        fn reset(&mut self) {}
+
+        fn save(&self) -> ControllerSave {
+            TrafficLightController::new(WorldGeometry::default()).save()
+        }
+    }
+
+    /// Puts the car on its path between the points before and at `index`, heading for `index`
+    /// at `speed`
+    fn place(car: &mut Car, index: usize, speed: f64) {
+        let (from, to) = (car.path[index - 1], car.path[index]);
+        car.position = from;
+        car.rotation = (to.1 - from.1).atan2(to.0 - from.0).to_degrees();
+        car.target_rotation = car.rotation;
+        car.path_index = index;
+        car.speed = speed;
+    }
+
+    /// Updates the car on its own until it finishes
+    fn drive(car: &mut Car, traffic_light: &mut dyn ControllerStrategy) {
+        let rules = rules();
+        for _ in 0..10_000 {
+            if car.finished {
+                return;
+            }
+            car.update(&Vec::new(), traffic_light, &rules, 1.0);
+        }
+        panic!("Car {} never finished", car.id);
+    }
+
+    #[test]
+    fn intersection_exit_comes_after_entry() {
+        // A setback of 28 leaves a resampled point inside the edge tolerance band
+        for geometry in [
+            WorldGeometry::default().with_spawn_setback(28.0),
+            WorldGeometry::new(800.0, 800.0).with_spawn_setback(29.2),
+        ] {
+            for origin in ORIGINS {
+                for direction in DIRECTIONS {
+                    let car = SimplifiedCar::new(origin, direction);
+                    let path = Car::calculate_path(&car, 0, NUM_PATH_POINTS, &geometry);
+                    let (entry, exit) = Car::intersection_indices(&path, &geometry);
+                    assert!(
+                        entry < exit,
+                        "{:?} {:?}: {} >= {}",
+                        origin,
+                        direction,
+                        entry,
+                        exit
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn car_stopping_at_the_stop_line_leaves_the_queue_alone() {
+        let geometry = WorldGeometry::default();
+        let full = CarBuilder::new(0, Origin::North, Direction::Straight).build();
+        let end = full.path_index_at_intersection as f64 / (full.path.len() - 1) as f64;
+        let mut car = CarBuilder::new(1, Origin::North, Direction::Straight)
+            .span(0.0, end)
+            .build();
+        assert!(!car.reaches_intersection());
+
+        let mut traffic_light = TrafficLightController::new(geometry);
+        traffic_light.add_car(SimplifiedCar::new(Origin::North, Direction::Straight));
+        drive(&mut car, &mut traffic_light);
+        assert_eq!(
+            traffic_light.queue_length(Origin::North, Direction::Straight),
+            1
+        );
+    }
+
+    #[test]
+    fn generated_paths_are_valid() {
+        let geometry = WorldGeometry::default();
+        for origin in ORIGINS {
+            for direction in DIRECTIONS {
+                let car = SimplifiedCar::new(origin, direction);
+                let path = Car::calculate_path(&car, 0, NUM_PATH_POINTS, &geometry);
+                let expected_length = expected_path_length(direction, NUM_PATH_POINTS);
+                assert_eq!(validate_path(&path, expected_length, MAX_PATH_GAP), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn broken_paths_are_rejected() {
+        let geometry = WorldGeometry::default();
+        let car = SimplifiedCar::new(Origin::West, Direction::Straight);
+        let mut path = Car::calculate_path(&car, 0, NUM_PATH_POINTS, &geometry);
+
+        let truncated = &path[..path.len() - 1];
+        assert_eq!(
+            validate_path(truncated, NUM_PATH_POINTS, MAX_PATH_GAP),
+            Err(PathError::WrongLength(NUM_PATH_POINTS - 1))
+        );
+
+        path[10].1 += MAX_PATH_GAP * 2.0;
+        assert!(matches!(
+            validate_path(&path, NUM_PATH_POINTS, MAX_PATH_GAP),
+            Err(PathError::Gap { index: 9, .. })
+        ));
+    }
+
+    #[test]
+    fn impossible_movements_fail_to_build() {
+        let approaches = HashSet::from([Origin::North, Origin::South, Origin::East]);
+        let build = |origin, direction, lane| {
+            CarBuilder::new(0, origin, direction)
+                .lane(lane)
+                .approaches(approaches.clone())
+                .try_build()
+                .err()
+        };
+        assert_eq!(
+            build(Origin::West, Direction::Straight, 0),
+            Some(PathError::Movement(MovementError::MissingOrigin(
+                Origin::West
+            )))
+        );
+        assert_eq!(
+            build(Origin::South, Direction::Left, 0),
+            Some(PathError::Movement(MovementError::MissingExit(
+                Origin::West
+            )))
+        );
+        assert_eq!(
+            build(Origin::North, Direction::Left, 1),
+            Some(PathError::Movement(MovementError::InvalidLane(1)))
+        );
+        assert_eq!(build(Origin::North, Direction::Straight, 0), None);
+
+        let lanes = WorldGeometry::default().layout.lanes();
+        assert_eq!(
+            CarBuilder::new(0, Origin::North, Direction::Left)
+                .receiving_lane(lanes)
+                .try_build()
+                .err(),
+            Some(PathError::Movement(MovementError::InvalidReceivingLane(
+                lanes
+            )))
+        );
+    }
+
+    #[test]
+    fn cars_that_cant_speed_up_or_slow_down_fail_to_build() {
+        let build = |builder: CarBuilder| builder.try_build().err();
+        let car = || CarBuilder::new(0, Origin::North, Direction::Straight);
+        assert_eq!(
+            build(car().acceleration(0.0)),
+            Some(PathError::NonPositiveRate(0.0))
+        );
+        assert_eq!(
+            build(car().deceleration(-DECELERATION)),
+            Some(PathError::NonPositiveRate(-DECELERATION))
+        );
+        assert_eq!(
+            build(car().emergency_deceleration(0.0)),
+            Some(PathError::NonPositiveRate(0.0))
+        );
+        assert!(build(car()).is_none());
+    }
+
+    #[test]
+    fn cars_build_on_a_bigger_canvas() {
+        let geometry = WorldGeometry::new(WIDTH as f64 * 3.0, WIDTH as f64 * 3.0);
+        for origin in ORIGINS {
+            for direction in DIRECTIONS {
+                let car = CarBuilder::new(0, origin, direction)
+                    .geometry(geometry)
+                    .try_build();
+                assert!(car.is_ok(), "{:?} {:?}: {:?}", origin, direction, car.err());
+            }
+        }
+    }
+
+    #[test]
+    fn fast_car_commits_to_a_yellow_and_slow_car_stops() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Yellow);
+        let rules = rules();
+
+        let mut fast = CarBuilder::new(0, Origin::North, Direction::Straight).build();
+        let index = fast.path_index_at_intersection;
+        place(&mut fast, index, MAX_SPEED);
+        assert!(fast.stopping_distance() >= fast.distance_to_stop_line());
+        fast.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+        assert!(fast.through_intersection());
+        drive(&mut fast, &mut traffic_light);
+
+        let mut slow = CarBuilder::new(1, Origin::North, Direction::Straight).build();
+        place(&mut slow, index, 1.0);
+        for _ in 0..200 {
+            slow.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+        }
+        assert!(!slow.through_intersection());
+        assert_eq!(slow.speed(), 0.0);
+        assert!(slow.distance_to_stop_line() >= 0.0);
+    }
+
+    #[test]
+    fn right_turn_waits_for_pedestrians() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        traffic_light.walk = true;
+        let rules = rules();
+        let mut car = CarBuilder::new(0, Origin::North, Direction::Right).build();
+        assert!(car.place_behind_stop_line(DISTANCE_THRESHOLD));
+        for _ in 0..200 {
+            car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+        }
+        assert!(!car.through_intersection());
+        assert_eq!(car.speed(), 0.0);
+
+        traffic_light.walk = false;
+        drive(&mut car, &mut traffic_light);
+    }
+
+    #[test]
+    fn half_frames_land_where_a_whole_frame_does() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        let rules = rules();
+        let mut whole = CarBuilder::new(0, Origin::West, Direction::Straight).build();
+        place(&mut whole, 3, MAX_SPEED);
+        let mut halves = whole.clone();
+
+        whole.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+        halves.update(&Vec::new(), &mut traffic_light, &rules, 0.5);
+        halves.update(&Vec::new(), &mut traffic_light, &rules, 0.5);
+        let (x, y) = whole.position();
+        assert!((halves.position().0 - x).hypot(halves.position().1 - y) < 1e-9);
+    }
+
+    #[test]
+    fn accessors_follow_the_car_along_its_path() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        let rules = rules();
+        let mut car = CarBuilder::new(0, Origin::East, Direction::Straight).build();
+        assert_eq!(car.path().len(), NUM_PATH_POINTS);
+        assert_eq!(car.path_index(), 1);
+        assert_eq!(car.speed(), 0.0);
+
+        for _ in 0..100 {
+            car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+        }
+        assert!(car.path_index() > 1);
+        assert_eq!(car.speed(), MAX_SPEED);
+        // The car is between the point it last passed and the one it's driving towards
+        let (from, to) = (
+            car.path()[car.path_index() - 1],
+            car.path()[car.path_index()],
+        );
+        assert!(car.position().0 <= from.0 && car.position().0 >= to.0);
+    }
+
+    #[test]
+    fn right_on_red_goes_only_when_cross_traffic_is_clear() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Red);
+        let rules = DrivingRules {
+            allow_right_on_red: true,
+            ..rules()
+        };
+        let waiting = || {
+            let mut car = CarBuilder::new(0, Origin::North, Direction::Right).build();
+            assert!(car.place_behind_stop_line(DISTANCE_THRESHOLD));
+            car
+        };
+
+        // An East straight car in the intersection is heading for the same exit
+        let mut cross = CarBuilder::new(1, Origin::East, Direction::Straight).build();
+        let index = cross.path_index_at_intersection + 1;
+        place(&mut cross, index, 0.0);
+        cross.through_intersection = true;
+        let mut blocked = waiting();
+        for _ in 0..200 {
+            blocked.update(&[cross.clone()], &mut traffic_light, &rules, 1.0);
+        }
+        assert!(!blocked.through_intersection());
+
+        let mut allowed = waiting();
+        for _ in 0..200 {
+            allowed.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+        }
+        assert!(allowed.through_intersection());
+    }
+
+    #[test]
+    fn low_acceleration_cars_take_longer_to_get_up_to_speed() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        let rules = rules();
+        let mut frames_to_speed = |builder: CarBuilder| {
+            let mut car = builder.build();
+            let mut frames = 0;
+            while car.speed() < MAX_SPEED / 2.0 {
+                car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+                frames += 1;
+                assert!(!car.finished);
+            }
+            frames
+        };
+        let regular = frames_to_speed(CarBuilder::new(0, Origin::South, Direction::Straight));
+        let sluggish = frames_to_speed(
+            CarBuilder::new(1, Origin::South, Direction::Straight).acceleration(ACCELERATION / 3.0),
+        );
+        assert!(sluggish > regular, "{} <= {}", sluggish, regular);
+    }
+
+    #[test]
+    fn permissive_left_waits_for_a_gap_but_protected_left_goes() {
+        let rules = rules();
+        let mut oncoming = CarBuilder::new(1, Origin::South, Direction::Straight).build();
+        assert!(oncoming.place_behind_stop_line(CAR_WIDTH));
+        let waiting = || {
+            let mut car = CarBuilder::new(0, Origin::North, Direction::Left).build();
+            assert!(car.place_behind_stop_line(DISTANCE_THRESHOLD));
+            car
+        };
+
+        let mut permissive = TestLights::new(TrafficLightState::Green).with(
+            Origin::North,
+            Direction::Left,
+            TrafficLightState::Red,
+        );
+        let mut car = waiting();
+        for _ in 0..200 {
+            car.update(&[oncoming.clone()], &mut permissive, &rules, 1.0);
+        }
+        assert!(!car.through_intersection());
+        // Once the oncoming car is gone there's a gap
+        drive(&mut car, &mut permissive);
+
+        let mut protected = TestLights::new(TrafficLightState::Red).with(
+            Origin::North,
+            Direction::Left,
+            TrafficLightState::Green,
+        );
+        let mut car = waiting();
+        for _ in 0..200 {
+            car.update(&[oncoming.clone()], &mut protected, &rules, 1.0);
+        }
+        assert!(car.through_intersection());
+    }
+
+    #[test]
+    fn cars_spawn_just_off_a_rectangular_canvas() {
+        let geometry = WorldGeometry::new(1000.0, 800.0);
+        let setback = geometry.spawn_setback;
+        for origin in ORIGINS {
+            for direction in DIRECTIONS {
+                let car = CarBuilder::new(0, origin, direction)
+                    .geometry(geometry)
+                    .build();
+                let (x, y) = car.position();
+                let (along, across, size) = match origin {
+                    Origin::North => (y, x, geometry.width),
+                    Origin::South => (geometry.height - y, x, geometry.width),
+                    Origin::East => (geometry.width - x, y, geometry.height),
+                    Origin::West => (x, y, geometry.height),
+                };
+                assert_eq!(along, -setback, "{:?} {:?}", origin, direction);
+                assert!(
+                    across > 0.0 && across < size,
+                    "{:?} {:?}",
+                    origin,
+                    direction
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn blocked_car_changes_lanes_when_there_is_room() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        let rules = rules();
+        let queued = |id, lane, distance| {
+            let mut car = CarBuilder::new(id, Origin::North, Direction::Straight)
+                .lane(lane)
+                .build();
+            assert!(car.place_behind_stop_line(distance));
+            car
+        };
+        // Stalled in lane 0, so it never moves
+        let stalled = queued(0, 0, 200.0);
+        let behind = 200.0 + CAR_WIDTH + FOLLOWING_GAP * 2.0;
+
+        let mut car = queued(1, 0, behind);
+        for _ in 0..300 {
+            car.update(
+                std::slice::from_ref(&stalled),
+                &mut traffic_light,
+                &rules,
+                1.0,
+            );
+        }
+        assert_eq!(car.lane(), 1);
+
+        // Next to where the car stops behind the stalled one
+        let beside = queued(2, 1, 200.0 + CAR_WIDTH + FOLLOWING_GAP);
+        let mut car = queued(1, 0, behind);
+        for _ in 0..300 {
+            car.update(
+                &[stalled.clone(), beside.clone()],
+                &mut traffic_light,
+                &rules,
+                1.0,
+            );
+        }
+        assert_eq!(car.lane(), 0);
+    }
+
+    #[test]
+    fn intersection_index_lands_at_the_intersection_for_any_point_count() {
+        let geometry = WorldGeometry::default();
+        for num_points in [12, 25, 60] {
+            for origin in ORIGINS {
+                for direction in DIRECTIONS {
+                    let car = SimplifiedCar::new(origin, direction);
+                    let path = Car::calculate_path(&car, 0, num_points, &geometry);
+                    let (entry, _) = Car::intersection_indices(&path, &geometry);
+                    let gap = (path[entry].0 - path[entry - 1].0)
+                        .hypot(path[entry].1 - path[entry - 1].1);
+                    // How far past the edge of the intersection the point is
+                    let (line_point, _) = stop_line(&geometry, origin);
+                    let heading = approach_heading(origin);
+                    let past = (path[entry].0 - line_point.0) * heading.0
+                        + (path[entry].1 - line_point.1) * heading.1;
+                    assert!(
+                        past >= -INTERSECTION_EDGE_TOLERANCE && past < gap,
+                        "{} points, {:?} {:?}: {} past the edge",
+                        num_points,
+                        origin,
+                        direction,
+                        past
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn time_to_intersection_matches_driving_there() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        let rules = rules();
+        for speed in [0.0, MAX_SPEED / 2.0, MAX_SPEED] {
+            let mut car = CarBuilder::new(0, Origin::West, Direction::Straight).build();
+            place(&mut car, 1, speed);
+            let estimate = car.frames_to_intersection().unwrap();
+            let mut frames = 0;
+            while !car.past_intersection() {
+                car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+                frames += 1;
+            }
+            // The estimate treats the speed as changing smoothly rather than once a frame
+            assert!(
+                (frames as f64 - estimate).abs() <= 2.0,
+                "from {}: {} frames, estimated {}",
+                speed,
+                frames,
+                estimate
+            );
+        }
+    }
+
+    #[test]
+    fn straight_car_does_not_turn_as_it_finishes() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        let rules = rules();
+        let mut car = CarBuilder::new(0, Origin::North, Direction::Straight).build();
+        while !car.finished {
+            let rotation = car.rotation();
+            car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+            assert!(
+                (car.rotation() - rotation).abs() < 1e-9,
+                "turned from {} to {} on frame {}",
+                rotation,
+                car.rotation(),
+                car.frames()
+            );
+        }
+        assert_eq!(car.rotation(), 90.0);
+    }
+
+    #[test]
+    fn left_turns_slow_down_through_the_curve() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        let rules = rules();
+        let mut car = CarBuilder::new(0, Origin::West, Direction::Left).build();
+        let mut fastest_in_turn = 0.0_f64;
+        while !car.finished {
+            car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+            if car.path_index() > car.path_index_at_intersection && !car.cleared_intersection() {
+                fastest_in_turn = fastest_in_turn.max(car.speed());
+            }
+        }
+        assert!(fastest_in_turn > 0.0);
+        assert!(fastest_in_turn < MAX_SPEED, "{}", fastest_in_turn);
+    }
+
+    #[test]
+    fn car_ahead_is_the_closest_one_in_the_lane() {
+        let queued = |id, direction, distance| {
+            let mut car = CarBuilder::new(id, Origin::East, direction).build();
+            assert!(car.place_behind_stop_line(distance));
+            car
+        };
+        let car = queued(0, Direction::Straight, 250.0);
+        let cars = vec![
+            car.clone(),
+            queued(1, Direction::Straight, 0.0),
+            queued(2, Direction::Straight, 100.0),
+            // Behind, and in the lane next to it
+            queued(3, Direction::Straight, 350.0),
+            queued(4, Direction::Left, 200.0),
+        ];
+        let (id, distance, speed) = car.car_ahead(&cars).unwrap();
+        assert_eq!(id, 2);
+        assert!((distance - 150.0).abs() < 1e-9);
+        assert_eq!(speed, 0.0);
+        assert_eq!(cars[1].car_ahead(&cars), None);
+    }
+
+    #[test]
+    fn degenerate_paths_are_errors_not_panics() {
+        let geometry = WorldGeometry::default();
+        assert_eq!(Car::intersection_indices(&[], &geometry), (0, 0));
+        assert_eq!(
+            validate_path(&[], NUM_PATH_POINTS, MAX_PATH_GAP),
+            Err(PathError::WrongLength(0))
+        );
+
+        let build = |builder: CarBuilder| builder.try_build().err();
+        assert!(
+            build(CarBuilder::new(0, Origin::North, Direction::Straight).num_path_points(0))
+                .is_some()
+        );
+        assert!(
+            build(CarBuilder::new(0, Origin::North, Direction::Left).num_path_points(2)).is_some()
+        );
+        assert!(matches!(
+            build(CarBuilder::new(0, Origin::North, Direction::Straight).span(0.5, 0.5)),
+            Some(PathError::TooShort(1))
+        ));
+    }
+
+    #[test]
+    fn should_proceed_stops_for_each_gate() {
+        let rules = rules();
+        let green = TestLights::new(TrafficLightState::Green);
+        let at_line = |id, origin, direction| {
+            let mut car = CarBuilder::new(id, origin, direction).build();
+            assert!(car.place_behind_stop_line(DISTANCE_THRESHOLD));
+            car
+        };
+
+        let right = at_line(0, Origin::North, Direction::Right);
+        assert!(right.should_proceed(&Vec::new(), &green, &rules));
+        let walk = TestLights {
+            walk: true,
+            ..TestLights::new(TrafficLightState::Green)
+        };
+        assert!(!right.should_proceed(&Vec::new(), &walk, &rules));
+
+        let left = at_line(1, Origin::North, Direction::Left);
+        let permissive = TestLights::new(TrafficLightState::Green).with(
+            Origin::North,
+            Direction::Left,
+            TrafficLightState::Red,
+        );
+        assert!(left.should_proceed(&Vec::new(), &permissive, &rules));
+        let oncoming = vec![at_line(2, Origin::South, Direction::Straight)];
+        assert!(!left.should_proceed(&oncoming, &permissive, &rules));
+
+        let mut straight = CarBuilder::new(3, Origin::North, Direction::Straight).build();
+        assert!(straight.place_behind_stop_line(CAR_WIDTH));
+        assert!(straight.should_proceed(&Vec::new(), &green, &rules));
+        let ahead = vec![at_line(4, Origin::North, Direction::Straight)];
+        assert!(!straight.should_proceed(&ahead, &green, &rules));
+        let obstacles = [Obstacle::rect(straight.front(), 10.0, 10.0)];
+        let blocked = DrivingRules {
+            obstacles: &obstacles,
+            ..rules
+        };
+        assert!(!straight.should_proceed(&Vec::new(), &green, &blocked));
+    }
+
+    #[test]
+    fn low_jerk_limit_eases_into_accelerating() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        let rules = rules();
+        let max_jerk = 0.01;
+        let mut car = CarBuilder::new(0, Origin::South, Direction::Straight)
+            .max_jerk(max_jerk)
+            .build();
+        let mut speeds = vec![car.speed()];
+        while car.speed() < MAX_SPEED / 2.0 {
+            car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+            speeds.push(car.speed());
+        }
+
+        let accelerations = speeds.windows(2).map(|s| s[1] - s[0]).collect::<Vec<_>>();
+        assert!(accelerations.iter().all(|&a| a >= 0.0));
+        assert!(accelerations[0] <= max_jerk + 1e-9);
+        for a in accelerations.windows(2) {
+            assert!((a[1] - a[0]).abs() <= max_jerk + 1e-9, "{:?}", a);
+        }
+    }
+
+    #[test]
+    fn full_speed_car_stops_before_a_red() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Red);
+        let rules = rules();
+        for origin in ORIGINS {
+            let mut car = CarBuilder::new(0, origin, Direction::Straight).build();
+            place(&mut car, 3, MAX_SPEED);
+            assert!(car.stopping_distance() < car.distance_to_stop_line());
+            for _ in 0..300 {
+                car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+            }
+            assert_eq!(car.speed(), 0.0);
+            assert!(!car.through_intersection());
+            let distance = car.distance_to_stop_line();
+            assert!(
+                (0.0..=DISTANCE_THRESHOLD).contains(&distance),
+                "{:?}: {}",
+                origin,
+                distance
+            );
+        }
+    }
+
+    #[test]
+    fn predicted_pose_matches_driving_a_straight_car() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        let rules = rules();
+        for origin in ORIGINS {
+            let mut car = CarBuilder::new(0, origin, Direction::Straight).build();
+            place(&mut car, 3, MAX_SPEED);
+            let frames = 60;
+            let (position, rotation) = car.predict_pose(frames);
+            for _ in 0..frames {
+                car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+            }
+            let distance = (position.0 - car.position().0).hypot(position.1 - car.position().1);
+            assert!(distance < 1e-6, "{:?}: {}", origin, distance);
+            assert!((rotation - car.rotation).abs() < 1e-6, "{:?}", origin);
+        }
+    }
+
+    #[test]
+    fn spawns_on_a_two_straight_one_left_layout_do_not_overlap() {
+        let geometry = WorldGeometry::default().with_layout(IntersectionLayout {
+            left_lanes: 1,
+            straight_lanes: 2,
+            right_lanes: 0,
+            ..IntersectionLayout::default()
+        });
+        let mut cars = Vec::new();
+        for origin in ORIGINS {
+            for (direction, lane) in [
+                (Direction::Left, 0),
+                (Direction::Straight, 0),
+                (Direction::Straight, 1),
+            ] {
+                let car = CarBuilder::new(cars.len(), origin, direction)
+                    .lane(lane)
+                    .geometry(geometry)
+                    .build();
+                cars.push(car);
+            }
+        }
+        for (i, car) in cars.iter().enumerate() {
+            for other in &cars[i + 1..] {
+                assert!(
+                    !car.overlaps(other),
+                    "{:?} {:?} lane {} and {:?} {:?} lane {}",
+                    car.origin,
+                    car.direction,
+                    car.lane(),
+                    other.origin,
+                    other.direction,
+                    other.lane()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn crossing_cars_arriving_together_take_turns() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        let mut cars = [Origin::North, Origin::East]
+            .into_iter()
+            .enumerate()
+            .map(|(id, origin)| {
+                let mut car = CarBuilder::new(id, origin, Direction::Straight).build();
+                assert!(car.place_behind_stop_line(50.0));
+                car
+            })
+            .collect::<Vec<_>>();
+        for frame in 0..2000 {
+            if cars.iter().all(|car| car.finished) {
+                break;
+            }
+            let rules = DrivingRules { frame, ..rules() };
+            let cars_clone = cars.clone();
+            for car in cars.iter_mut().filter(|car| !car.finished) {
+                car.update(&cars_clone, &mut traffic_light, &rules, 1.0);
+            }
+            assert!(!cars[0].overlaps(&cars[1]), "frame {}", frame);
+            // They get there together, so the lower id goes first
+            assert!(
+                !cars[1].through_intersection || cars[0].cleared_intersection(),
+                "frame {}",
+                frame
+            );
+        }
+        assert!(cars.iter().all(|car| car.finished));
+        assert_eq!(cars[0].intersection_arrival, cars[1].intersection_arrival);
+    }
+
+    #[test]
+    fn very_fast_car_still_follows_every_point() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        for direction in DIRECTIONS {
+            let car = CarBuilder::new(0, Origin::North, direction).build();
+            // Far more than the points are apart, so most updates pass several of them
+            let speed = 3.0
+                * car
+                    .path
+                    .windows(2)
+                    .map(|pair| (pair[1].0 - pair[0].0).hypot(pair[1].1 - pair[0].1))
+                    .fold(0.0, f64::max);
+            let rules = DrivingRules {
+                speed_limit: speed,
+                ..rules()
+            };
+            let mut car = CarBuilder::new(0, Origin::North, direction)
+                .max_speed(speed)
+                .build();
+            place(&mut car, 1, speed);
+            let mut path_index = car.path_index;
+            for _ in 0..100 {
+                if car.finished {
+                    break;
+                }
+                car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+                assert!(car.path_index >= path_index);
+                path_index = car.path_index;
+
+                // Turns lag behind their points at this speed, but straight cars stay on the line
+                // between the last point they passed and the next one
+                if direction == Direction::Straight {
+                    let (from, to) = (car.path[path_index - 1], car.path[path_index]);
+                    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+                    let off_path =
+                        ((car.position.0 - from.0) * dy - (car.position.1 - from.1) * dx).abs()
+                            / dx.hypot(dy);
+                    assert!(off_path < 1.0, "{}", off_path);
+                }
+            }
+            assert!(car.finished, "{:?}", direction);
+        }
+    }
+
+    #[test]
+    fn line_intersection_point_finds_crossings_but_not_parallels() {
+        let line = ((0.0, 0.0), (4.0, 4.0));
+        assert_eq!(
+            line_intersection_point(line, &((0.0, 4.0), (4.0, 0.0))),
+            Some((2.0, 2.0))
+        );
+        // Touching at an end still counts
+        assert_eq!(
+            line_intersection_point(line, &((4.0, 4.0), (8.0, 0.0))),
+            Some((4.0, 4.0))
+        );
+        // The lines would cross, but past the end of the segments
+        assert_eq!(
+            line_intersection_point(line, &((5.0, 0.0), (6.0, -1.0))),
+            None
+        );
+        // Parallel, and overlapping
+        assert_eq!(
+            line_intersection_point(line, &((1.0, 0.0), (5.0, 4.0))),
+            None
+        );
+        assert_eq!(
+            line_intersection_point(line, &((1.0, 1.0), (3.0, 3.0))),
+            None
+        );
+    }
+
+    #[test]
+    fn collision_points_are_where_the_outlines_cross() {
+        let car = CarBuilder::new(0, Origin::North, Direction::Straight).build();
+        let mut other = car.clone();
+        other.id = 1;
+        other.position.1 += 2.0 * car.length();
+        assert!(car.collision_points(&other).is_empty());
+
+        // Moved over and back by half a car, the outlines cross twice, on the first car
+        other.position = car.position;
+        other.position.0 += CAR_WIDTH / 2.0;
+        other.position.1 += car.length() / 2.0;
+        assert!(car.intersects_rect(other.vertices()));
+        let points = car.collision_points(&other);
+        assert_eq!(points.len(), 2);
+        for point in points {
+            assert!(
+                (point.0 - car.position.0).abs() <= CAR_WIDTH
+                    && (point.1 - car.position.1).abs() <= car.length()
+            );
+        }
+    }
+
+    #[test]
+    fn car_stops_just_behind_a_stopped_bus() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        let rules = rules();
+        let mut bus = CarBuilder::new(0, Origin::North, Direction::Straight)
+            .kind(CarKind::Bus)
+            .build();
+        place(&mut bus, 4, 0.0);
+        let mut car = CarBuilder::new(1, Origin::North, Direction::Straight).build();
+        place(&mut car, 1, MAX_SPEED);
+        assert!(bus.length() > car.length());
+
+        // The bus doesn't move, so it stays where it was
+        let cars = vec![bus.clone(), car.clone()];
+        for _ in 0..600 {
+            car.update(&cars, &mut traffic_light, &rules, 1.0);
+            assert!(!car.overlaps(&bus));
+        }
+        assert_eq!(car.speed(), 0.0);
+        let gap = car.leader_gap(&[bus.clone()]).unwrap().0;
+        assert!((0.0..=2.0 * FOLLOWING_GAP).contains(&gap), "{}", gap);
+    }
+
+    #[test]
+    fn left_turn_takes_a_gap_behind_a_right_turn_but_not_a_straight() {
+        let rules = rules();
+        let mut permissive = TestLights::new(TrafficLightState::Green).with(
+            Origin::North,
+            Direction::Left,
+            TrafficLightState::Red,
+        );
+        // Too close to turn in front of a straight car, but far enough for a right turn
+        let distance = 3.0 * CAR_WIDTH;
+        let mut goes_in_front_of = |direction| {
+            let mut oncoming = CarBuilder::new(1, Origin::South, direction).build();
+            assert!(oncoming.place_behind_stop_line(0.0));
+            let behind = distance - oncoming.distance_to_intersection();
+            assert!(oncoming.place_behind_stop_line(behind));
+            assert!((oncoming.distance_to_intersection() - distance).abs() < 1.0);
+
+            let mut car = CarBuilder::new(0, Origin::North, Direction::Left).build();
+            assert!(car.place_behind_stop_line(DISTANCE_THRESHOLD));
+            for _ in 0..200 {
+                car.update(&[oncoming.clone()], &mut permissive, &rules, 1.0);
+            }
+            car.through_intersection()
+        };
+        assert!(goes_in_front_of(Direction::Right));
+        assert!(!goes_in_front_of(Direction::Straight));
+    }
+
+    #[test]
+    fn front_stops_at_the_stop_line_from_any_speed() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Red);
+        let rules = rules();
+        for speed in [0.25, 0.5, 0.75, 1.0].map(|fraction| fraction * MAX_SPEED) {
+            let mut car = CarBuilder::new(0, Origin::West, Direction::Straight)
+                .max_speed(speed)
+                .build();
+            place(&mut car, 2, speed);
+            for _ in 0..600 {
+                car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+            }
+            // Cars start braking so they stop up to DISTANCE_THRESHOLD short of the line
+            assert_eq!(car.speed(), 0.0);
+            let distance = car.distance_to_stop_line();
+            assert!(
+                (0.0..=DISTANCE_THRESHOLD).contains(&distance),
+                "{}: {}",
+                speed,
+                distance
+            );
+        }
+    }
+
+    #[test]
+    fn bus_dwells_at_its_stop_and_the_car_behind_waits() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        let (stop_distance, dwell_frames) = (50.0, 90.0);
+        let mut bus = CarBuilder::new(0, Origin::North, Direction::Straight)
+            .kind(CarKind::Bus)
+            .bus_stop(stop_distance, dwell_frames)
+            .build();
+        assert!(bus.place_behind_stop_line(150.0));
+        let mut car = CarBuilder::new(1, Origin::North, Direction::Straight).build();
+        assert!(car.place_behind_stop_line(150.0 + bus.length() + 2.0 * FOLLOWING_GAP));
+        let mut cars = vec![bus, car];
+
+        let (mut bus_at_stop, mut car_waited) = (0_usize, false);
+        for frame in 0..2000 {
+            let rules = DrivingRules { frame, ..rules() };
+            let cars_clone = cars.clone();
+            for car in cars.iter_mut().filter(|car| !car.finished) {
+                car.update(&cars_clone, &mut traffic_light, &rules, 1.0);
+            }
+            let [bus, car] = &cars[..] else {
+                unreachable!()
+            };
+            assert!(bus.finished || car.finished || !bus.overlaps(car));
+            if bus.speed() == 0.0
+                && (bus.distance_to_stop_line() - stop_distance).abs() <= DISTANCE_THRESHOLD
+            {
+                bus_at_stop += 1;
+                car_waited |= car.speed() == 0.0;
+            }
+        }
+        assert!(cars.iter().all(|car| car.finished));
+        assert!(
+            bus_at_stop.abs_diff(dwell_frames as usize) <= 1,
+            "{}",
+            bus_at_stop
+        );
+        assert!(car_waited);
+    }
+
+    #[test]
+    fn straight_car_travels_the_length_of_its_path() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        for origin in ORIGINS {
+            let mut car = CarBuilder::new(0, origin, Direction::Straight).build();
+            assert_eq!(car.position(), car.path[0]);
+            let path_length = car.path[..=car.path_end]
+                .windows(2)
+                .map(|pair| (pair[1].0 - pair[0].0).hypot(pair[1].1 - pair[0].1))
+                .sum::<f64>();
+            drive(&mut car, &mut traffic_light);
+            // It finishes within a frame's drive of the last point
+            assert!(
+                (car.distance_traveled() - path_length).abs() <= MAX_SPEED,
+                "{:?}: {} and {}",
+                origin,
+                car.distance_traveled(),
+                path_length
+            );
+        }
+    }
+
+    #[test]
+    fn car_that_stops_twice_counts_two_stops() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Red);
+        let rules = rules();
+        let mut car = CarBuilder::new(0, Origin::North, Direction::Straight).build();
+        place(&mut car, 1, MAX_SPEED);
+        let mut leader = CarBuilder::new(1, Origin::North, Direction::Straight).build();
+        assert!(leader.place_behind_stop_line(150.0));
+
+        // Behind the car ahead, then at the light once it's gone
+        for cars in [vec![leader], Vec::new()] {
+            for _ in 0..600 {
+                car.update(&cars, &mut traffic_light, &rules, 1.0);
+            }
+            assert_eq!(car.speed(), 0.0);
+        }
+        assert!(car.distance_to_stop_line() <= DISTANCE_THRESHOLD);
+        assert_eq!(car.stop_count(), 2);
+    }
+
+    #[test]
+    fn queue_pulls_away_one_reaction_time_per_car() {
+        let reaction_frames = 20.0;
+        let mut traffic_light = TestLights::new(TrafficLightState::Red);
+        let mut cars: Vec<Car> = (0..3)
+            .map(|id| {
+                let mut car = CarBuilder::new(id, Origin::North, Direction::Straight)
+                    .reaction_frames(reaction_frames)
+                    .build();
+                let distance = id as f64 * (car.length() + FOLLOWING_GAP);
+                assert!(car.place_behind_stop_line(distance + 1.0));
+                car
+            })
+            .collect();
+
+        let mut started = [None; 3];
+        for frame in 0..600 {
+            // Soon enough that the cars behind don't give up and change lanes
+            if frame == 10 {
+                traffic_light.state = TrafficLightState::Green;
+            }
+            let rules = DrivingRules { frame, ..rules() };
+            let cars_clone = cars.clone();
+            for car in cars.iter_mut().filter(|car| !car.finished) {
+                car.update(&cars_clone, &mut traffic_light, &rules, 1.0);
+            }
+            for (start, car) in started.iter_mut().zip(&cars) {
+                if frame >= 10 && start.is_none() && car.speed() > 0.0 {
+                    *start = Some(frame);
+                }
+            }
+        }
+        let [Some(first), Some(_), Some(third)] = started else {
+            panic!("Not every car pulled away: {:?}", started);
+        };
+        assert!(first >= 10 + reaction_frames as usize, "{}", first);
+        assert!(
+            (third - first).abs_diff(2 * reaction_frames as usize) <= 2,
+            "{:?}",
+            started
+        );
+    }
+
+    #[test]
+    fn label_names_the_car_and_sits_above_it_at_any_rotation() {
+        let mut car = CarBuilder::new(7, Origin::North, Direction::Left).build();
+        place(&mut car, 1, 2.345);
+        for rotation in (0..360).step_by(15) {
+            car.rotation = rotation as f64;
+            let (label, (x, y)) = car.label();
+            assert_eq!(label, "7 2.3");
+            for (vx, vy) in car.vertices() {
+                assert!(
+                    y < vy && x <= vx,
+                    "{} {:?} {:?}",
+                    rotation,
+                    (x, y),
+                    (vx, vy)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn car_leaving_before_the_intersection_ignores_the_light() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Red);
+        let mut car = CarBuilder::new(0, Origin::North, Direction::Straight)
+            .span(0.1, 0.3)
+            .build();
+        let last_index = (car.path.len() - 1) as f64;
+        let (start, end) = (
+            (0.1 * last_index).round() as usize,
+            (0.3 * last_index).round() as usize,
+        );
+        assert!(end < car.path_index_at_intersection);
+        assert_eq!(car.position(), car.path[start]);
+        // The world only tells the controller about cars that reach the intersection
+        assert!(!car.reaches_intersection());
+
+        drive(&mut car, &mut traffic_light);
+        let (x, y) = car.position();
+        assert!((x - car.path[end].0).hypot(y - car.path[end].1) <= DISTANCE_THRESHOLD);
+        assert!(!car.through_intersection());
+        assert_eq!(car.stop_count(), 0);
+    }
+
+    #[test]
+    fn follower_keeps_pace_with_a_moving_leader_but_brakes_for_a_stopped_one() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        let cruising = |id, distance| {
+            let mut car = CarBuilder::new(id, Origin::North, Direction::Straight).build();
+            assert!(car.place_behind_stop_line(distance));
+            car.speed = MAX_SPEED;
+            car.stopped = false;
+            car
+        };
+
+        // One gap apart at full speed
+        let mut leader = cruising(0, 300.0);
+        let mut follower = cruising(1, 300.0 + leader.length() + FOLLOWING_GAP);
+        for frame in 0..30 {
+            let rules = DrivingRules { frame, ..rules() };
+            let cars = vec![leader.clone(), follower.clone()];
+            leader.update(&cars, &mut traffic_light, &rules, 1.0);
+            follower.update(&cars, &mut traffic_light, &rules, 1.0);
+            assert_eq!(follower.speed(), MAX_SPEED, "frame {}", frame);
+        }
+
+        // The same gap to a car that isn't moving is nowhere near enough
+        let mut stopped = cruising(0, 300.0);
+        stopped.speed = 0.0;
+        let mut follower = cruising(1, 300.0 + stopped.length() + FOLLOWING_GAP);
+        let rules = rules();
+        follower.update(&[stopped.clone()], &mut traffic_light, &rules, 1.0);
+        assert!(follower.speed() < MAX_SPEED);
+        for _ in 0..300 {
+            follower.update(&[stopped.clone()], &mut traffic_light, &rules, 1.0);
+            assert!(!follower.overlaps(&stopped));
+        }
+        assert_eq!(follower.speed(), 0.0);
+    }
+
+    #[test]
+    fn channelized_right_ignores_red_but_yields_to_close_cross_traffic() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Red);
+        let geometry = WorldGeometry::default().with_layout(IntersectionLayout {
+            channelized_right: true,
+            ..IntersectionLayout::default()
+        });
+        let slip_lane_car = || {
+            CarBuilder::new(0, Origin::North, Direction::Right)
+                .geometry(geometry)
+                .build()
+        };
+
+        let mut free = slip_lane_car();
+        assert!(!free.reaches_intersection());
+        drive(&mut free, &mut traffic_light);
+        assert_eq!(free.stop_count(), 0);
+
+        // An East straight car just short of the intersection is heading for the same exit
+        let mut cross = CarBuilder::new(1, Origin::East, Direction::Straight)
+            .geometry(geometry)
+            .build();
+        assert!(cross.place_behind_stop_line(CAR_WIDTH));
+        cross.speed = MAX_SPEED;
+        cross.stopped = false;
+        let mut yielding = slip_lane_car();
+        let rules = rules();
+        for _ in 0..600 {
+            yielding.update(&[cross.clone()], &mut traffic_light, &rules, 1.0);
+        }
+        assert_eq!(yielding.speed(), 0.0);
+        assert!(!yielding.through_intersection());
+
+        // Once it's gone the slip lane car merges
+        drive(&mut yielding, &mut traffic_light);
+    }
+
+    #[test]
+    fn left_turn_path_points_are_evenly_spaced() {
+        for origin in ORIGINS {
+            let car = CarBuilder::new(0, origin, Direction::Left).build();
+            let gaps: Vec<f64> = car
+                .path
+                .windows(2)
+                .map(|points| (points[1].0 - points[0].0).hypot(points[1].1 - points[0].1))
+                .collect();
+            let mean = gaps.iter().sum::<f64>() / gaps.len() as f64;
+            // Points on the arc cut its corners a little, so they're slightly closer together
+            for gap in gaps {
+                assert!(
+                    (gap - mean).abs() < 0.02 * mean,
+                    "{:?} {} {}",
+                    origin,
+                    gap,
+                    mean
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn only_drivers_who_do_not_comply_run_a_fresh_red() {
+        let rules = rules();
+        for compliant in [false, true] {
+            let mut traffic_light = TestLights::new(TrafficLightState::Green);
+            let mut car = CarBuilder::new(0, Origin::North, Direction::Straight)
+                .compliant(compliant)
+                .build();
+            // Plenty of room to stop
+            assert!(car.place_behind_stop_line(150.0));
+            car.speed = MAX_SPEED;
+            car.stopped = false;
+            car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+
+            traffic_light.state = TrafficLightState::Red;
+            for _ in 0..300 {
+                car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+            }
+            assert_eq!(car.through_intersection(), !compliant);
+            assert_eq!(car.stop_count(), compliant as usize);
+        }
+    }
+
+    #[test]
+    fn only_turning_cars_drift_off_their_path() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        let rules = rules();
+        // Largest drift to either side over the whole drive
+        let mut drift = |direction| {
+            let mut car = CarBuilder::new(0, Origin::North, direction).build();
+            let (mut left, mut right) = (0.0_f64, 0.0_f64);
+            while !car.finished {
+                car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+                if !car.finished {
+                    left = left.min(car.lateral_error());
+                    right = right.max(car.lateral_error());
+                }
+            }
+            (left, right)
+        };
+
+        let (left, right) = drift(Direction::Straight);
+        assert!(left > -1e-6 && right < 1e-6, "{} {}", left, right);
+        for direction in [Direction::Left, Direction::Right] {
+            let (left, right) = drift(direction);
+            assert!(right - left > 1.0, "{:?} {} {}", direction, left, right);
+        }
+    }
+
+    #[test]
+    fn car_nosing_into_the_box_on_a_fresh_red_clears_it() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Red);
+        let rules = rules();
+        // The furthest back the car can be with its front in the box
+        let mut car = CarBuilder::new(0, Origin::North, Direction::Straight).build();
+        let nosed_in = (0..200).map(|i| -(i as f64)).find(|&distance| {
+            assert!(car.place_behind_stop_line(distance));
+            car.in_intersection()
+        });
+        assert!(nosed_in.is_some());
+        assert!(car.path_index <= car.path_index_at_intersection);
+        car.speed = 1.0;
+        car.stopped = false;
+
+        car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+        assert!(car.through_intersection());
+        drive(&mut car, &mut traffic_light);
+        assert_eq!(car.stop_count(), 0);
+    }
+
+    #[test]
+    fn trail_is_bounded_and_fades_towards_the_oldest_point() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Red);
+        let rules = rules();
+        let mut car = CarBuilder::new(0, Origin::North, Direction::Straight).build();
+        // Longer than the history, stopped at the light for most of it
+        for _ in 0..2 * POSITION_HISTORY_LENGTH {
+            car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+            assert!(car.position_history.len() <= POSITION_HISTORY_LENGTH);
+        }
+        assert_eq!(car.position_history.len(), POSITION_HISTORY_LENGTH);
+
+        // One segment between each pair of points
+        assert_eq!(car.trail(120).len(), 119);
+        assert_eq!(
+            car.trail(10 * POSITION_HISTORY_LENGTH).len(),
+            POSITION_HISTORY_LENGTH - 1
+        );
+        let trail = car.trail(120);
+        assert!(trail.windows(2).all(|pair| pair[0].2 < pair[1].2));
+        assert_eq!(trail.last().unwrap().1, car.position());
+    }
+
+    #[test]
+    fn car_at_max_speed_goes_by_every_point() {
+        let mut traffic_light = TestLights::new(TrafficLightState::Green);
+        let rules = rules();
+        for geometry in [
+            WorldGeometry::default(),
+            WorldGeometry::new(800.0, 800.0).with_spawn_setback(100.0),
+        ] {
+            for origin in ORIGINS {
+                for direction in DIRECTIONS {
+                    let mut car = CarBuilder::new(0, origin, direction)
+                        .geometry(geometry)
+                        .build();
+                    place(&mut car, 1, MAX_SPEED);
+                    let mut path_index = car.path_index;
+                    for _ in 0..1000 {
+                        if car.finished {
+                            break;
+                        }
+                        let position = car.position;
+                        car.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+                        assert!(car.path_index >= path_index);
+                        // Every point the car moved on from was on the way, not stepped over from
+                        // off to the side
+                        for point in &car.path[path_index..car.path_index] {
+                            let distance = (point.0 - position.0).hypot(point.1 - position.1);
+                            assert!(
+                                distance <= MAX_SPEED + DISTANCE_THRESHOLD,
+                                "{:?} {:?} {}",
+                                origin,
+                                direction,
+                                distance
+                            );
+                        }
+                        path_index = car.path_index;
+                    }
+                    assert!(car.finished, "{:?} {:?}", origin, direction);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn turn_paths_never_double_back_whatever_the_setback() {
+        for setback in [CAR_WIDTH / 2.0, 300.0, 700.0] {
+            let geometry = WorldGeometry::default().with_spawn_setback(setback);
+            for origin in ORIGINS {
+                for direction in [Direction::Left, Direction::Right] {
+                    let car = CarBuilder::new(0, origin, direction)
+                        .geometry(geometry)
+                        .build();
+                    for points in car.path.windows(3) {
+                        let before = (points[1].0 - points[0].0, points[1].1 - points[0].1);
+                        let after = (points[2].0 - points[1].0, points[2].1 - points[1].1);
+                        assert!(
+                            before.0 * after.0 + before.1 * after.1 > 0.0,
+                            "{} {:?} {:?} {:?}",
+                            setback,
+                            origin,
+                            direction,
+                            points
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cars_released_together_never_touch_in_the_intersection() {
+        // Both go from the given number of points back from the stop line with a green, however
+        // their movements conflict
+        let release = |movements: [(Origin, Direction, usize); 2]| {
+            let mut traffic_light = TestLights::new(TrafficLightState::Green);
+            let mut cars = movements
+                .iter()
+                .enumerate()
+                .map(|(id, &(origin, direction, back))| {
+                    let mut car = CarBuilder::new(id, origin, direction).build();
+                    let index = car.path_index_at_intersection - back;
+                    place(&mut car, index, 0.0);
+                    car
+                })
+                .collect::<Vec<_>>();
+            for frame in 0..2000 {
+                let rules = DrivingRules { frame, ..rules() };
+                let others = cars.clone();
+                for car in &mut cars {
+                    car.update(&others, &mut traffic_light, &rules, 1.0);
+                }
+                assert!(
+                    !Car::cars_intersect(
+                        cars[0].position,
+                        cars[0].rotation,
+                        cars[1].position,
+                        cars[1].rotation
+                    ),
+                    "{:?} on frame {}",
+                    movements,
+                    frame
+                );
+            }
+            assert!(cars.iter().all(|car| car.finished), "{:?}", movements);
+        };
+
+        release([
+            (Origin::North, Direction::Left, 0),
+            (Origin::South, Direction::Left, 0),
+        ]);
+        // These paths cross, and with the first car a few points back they'd get there together
+        // if one didn't wait for the other
+        for back in 0..6 {
+            release([
+                (Origin::North, Direction::Left, back),
+                (Origin::East, Direction::Straight, 0),
+            ]);
+            release([
+                (Origin::West, Direction::Straight, back),
+                (Origin::North, Direction::Straight, 0),
+            ]);
+        }
+    }
+
+    #[test]
+    fn planned_stop_brakes_gently_but_a_frozen_leader_brakes_hard() {
+        let cruising = |id, distance| {
+            let mut car = CarBuilder::new(id, Origin::North, Direction::Straight).build();
+            assert!(car.place_behind_stop_line(distance));
+            car.speed = MAX_SPEED;
+            car.stopped = false;
+            car
+        };
+        // Most speed lost on any one frame, driving among `others`
+        fn hardest_braking(
+            car: &mut Car,
+            others: &[Car],
+            traffic_light: &mut dyn ControllerStrategy,
+        ) -> f64 {
+            let rules = rules();
+            let mut hardest: f64 = 0.0;
+            for _ in 0..600 {
+                let speed = car.speed;
+                let mut cars = others.to_vec();
+                cars.push(car.clone());
+                car.update(&cars, traffic_light, &rules, 1.0);
+                hardest = hardest.max(speed - car.speed);
+            }
+            hardest
+        }
+
+        // Plenty of room to stop for the red
+        let mut red = TestLights::new(TrafficLightState::Red);
+        let mut car = cruising(0, 300.0);
+        let hardest = hardest_braking(&mut car, &[], &mut red);
+        assert_eq!(car.speed(), 0.0);
+        assert!(
+            hardest > 0.0 && hardest <= DECELERATION + 1e-9,
+            "{}",
+            hardest
+        );
+
+        // Following at full speed when the car ahead stops dead
+        let mut green = TestLights::new(TrafficLightState::Green);
+        let mut leader = cruising(0, 300.0);
+        leader.speed = 0.0;
+        let mut follower = cruising(1, 300.0 + leader.length() + FOLLOWING_GAP);
+        let hardest = hardest_braking(&mut follower, &[leader.clone()], &mut green);
+        assert_eq!(follower.speed(), 0.0);
+        assert!(!follower.overlaps(&leader));
+        assert!(hardest > DECELERATION, "{}", hardest);
+    }
+
+    #[test]
+    fn turners_reach_any_receiving_lane_without_kinking() {
+        let geometry = WorldGeometry::default();
+        let lane_width = geometry.layout.lane_width;
+        let lanes = geometry.layout.lanes();
+        for direction in [Direction::Left, Direction::Right] {
+            let paths = (0..lanes)
+                .map(|lane| {
+                    CarBuilder::new(0, Origin::North, direction)
+                        .receiving_lane(lane)
+                        .build()
+                        .path
+                })
+                .collect::<Vec<_>>();
+            // Heading out east or west, each lane one further from the middle of the road
+            let distances_from_middle = paths
+                .iter()
+                .map(|path| (path.last().unwrap().1 - geometry.middle().1).abs())
+                .collect::<Vec<_>>();
+            for (lane, distance) in distances_from_middle.iter().enumerate() {
+                assert!(
+                    (distance - (lane as f64 + 0.5) * lane_width).abs() < 1e-6,
+                    "{:?} {:?}",
+                    direction,
+                    distances_from_middle
+                );
+            }
+            // Right turns hug the corner too tightly to spread over this few points
+            if direction == Direction::Left {
+                for path in &paths {
+                    let headings = path
+                        .windows(2)
+                        .map(|points| (points[1].1 - points[0].1).atan2(points[1].0 - points[0].0))
+                        .collect::<Vec<_>>();
+                    let sharpest = headings
+                        .windows(2)
+                        .map(|pair| (pair[1] - pair[0]).abs())
+                        .fold(0.0, f64::max);
+                    // The quarter turn is spread over several points, so no one point turns much
+                    // of it
+                    assert!(sharpest.to_degrees() < 20.0, "{}", sharpest.to_degrees());
+                }
+            }
+
+            assert_eq!(
+                CarBuilder::new(0, Origin::North, direction)
+                    .receiving_lane(lanes)
+                    .try_build()
+                    .err(),
+                Some(PathError::Movement(MovementError::InvalidReceivingLane(
+                    lanes
+                )))
+            );
+        }
+
+        // Without a receiving lane, right turns keep to the curb side of the road
+        let default_right = CarBuilder::new(0, Origin::North, Direction::Right).build();
+        let curb_side = CarBuilder::new(0, Origin::North, Direction::Right)
+            .receiving_lane(geometry.layout.lane_offset(Direction::Right, 0))
+            .build();
+        assert_eq!(default_right.path, curb_side.path);
+    }
+
+    #[test]
+    fn straight_cars_side_by_side_never_intersect() {
+        for origin in ORIGINS {
+            let rotation = match origin {
+                Origin::North => 90.0,
+                Origin::South => 270.0,
+                Origin::East => 180.0,
+                Origin::West => 0.0,
+            };
+            let [inner, outer] = [0, 1].map(|lane| {
+                CarBuilder::new(lane, origin, Direction::Straight)
+                    .lane(lane)
+                    .build()
+                    .path
+            });
+            // Wherever along their lanes the two cars are, even level with each other
+            for &position in &inner {
+                for &other_position in &outer {
+                    assert!(
+                        !Car::cars_intersect(position, rotation, other_position, rotation),
+                        "{:?} {:?} {:?}",
+                        origin,
+                        position,
+                        other_position
+                    );
+                }
+            }
+        }
+    }
+}
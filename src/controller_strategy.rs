@@ -0,0 +1,115 @@
+use piston_window::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::{
+    car,
+    fixed_time_controller::{FixedTimeController, FixedTimeControllerSave},
+    geometry::WorldGeometry,
+    simulation_world::ORIGINS,
+    traffic_light::TrafficLightState,
+    traffic_light_controller::{
+        movements_conflict, SimplifiedCar, TrafficLightController, TrafficLightControllerSave,
+    },
+};
+
+/// Saved state of any of the controllers
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ControllerSave {
+    Adaptive(TrafficLightControllerSave),
+    FixedTime(FixedTimeControllerSave),
+}
+
+impl ControllerSave {
+    /// Makes a controller of the saved kind, in the saved state
+    pub fn restore(&self, geometry: WorldGeometry) -> Box<dyn ControllerStrategy> {
+        match self {
+            ControllerSave::Adaptive(save) => {
+                Box::new(TrafficLightController::load(save, geometry))
+            }
+            ControllerSave::FixedTime(save) => Box::new(FixedTimeController::load(save, geometry)),
+        }
+    }
+}
+
+/// Decides when each light changes. Cars only talk to the lights through this trait so that
+/// different controllers can be compared against each other.
+pub trait ControllerStrategy {
+    /// Moves the timers forward by `dt` of simulated time. The world calls this once per update,
+    /// so the lights keep time with the cars whatever the frame rate is.
+    fn advance(&mut self, dt: Duration);
+
+    fn update(&mut self);
+
+    fn draw(&self, context: &Context, graphics: &mut G2d);
+
+    /// Called when a car starts waiting for the light
+    fn add_car(&mut self, car: SimplifiedCar);
+
+    /// Called when a car no longer needs the light, either because it went into the intersection
+    /// or because it committed to going on yellow
+    fn remove_car(&mut self, car: SimplifiedCar);
+
+    /// Returns if the light is green or yellow for the given origin.
+    fn is_green(&self, origin: car::Origin, direction: car::Direction) -> bool;
+
+    fn is_yellow(&self, origin: car::Origin, direction: car::Direction) -> bool;
+
+    /// Returns if pedestrians are crossing the leg on the side of the given origin
+    fn is_walk(&self, origin: car::Origin) -> bool;
+
+    fn light_state(&self, origin: car::Origin, direction: car::Direction) -> TrafficLightState;
+
+    /// Gives the movements a green, and every other movement a red, until `clear_override` is
+    /// called. Controllers that can't be overridden ignore this.
+    fn force_phase(&mut self, _movements: Vec<SimplifiedCar>) {}
+
+    /// Hands the lights back to the controller after `force_phase`
+    fn clear_override(&mut self) {}
+
+    /// Returns if left turns from the given origin can go after yielding to oncoming traffic,
+    /// which is whenever their own light is red but the straight light next to them is green
+    fn is_permissive_left(&self, origin: car::Origin) -> bool {
+        self.light_state(origin, car::Direction::Left) == TrafficLightState::Red
+            && self.light_state(origin, car::Direction::Straight) == TrafficLightState::Green
+    }
+
+    /// Movements whose light is currently green or yellow
+    fn active_movements(&self) -> Vec<SimplifiedCar> {
+        ORIGINS
+            .iter()
+            .flat_map(|&origin| {
+                [
+                    car::Direction::Left,
+                    car::Direction::Straight,
+                    car::Direction::Right,
+                ]
+                .map(|direction| SimplifiedCar::new(origin, direction))
+            })
+            .filter(|movement| {
+                self.light_state(movement.origin, movement.direction) != TrafficLightState::Red
+            })
+            .collect()
+    }
+
+    /// Pairs of active movements that conflict with each other. Should always be empty; anything
+    /// in here is a bug in the controller.
+    fn active_conflicts(&self) -> Vec<(SimplifiedCar, SimplifiedCar)> {
+        let active = self.active_movements();
+        let mut conflicts = Vec::new();
+        for (i, &a) in active.iter().enumerate() {
+            for &b in &active[i + 1..] {
+                if movements_conflict(a, b) {
+                    conflicts.push((a, b));
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Puts the controller back the way it was when it was made, keeping its settings
+    fn reset(&mut self);
+
+    /// Captures the controller's state so the run can be picked up again later
+    fn save(&self) -> ControllerSave;
+}
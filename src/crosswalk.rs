@@ -0,0 +1,167 @@
+use piston_window::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::{
+    car,
+    geometry::WorldGeometry,
+    traffic_light::{TrafficLight, TrafficLightState},
+    WALK_TIME,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WalkState {
+    Walk,
+    DontWalk,
+}
+
+/// The parts of a crosswalk that change while the simulation runs
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CrosswalkSave {
+    state: WalkState,
+    walk_elapsed: Duration,
+    served: bool,
+}
+
+/// Pedestrian crossing over the leg of the intersection on the side of `origin`. Pedestrians get
+/// to walk once all of the lights for that origin have turned red, and have to stop as soon as
+/// one of them turns green again.
+#[derive(Debug)]
+pub struct Crosswalk {
+    pub origin: car::Origin,
+    pub state: WalkState,
+    walk_elapsed: Duration,
+    /// Whether pedestrians already got their walk since the last time the vehicles were green
+    served: bool,
+    geometry: WorldGeometry,
+}
+
+impl Crosswalk {
+    pub fn new(origin: car::Origin, geometry: WorldGeometry) -> Crosswalk {
+        Crosswalk {
+            origin,
+            state: WalkState::DontWalk,
+            walk_elapsed: Duration::ZERO,
+            served: false,
+            geometry,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.state = WalkState::DontWalk;
+        self.walk_elapsed = Duration::ZERO;
+        self.served = false;
+    }
+
+    pub fn generate_crosswalks(geometry: WorldGeometry) -> Vec<Crosswalk> {
+        vec![
+            Crosswalk::new(car::Origin::North, geometry),
+            Crosswalk::new(car::Origin::South, geometry),
+            Crosswalk::new(car::Origin::East, geometry),
+            Crosswalk::new(car::Origin::West, geometry),
+        ]
+    }
+
+    /// Updates every crosswalk from the state of the lights on its side of the intersection
+    pub fn update_crosswalks(crosswalks: &mut [Crosswalk], traffic_lights: &[TrafficLight]) {
+        for crosswalk in crosswalks {
+            let vehicles_red = traffic_lights
+                .iter()
+                .filter(|traffic_light| traffic_light.origin == crosswalk.origin)
+                .all(|traffic_light| traffic_light.state == TrafficLightState::Red);
+            crosswalk.update(vehicles_red);
+        }
+    }
+
+    pub fn is_walk(crosswalks: &[Crosswalk], origin: car::Origin) -> bool {
+        crosswalks
+            .iter()
+            .any(|crosswalk| crosswalk.origin == origin && crosswalk.state == WalkState::Walk)
+    }
+
+    pub fn update(&mut self, vehicles_red: bool) {
+        if !vehicles_red {
+            self.state = WalkState::DontWalk;
+            self.served = false;
+            return;
+        }
+
+        match self.state {
+            WalkState::Walk => {
+                if self.walk_elapsed >= WALK_TIME {
+                    self.state = WalkState::DontWalk;
+                    self.served = true;
+                }
+            }
+            WalkState::DontWalk => {
+                if !self.served {
+                    self.state = WalkState::Walk;
+                    self.walk_elapsed = Duration::ZERO;
+                }
+            }
+        }
+    }
+
+    pub fn save(&self) -> CrosswalkSave {
+        CrosswalkSave {
+            state: self.state,
+            walk_elapsed: self.walk_elapsed,
+            served: self.served,
+        }
+    }
+
+    pub fn load(&mut self, save: &CrosswalkSave) {
+        self.state = save.state;
+        self.walk_elapsed = save.walk_elapsed;
+        self.served = save.served;
+    }
+
+    /// Moves the walk timer forward by `dt` of simulated time
+    pub fn advance(&mut self, dt: Duration) {
+        self.walk_elapsed += dt;
+    }
+
+    pub fn draw(&self, context: &Context, graphics: &mut G2d) {
+        let middle = self.geometry.middle();
+        let half_size = self.geometry.intersection_half_size();
+        let color = match self.state {
+            WalkState::Walk => [1.0, 1.0, 1.0, 0.8],
+            WalkState::DontWalk => [0.4, 0.4, 0.4, 0.8],
+        };
+
+        let num_stripes = 12;
+        let stripe_gap = half_size * 2.0 / num_stripes as f64;
+        let stripe_width = stripe_gap / 2.0;
+        let stripe_length = self.geometry.layout.lane_width / 3.0;
+        for i in 0..num_stripes {
+            let along = -half_size + (i as f64 + 0.25) * stripe_gap;
+            let rect = match self.origin {
+                car::Origin::North => [
+                    middle.0 + along,
+                    middle.1 - half_size,
+                    stripe_width,
+                    stripe_length,
+                ],
+                car::Origin::South => [
+                    middle.0 + along,
+                    middle.1 + half_size - stripe_length,
+                    stripe_width,
+                    stripe_length,
+                ],
+                car::Origin::East => [
+                    middle.0 + half_size - stripe_length,
+                    middle.1 + along,
+                    stripe_length,
+                    stripe_width,
+                ],
+                car::Origin::West => [
+                    middle.0 - half_size,
+                    middle.1 + along,
+                    stripe_length,
+                    stripe_width,
+                ],
+            };
+            rectangle(color, rect, context.transform, graphics);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    car,
+    simulation_world::{SimulationWorld, ORIGINS},
+    traffic_light::TrafficLightState,
+};
+
+const DIRECTIONS: [car::Direction; 3] = [
+    car::Direction::Left,
+    car::Direction::Straight,
+    car::Direction::Right,
+];
+
+/// Writes one row per frame with the queue of every approach, the number of finished cars, which
+/// movements have a green light and if the metrics are still warming up
+pub struct CsvLogger {
+    writer: BufWriter<File>,
+}
+
+impl CsvLogger {
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<CsvLogger> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "frame,north_queue,south_queue,east_queue,west_queue,total_finished,phase,warming_up"
+        )?;
+        Ok(CsvLogger { writer })
+    }
+
+    pub fn log(&mut self, world: &SimulationWorld) -> io::Result<()> {
+        let queues = ORIGINS.map(|origin| {
+            world
+                .cars
+                .iter()
+                .filter(|car| car.origin == origin && !car.finished && !car.through_intersection())
+                .count()
+        });
+
+        // Every movement that isn't red, e.g. "North Straight|South Straight"
+        let phase = ORIGINS
+            .iter()
+            .flat_map(|&origin| DIRECTIONS.iter().map(move |&direction| (origin, direction)))
+            .filter(|&(origin, direction)| {
+                world.traffic_light.light_state(origin, direction) != TrafficLightState::Red
+            })
+            .map(|(origin, direction)| format!("{:?} {:?}", origin, direction))
+            .collect::<Vec<_>>()
+            .join("|");
+
+        writeln!(
+            self.writer,
+            "{},{},{},{},{},{},{},{}",
+            world.frame,
+            queues[0],
+            queues[1],
+            queues[2],
+            queues[3],
+            world.total_finished,
+            phase,
+            world.metrics.is_warming_up(world.frame)
+        )
+    }
+}
+
+impl Drop for CsvLogger {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
@@ -0,0 +1,43 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// How cars arrive at an approach
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DemandModel {
+    /// Evenly spaced cars, `per_frame` of them every frame on average
+    Uniform { per_frame: f64 },
+    /// Cars arrive independently of each other, `rate` per frame on average
+    Poisson { rate: f64 },
+    /// `size` cars at once every `interval` frames, like a platoon let out by an upstream light
+    Burst { size: usize, interval: usize },
+}
+
+impl DemandModel {
+    /// Number of cars that arrive on the given frame
+    pub fn arrivals<R: Rng>(&self, frame: usize, rng: &mut R) -> usize {
+        match *self {
+            DemandModel::Uniform { per_frame } => {
+                ((frame + 1) as f64 * per_frame).floor() as usize
+                    - (frame as f64 * per_frame).floor() as usize
+            }
+            DemandModel::Poisson { rate } => {
+                // Knuth's method: count uniform draws until their product drops below e^-rate
+                let limit = (-rate).exp();
+                let mut product: f64 = rng.gen();
+                let mut arrivals = 0;
+                while product > limit {
+                    product *= rng.gen::<f64>();
+                    arrivals += 1;
+                }
+                arrivals
+            }
+            DemandModel::Burst { size, interval } => {
+                if interval > 0 && frame.is_multiple_of(interval) {
+                    size
+                } else {
+                    0
+                }
+            }
+        }
+    }
+}
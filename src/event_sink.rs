@@ -0,0 +1,112 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::car;
+
+/// Gets told about things happening to cars, so instrumentation doesn't have to poll every car
+/// every update. All of the methods do nothing by default.
+pub trait EventSink {
+    fn on_spawn(&mut self, _id: usize, _origin: car::Origin, _direction: car::Direction) {}
+
+    /// Called when a car goes into the intersection (or commits to going on yellow)
+    fn on_enter_intersection(&mut self, _id: usize) {}
+
+    /// Called when a car makes it all the way through. `total_frames` is how many updates the car
+    /// was around for and `distance_traveled` how far it drove.
+    fn on_finish(&mut self, _id: usize, _total_frames: usize, _distance_traveled: f64) {}
+
+    /// Called when two cars start overlapping
+    fn on_collision(&mut self, _a: usize, _b: usize) {}
+
+    /// Called when a car starts overlapping an obstacle. `obstacle` is its index in the world's
+    /// obstacles.
+    fn on_obstacle_collision(&mut self, _id: usize, _obstacle: usize) {}
+
+    /// Called when a car drove off the canvas without finishing its path and was finished for it
+    fn on_escape(&mut self, _id: usize) {}
+}
+
+pub struct NoopEventSink;
+
+impl EventSink for NoopEventSink {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CarEvent {
+    Spawn {
+        id: usize,
+        origin: car::Origin,
+        direction: car::Direction,
+    },
+    EnterIntersection {
+        id: usize,
+    },
+    Finish {
+        id: usize,
+        total_frames: usize,
+        distance_traveled: f64,
+    },
+    Collision {
+        a: usize,
+        b: usize,
+    },
+    ObstacleCollision {
+        id: usize,
+        obstacle: usize,
+    },
+    Escape {
+        id: usize,
+    },
+}
+
+/// Records every event. Keep a handle from `events()` before giving the sink to the world.
+#[derive(Default)]
+pub struct VecEventSink {
+    events: Rc<RefCell<Vec<CarEvent>>>,
+}
+
+impl VecEventSink {
+    pub fn new() -> VecEventSink {
+        VecEventSink::default()
+    }
+
+    pub fn events(&self) -> Rc<RefCell<Vec<CarEvent>>> {
+        Rc::clone(&self.events)
+    }
+}
+
+impl EventSink for VecEventSink {
+    fn on_spawn(&mut self, id: usize, origin: car::Origin, direction: car::Direction) {
+        self.events.borrow_mut().push(CarEvent::Spawn {
+            id,
+            origin,
+            direction,
+        });
+    }
+
+    fn on_enter_intersection(&mut self, id: usize) {
+        self.events
+            .borrow_mut()
+            .push(CarEvent::EnterIntersection { id });
+    }
+
+    fn on_finish(&mut self, id: usize, total_frames: usize, distance_traveled: f64) {
+        self.events.borrow_mut().push(CarEvent::Finish {
+            id,
+            total_frames,
+            distance_traveled,
+        });
+    }
+
+    fn on_collision(&mut self, a: usize, b: usize) {
+        self.events.borrow_mut().push(CarEvent::Collision { a, b });
+    }
+
+    fn on_obstacle_collision(&mut self, id: usize, obstacle: usize) {
+        self.events
+            .borrow_mut()
+            .push(CarEvent::ObstacleCollision { id, obstacle });
+    }
+
+    fn on_escape(&mut self, id: usize) {
+        self.events.borrow_mut().push(CarEvent::Escape { id });
+    }
+}
@@ -0,0 +1,738 @@
+use piston_window::*;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+
+use crate::{
+    car,
+    controller_strategy::{ControllerSave, ControllerStrategy},
+    crosswalk::{Crosswalk, CrosswalkSave},
+    geometry::WorldGeometry,
+    phase_plan::PhasePlan,
+    traffic_light::{TrafficLight, TrafficLightSave, TrafficLightState},
+    traffic_light_controller::{movements_conflict, SimplifiedCar, TrafficLightController},
+    ALLOW_GO_ON_YELLOW, MINIMUM_GREEN_TIME,
+};
+
+/// How far the green splits can add up to something other than 1
+const GREEN_SPLIT_TOLERANCE: f64 = 1e-6;
+
+/// Reasons green splits can't be used with a plan
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GreenSplitError {
+    /// There has to be one split for each phase of the plan
+    WrongCount { phases: usize, splits: usize },
+    /// The splits have to be between 0 and 1
+    OutOfRange(f64),
+    /// The splits have to add up to 1
+    BadSum(f64),
+}
+
+/// Reasons a phase plan can't be run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlanError {
+    /// The plan has no phases to give a green to, like a plan cut down to approaches that don't
+    /// connect to each other
+    NoPhases,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FixedTimeControllerSave {
+    green_time: Duration,
+    yellow_time: Duration,
+    plan: PhasePlan,
+    gap_out_enabled: bool,
+    queue: Vec<(SimplifiedCar, usize)>,
+    traffic_lights: Vec<TrafficLightSave>,
+    crosswalks: Vec<CrosswalkSave>,
+    phase_index: usize,
+    phase_elapsed: Duration,
+    progression_offset: Option<(car::Origin, Duration)>,
+    green_splits: Option<Vec<f64>>,
+    forced_phase: Option<Vec<SimplifiedCar>>,
+    overridden: bool,
+}
+
+/// Baseline controller that mostly ignores the cars: every phase of the plan gets a green followed
+/// by a yellow in turn. The only thing the cars change is when greens gap out, if that's on.
+pub struct FixedTimeController {
+    green_time: Duration,
+    yellow_time: Duration,
+    plan: PhasePlan,
+    /// Ends a green early once no cars are coming for it and another phase has cars waiting
+    pub gap_out_enabled: bool,
+    /// Number of cars waiting for (or driving up to) each light. Only used to gap out.
+    queue: HashMap<SimplifiedCar, usize>,
+    traffic_lights: Vec<TrafficLight>,
+    crosswalks: Vec<Crosswalk>,
+    phase_index: usize,
+    /// Simulated time since the current phase started
+    phase_elapsed: Duration,
+    /// Corridor (given by either of its approaches) whose green is lined up to start a set time
+    /// after the controller starts
+    progression_offset: Option<(car::Origin, Duration)>,
+    /// Share of the cycle's green time each phase gets. Without them every phase gets
+    /// `green_time`.
+    green_splits: Option<Vec<f64>>,
+    /// Movements held green by `force_phase`
+    forced_phase: Option<Vec<SimplifiedCar>>,
+    /// Set from `force_phase` until the lights are back to what the plan wants after
+    /// `clear_override`. The plan's clock doesn't run while this is set.
+    overridden: bool,
+}
+
+impl FixedTimeController {
+    pub fn new(
+        green_time: Duration,
+        yellow_time: Duration,
+        geometry: WorldGeometry,
+    ) -> FixedTimeController {
+        FixedTimeController {
+            green_time,
+            yellow_time,
+            plan: PhasePlan::default(),
+            gap_out_enabled: false,
+            queue: TrafficLightController::generate_queue(),
+            traffic_lights: TrafficLightController::generate_traffic_lights(geometry),
+            crosswalks: Crosswalk::generate_crosswalks(geometry),
+            phase_index: 0,
+            phase_elapsed: Duration::ZERO,
+            progression_offset: None,
+            green_splits: None,
+            forced_phase: None,
+            overridden: false,
+        }
+    }
+
+    /// Makes a controller in the state it was saved in
+    pub fn load(save: &FixedTimeControllerSave, geometry: WorldGeometry) -> FixedTimeController {
+        let mut controller = FixedTimeController::new(save.green_time, save.yellow_time, geometry);
+        controller.plan = save.plan.clone();
+        controller.gap_out_enabled = save.gap_out_enabled;
+        controller.queue = save.queue.iter().copied().collect();
+        for (traffic_light, light_save) in controller
+            .traffic_lights
+            .iter_mut()
+            .zip(&save.traffic_lights)
+        {
+            traffic_light.load(light_save);
+        }
+        for (crosswalk, crosswalk_save) in controller.crosswalks.iter_mut().zip(&save.crosswalks) {
+            crosswalk.load(crosswalk_save);
+        }
+        controller.phase_index = save.phase_index;
+        controller.phase_elapsed = save.phase_elapsed;
+        controller.progression_offset = save.progression_offset;
+        controller.green_splits = save.green_splits.clone();
+        controller.forced_phase = save.forced_phase.clone();
+        controller.overridden = save.overridden;
+        controller
+    }
+
+    /// Runs the phases of `plan` instead of giving each approach a green in turn. Drops any green
+    /// splits, since they were for the old plan's phases. Fails if the plan has no phases.
+    pub fn with_plan(mut self, plan: PhasePlan) -> Result<FixedTimeController, PlanError> {
+        if plan.phases.is_empty() {
+            return Err(PlanError::NoPhases);
+        }
+        self.plan = plan;
+        self.phase_index = 0;
+        self.green_splits = None;
+        self.apply_progression_offset();
+        Ok(self)
+    }
+
+    /// Shares the cycle's green time out between the phases of the plan, in order, instead of
+    /// giving each of them `green_time`. The cycle stays as long as it was: the yellows are taken
+    /// out first and the rest is split up. The splits have to add up to 1.
+    pub fn with_green_splits(
+        mut self,
+        splits: Vec<f64>,
+    ) -> Result<FixedTimeController, GreenSplitError> {
+        if splits.len() != self.plan.phases.len() {
+            return Err(GreenSplitError::WrongCount {
+                phases: self.plan.phases.len(),
+                splits: splits.len(),
+            });
+        }
+        if let Some(&split) = splits.iter().find(|split| !(0.0..=1.0).contains(*split)) {
+            return Err(GreenSplitError::OutOfRange(split));
+        }
+        let sum = splits.iter().sum::<f64>();
+        if (sum - 1.0).abs() > GREEN_SPLIT_TOLERANCE {
+            return Err(GreenSplitError::BadSum(sum));
+        }
+
+        self.green_splits = Some(splits);
+        self.apply_progression_offset();
+        Ok(self)
+    }
+
+    /// How long the phase's green lasts
+    pub fn phase_green_time(&self, phase_index: usize) -> Duration {
+        match &self.green_splits {
+            Some(splits) => self
+                .green_time
+                .mul_f64(splits[phase_index] * self.plan.phases.len() as f64),
+            None => self.green_time,
+        }
+    }
+
+    /// Shifts the cycle so the first green of the corridor `origin` is on (it and the approach
+    /// opposite it) starts `offset` after the controller starts or is reset. Lining that up with
+    /// when platoons from upstream arrive, like `DemandModel::Burst` ones, makes a green wave.
+    pub fn set_progression_offset(&mut self, origin: car::Origin, offset: Duration) {
+        self.progression_offset = Some((origin, offset));
+        self.apply_progression_offset();
+    }
+
+    pub fn progression_offset(&self) -> Option<(car::Origin, Duration)> {
+        self.progression_offset
+    }
+
+    /// Restarts the cycle at the point that puts the corridor's green `progression_offset` away
+    fn apply_progression_offset(&mut self) {
+        let Some((origin, offset)) = self.progression_offset else {
+            return;
+        };
+        let opposite = car::exit_leg(origin, car::Direction::Straight);
+        let Some(corridor_phase) = self.plan.phases.iter().position(|phase| {
+            phase
+                .iter()
+                .any(|movement| movement.origin == origin || movement.origin == opposite)
+        }) else {
+            return;
+        };
+
+        let phase_time = |phase_index| self.phase_green_time(phase_index) + self.yellow_time;
+        let cycle_time = (0..self.plan.phases.len())
+            .map(phase_time)
+            .sum::<Duration>();
+        // Every point of an empty cycle is as good as any other
+        if cycle_time.is_zero() {
+            return;
+        }
+        let corridor_start = (0..corridor_phase).map(phase_time).sum::<Duration>();
+        // How far into the cycle we have to be now for the corridor's green to start at `offset`
+        let mut cycle_elapsed = Duration::from_nanos(
+            ((corridor_start.as_nanos() + cycle_time.as_nanos()
+                - offset.as_nanos() % cycle_time.as_nanos())
+                % cycle_time.as_nanos()) as u64,
+        );
+        let mut phase_index = 0;
+        while phase_index + 1 < self.plan.phases.len() && cycle_elapsed >= phase_time(phase_index) {
+            cycle_elapsed -= phase_time(phase_index);
+            phase_index += 1;
+        }
+        self.phase_index = phase_index;
+        self.phase_elapsed = cycle_elapsed;
+    }
+
+    /// State the plan wants the light to be in right now
+    fn plan_state(&self, traffic_light: &TrafficLight) -> TrafficLightState {
+        if !self.plan.is_green_in(
+            self.phase_index,
+            traffic_light.origin,
+            traffic_light.direction,
+        ) {
+            TrafficLightState::Red
+        } else if self.phase_elapsed < self.phase_green_time(self.phase_index) {
+            TrafficLightState::Green
+        } else {
+            TrafficLightState::Yellow
+        }
+    }
+
+    /// Moves the lights towards the forced phase, or back to the plan once the override is
+    /// cleared. Greens that have to end go through their yellow, and a light only turns green
+    /// once every light that conflicts with it is red.
+    fn update_overridden(&mut self) {
+        let wanted = self
+            .traffic_lights
+            .iter()
+            .map(|traffic_light| {
+                let movement = SimplifiedCar::new(traffic_light.origin, traffic_light.direction);
+                match &self.forced_phase {
+                    Some(forced_phase) if forced_phase.contains(&movement) => {
+                        TrafficLightState::Green
+                    }
+                    Some(_) => TrafficLightState::Red,
+                    None => self.plan_state(traffic_light),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for (traffic_light, &wanted) in self.traffic_lights.iter_mut().zip(&wanted) {
+            if wanted == TrafficLightState::Green {
+                continue;
+            }
+            match traffic_light.state {
+                TrafficLightState::Green => traffic_light.change_to_red(),
+                TrafficLightState::Yellow
+                    if wanted == TrafficLightState::Red
+                        && traffic_light.red_elapsed >= self.yellow_time =>
+                {
+                    traffic_light.state = TrafficLightState::Red;
+                }
+                _ => {}
+            }
+        }
+        // One at a time, so two wanted greens that cross each other can't both go
+        for i in 0..self.traffic_lights.len() {
+            if wanted[i] != TrafficLightState::Green
+                || self.traffic_lights[i].state != TrafficLightState::Red
+            {
+                continue;
+            }
+            let movement = SimplifiedCar::new(
+                self.traffic_lights[i].origin,
+                self.traffic_lights[i].direction,
+            );
+            let crossing_clear = self.traffic_lights.iter().all(|other| {
+                other.state == TrafficLightState::Red
+                    || !movements_conflict(
+                        movement,
+                        SimplifiedCar::new(other.origin, other.direction),
+                    )
+            });
+            if crossing_clear {
+                self.traffic_lights[i].state = TrafficLightState::Green;
+                self.traffic_lights[i].green_elapsed = Duration::ZERO;
+            }
+        }
+
+        // Back on the plan once every light has caught up with it. Lights the plan has on yellow
+        // can already be red.
+        if self.forced_phase.is_none()
+            && self
+                .traffic_lights
+                .iter()
+                .zip(&wanted)
+                .all(|(traffic_light, &wanted)| {
+                    traffic_light.state == wanted
+                        || (wanted == TrafficLightState::Yellow
+                            && traffic_light.state == TrafficLightState::Red)
+                })
+        {
+            self.overridden = false;
+        }
+    }
+
+    /// Returns if no cars are coming for any of the movements in the phase
+    fn phase_is_empty(&self, phase_index: usize) -> bool {
+        self.plan.phases[phase_index]
+            .iter()
+            .all(|movement| self.queue.get(movement).copied().unwrap_or(0) == 0)
+    }
+
+    fn get_traffic_light(&self, origin: car::Origin, direction: car::Direction) -> &TrafficLight {
+        self.traffic_lights
+            .iter()
+            .find(|traffic_light| {
+                traffic_light.origin == origin && traffic_light.direction == direction
+            })
+            .unwrap()
+    }
+}
+
+impl ControllerStrategy for FixedTimeController {
+    fn advance(&mut self, dt: Duration) {
+        if !self.overridden {
+            self.phase_elapsed += dt;
+        }
+        for traffic_light in &mut self.traffic_lights {
+            traffic_light.advance(dt);
+        }
+        for crosswalk in &mut self.crosswalks {
+            crosswalk.advance(dt);
+        }
+    }
+
+    fn update(&mut self) {
+        if self.overridden {
+            self.update_overridden();
+            Crosswalk::update_crosswalks(&mut self.crosswalks, &self.traffic_lights);
+            return;
+        }
+
+        // Skip straight to the yellow when the green is going to waste
+        let elapsed = self.phase_elapsed;
+        let green_time = self.phase_green_time(self.phase_index);
+        if self.gap_out_enabled
+            && elapsed >= MINIMUM_GREEN_TIME
+            && elapsed < green_time
+            && self.phase_is_empty(self.phase_index)
+            && (0..self.plan.phases.len()).any(|i| !self.phase_is_empty(i))
+        {
+            self.phase_elapsed = green_time;
+        }
+
+        if self.phase_elapsed >= green_time + self.yellow_time {
+            self.phase_index = (self.phase_index + 1) % self.plan.phases.len();
+            self.phase_elapsed = Duration::ZERO;
+        }
+
+        let states = self
+            .traffic_lights
+            .iter()
+            .map(|traffic_light| self.plan_state(traffic_light))
+            .collect::<Vec<_>>();
+        for (traffic_light, state) in self.traffic_lights.iter_mut().zip(states) {
+            traffic_light.state = state;
+        }
+
+        Crosswalk::update_crosswalks(&mut self.crosswalks, &self.traffic_lights);
+    }
+
+    fn draw(&self, context: &Context, graphics: &mut G2d) {
+        for crosswalk in &self.crosswalks {
+            crosswalk.draw(context, graphics);
+        }
+        for traffic_light in &self.traffic_lights {
+            traffic_light.draw(context, graphics);
+        }
+    }
+
+    fn add_car(&mut self, car: SimplifiedCar) {
+        if let Some(queue) = self.queue.get_mut(&car) {
+            *queue += 1;
+        }
+    }
+
+    fn remove_car(&mut self, car: SimplifiedCar) {
+        if let Some(queue) = self.queue.get_mut(&car) {
+            *queue = queue.saturating_sub(1);
+        }
+    }
+
+    fn is_green(&self, origin: car::Origin, direction: car::Direction) -> bool {
+        self.get_traffic_light(origin, direction).state != TrafficLightState::Red
+    }
+
+    fn is_yellow(&self, origin: car::Origin, direction: car::Direction) -> bool {
+        ALLOW_GO_ON_YELLOW
+            && self.get_traffic_light(origin, direction).state == TrafficLightState::Yellow
+    }
+
+    fn is_walk(&self, origin: car::Origin) -> bool {
+        Crosswalk::is_walk(&self.crosswalks, origin)
+    }
+
+    fn light_state(&self, origin: car::Origin, direction: car::Direction) -> TrafficLightState {
+        self.get_traffic_light(origin, direction).state
+    }
+
+    fn force_phase(&mut self, movements: Vec<SimplifiedCar>) {
+        self.forced_phase = Some(movements);
+        self.overridden = true;
+    }
+
+    /// The plan picks up where it was when the phase was forced, once the forced greens have
+    /// gone through their yellow
+    fn clear_override(&mut self) {
+        self.forced_phase = None;
+    }
+
+    fn reset(&mut self) {
+        for queue in self.queue.values_mut() {
+            *queue = 0;
+        }
+        for traffic_light in &mut self.traffic_lights {
+            traffic_light.reset();
+        }
+        for crosswalk in &mut self.crosswalks {
+            crosswalk.reset();
+        }
+        self.phase_index = 0;
+        self.phase_elapsed = Duration::ZERO;
+        self.forced_phase = None;
+        self.overridden = false;
+        self.apply_progression_offset();
+    }
+
+    fn save(&self) -> ControllerSave {
+        ControllerSave::FixedTime(FixedTimeControllerSave {
+            green_time: self.green_time,
+            yellow_time: self.yellow_time,
+            plan: self.plan.clone(),
+            gap_out_enabled: self.gap_out_enabled,
+            queue: self
+                .queue
+                .iter()
+                .map(|(car, length)| (*car, *length))
+                .collect(),
+            traffic_lights: self.traffic_lights.iter().map(TrafficLight::save).collect(),
+            crosswalks: self.crosswalks.iter().map(Crosswalk::save).collect(),
+            phase_index: self.phase_index,
+            phase_elapsed: self.phase_elapsed,
+            progression_offset: self.progression_offset,
+            green_splits: self.green_splits.clone(),
+            forced_phase: self.forced_phase.clone(),
+            overridden: self.overridden,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{demand_model::DemandModel, simulation_world::SimulationWorld};
+    use std::collections::HashSet;
+
+    fn controller() -> FixedTimeController {
+        FixedTimeController::new(
+            Duration::from_secs(5),
+            Duration::from_secs(1),
+            WorldGeometry::default(),
+        )
+    }
+
+    #[test]
+    fn plans_without_phases_are_rejected() {
+        assert_eq!(
+            controller()
+                .with_plan(PhasePlan { phases: Vec::new() })
+                .err(),
+            Some(PlanError::NoPhases)
+        );
+
+        // Nothing from a lone approach has anywhere to go
+        let dead_end =
+            PhasePlan::four_phase().only_approaches(&HashSet::from([car::Origin::North]));
+        assert_eq!(
+            controller().with_plan(dead_end).err(),
+            Some(PlanError::NoPhases)
+        );
+
+        let t_intersection = PhasePlan::four_phase().only_approaches(&HashSet::from([
+            car::Origin::North,
+            car::Origin::East,
+            car::Origin::West,
+        ]));
+        assert!(controller().with_plan(t_intersection).is_ok());
+    }
+
+    #[test]
+    fn protected_lefts_never_overlap_the_opposing_straight() {
+        let mut controller = controller()
+            .with_plan(PhasePlan::protected_lefts())
+            .unwrap();
+        let origins = [
+            car::Origin::North,
+            car::Origin::South,
+            car::Origin::East,
+            car::Origin::West,
+        ];
+        let mut lefts_went = HashSet::new();
+        // Two whole cycles of 6 second phases
+        for _ in 0..2 * 4 * 6 * 120 {
+            controller.advance(Duration::from_secs_f64(1.0 / 120.0));
+            controller.update();
+            for origin in origins {
+                if !controller.is_green(origin, car::Direction::Left) {
+                    continue;
+                }
+                lefts_went.insert(origin);
+                let opposite = car::exit_leg(origin, car::Direction::Straight);
+                assert!(
+                    !controller.is_green(opposite, car::Direction::Straight),
+                    "{:?} left and {:?} straight",
+                    origin,
+                    opposite
+                );
+            }
+        }
+        assert_eq!(lefts_went.len(), 4);
+    }
+
+    #[test]
+    fn empty_green_gaps_out_after_the_minimum_green() {
+        // Updates until the North-South green turns yellow, with cars only on the cross street
+        let green_frames = |gap_out_enabled| -> usize {
+            let mut controller = controller().with_plan(PhasePlan::two_phase()).unwrap();
+            controller.gap_out_enabled = gap_out_enabled;
+            controller.add_car(SimplifiedCar::new(
+                car::Origin::East,
+                car::Direction::Straight,
+            ));
+            let mut frames = 0;
+            loop {
+                controller.advance(Duration::from_secs_f64(1.0 / 120.0));
+                controller.update();
+                if controller.light_state(car::Origin::North, car::Direction::Straight)
+                    != TrafficLightState::Green
+                {
+                    return frames;
+                }
+                frames += 1;
+            }
+        };
+        let minimum_frames = (MINIMUM_GREEN_TIME.as_secs_f64() * 120.0).round() as usize;
+        assert!(green_frames(true).abs_diff(minimum_frames) <= 1);
+        assert!(green_frames(false).abs_diff(5 * 120) <= 1);
+    }
+
+    #[test]
+    fn tuned_progression_offset_catches_the_platoon_on_green() {
+        // Lets a North platoon out every cycle, starting when the controller does, with the North
+        // green starting `offset` later. Returns how many frames cars spent stopped, and the light
+        // the first car saw as it got to the stop line.
+        let run = |offset| {
+            // Far enough back that the platoon misses a green that starts when it's let out
+            let geometry = WorldGeometry::default().with_spawn_setback(750.0);
+            let mut controller =
+                FixedTimeController::new(Duration::from_secs(1), Duration::from_secs(1), geometry)
+                    .with_plan(PhasePlan::two_phase())
+                    .unwrap();
+            controller.set_progression_offset(car::Origin::North, offset);
+            let mut world = SimulationWorld::with_controller(Box::new(controller), geometry);
+            world.random_spawns = false;
+            // Without East and West every North car goes straight
+            world.set_enabled_approaches(HashSet::from([car::Origin::North, car::Origin::South]));
+            let cycle_frames = 4 * 120;
+            world.set_demand(
+                car::Origin::North,
+                DemandModel::Burst {
+                    size: 3,
+                    interval: cycle_frames,
+                },
+            );
+            let (mut stopped_frames, mut light_on_arrival) = (0, None);
+            for _ in 0..4 * cycle_frames {
+                world.update();
+                stopped_frames += world.cars.iter().filter(|car| car.speed() == 0.0).count();
+                let arrived = world
+                    .cars
+                    .iter()
+                    .any(|car| car.distance_to_stop_line() <= car::CAR_WIDTH);
+                if light_on_arrival.is_none() && arrived {
+                    light_on_arrival = Some(
+                        world
+                            .traffic_light
+                            .light_state(car::Origin::North, car::Direction::Straight),
+                    );
+                }
+            }
+            (stopped_frames, light_on_arrival)
+        };
+
+        let (untuned_wait, untuned_light) = run(Duration::ZERO);
+        let (tuned_wait, tuned_light) = run(Duration::from_millis(1600));
+        assert_ne!(untuned_light, Some(TrafficLightState::Green));
+        assert_eq!(tuned_light, Some(TrafficLightState::Green));
+        assert!(
+            tuned_wait < untuned_wait,
+            "{} >= {}",
+            tuned_wait,
+            untuned_wait
+        );
+    }
+
+    #[test]
+    fn progression_offset_survives_a_new_plan_and_an_empty_cycle() {
+        let mut offset_first = controller();
+        offset_first.set_progression_offset(car::Origin::East, Duration::ZERO);
+        let offset_first = offset_first.with_plan(PhasePlan::two_phase()).unwrap();
+        let mut plan_first = controller().with_plan(PhasePlan::two_phase()).unwrap();
+        plan_first.set_progression_offset(car::Origin::East, Duration::ZERO);
+        // East-West is the second phase, so the cycle starts there either way
+        assert_eq!(plan_first.phase_index, 1);
+        assert_eq!(offset_first.phase_index, plan_first.phase_index);
+        assert_eq!(offset_first.phase_elapsed, plan_first.phase_elapsed);
+
+        let mut empty_cycle =
+            FixedTimeController::new(Duration::ZERO, Duration::ZERO, WorldGeometry::default());
+        empty_cycle.set_progression_offset(car::Origin::North, Duration::from_secs(1));
+        empty_cycle.advance(Duration::from_secs_f64(1.0 / 120.0));
+        empty_cycle.update();
+    }
+
+    #[test]
+    fn arterial_with_twice_the_split_gets_twice_the_green() {
+        assert!(matches!(
+            controller()
+                .with_plan(PhasePlan::four_phase())
+                .unwrap()
+                .with_green_splits(vec![0.4, 0.2, 0.2, 0.1]),
+            Err(GreenSplitError::BadSum(_))
+        ));
+
+        let mut controller = controller()
+            .with_plan(PhasePlan::four_phase())
+            .unwrap()
+            .with_green_splits(vec![0.4, 0.2, 0.2, 0.2])
+            .unwrap();
+        // No cars, so greens would gap out without this
+        controller.gap_out_enabled = false;
+        let origins = [
+            car::Origin::North,
+            car::Origin::East,
+            car::Origin::South,
+            car::Origin::West,
+        ];
+        let mut green_frames = [0; 4];
+        // One whole cycle of four 5 second greens and their yellows
+        for _ in 0..4 * 6 * 120 {
+            controller.advance(Duration::from_secs_f64(1.0 / 120.0));
+            controller.update();
+            for (i, &origin) in origins.iter().enumerate() {
+                // Not `is_green`, which counts the yellow too
+                if controller.light_state(origin, car::Direction::Straight)
+                    == TrafficLightState::Green
+                {
+                    green_frames[i] += 1;
+                }
+            }
+        }
+        for side_street in &green_frames[1..] {
+            let ratio = green_frames[0] as f64 / *side_street as f64;
+            assert!((ratio - 2.0).abs() < 0.1, "{:?}", green_frames);
+        }
+    }
+
+    #[test]
+    fn forced_phase_goes_through_yellow_and_hands_back_to_the_plan() {
+        let mut controller = controller().with_plan(PhasePlan::two_phase()).unwrap();
+        controller.gap_out_enabled = false;
+        let run = |controller: &mut FixedTimeController, frames| {
+            for _ in 0..frames {
+                controller.advance(Duration::from_secs_f64(1.0 / 120.0));
+                controller.update();
+                assert!(
+                    !(controller.is_green(car::Origin::North, car::Direction::Straight)
+                        && controller.is_green(car::Origin::East, car::Direction::Straight)),
+                    "crossing greens"
+                );
+            }
+        };
+        // A second into the North-South green
+        run(&mut controller, 120);
+        controller.force_phase(vec![SimplifiedCar::new(
+            car::Origin::East,
+            car::Direction::Straight,
+        )]);
+        run(&mut controller, 1);
+        assert_eq!(
+            controller.light_state(car::Origin::North, car::Direction::Straight),
+            TrafficLightState::Yellow
+        );
+
+        // Far past where the plan would have changed phase on its own
+        run(&mut controller, 20 * 120);
+        assert_eq!(
+            controller.light_state(car::Origin::East, car::Direction::Straight),
+            TrafficLightState::Green
+        );
+        assert_eq!(
+            controller.light_state(car::Origin::North, car::Direction::Straight),
+            TrafficLightState::Red
+        );
+
+        controller.clear_override();
+        run(&mut controller, 2 * 120);
+        assert!(!controller.overridden);
+        assert_eq!(
+            controller.light_state(car::Origin::North, car::Direction::Straight),
+            TrafficLightState::Green
+        );
+    }
+}
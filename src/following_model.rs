@@ -0,0 +1,244 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, sync::Arc};
+
+use crate::car::{ACCELERATION, DECELERATION, FOLLOWING_GAP};
+
+/// Decides how fast a car can go behind the car ahead of it. Speeds are per frame, and `gap` is
+/// the distance from the car's front bumper to the leader's rear bumper.
+pub trait FollowingModel {
+    /// Fastest the car should go next frame. 0 means it has to stop.
+    fn desired_speed(&self, gap: f64, speed: f64, leader_speed: f64, max_speed: f64) -> f64;
+}
+
+/// Keeps at least `min_gap` to the leader, and stops as soon as it can't. Until then it goes as
+/// fast as it can while still being able to slow down to the leader's speed before the gap gets
+/// smaller than `min_gap`, so a car keeping up with its leader doesn't brake, while one catching up
+/// to a stopped car starts braking further back.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ConstantGap {
+    pub min_gap: f64,
+    /// Braking used to work out how long it takes to slow down to the leader's speed
+    pub deceleration: f64,
+}
+
+impl Default for ConstantGap {
+    fn default() -> ConstantGap {
+        ConstantGap {
+            min_gap: FOLLOWING_GAP,
+            deceleration: DECELERATION,
+        }
+    }
+}
+
+impl FollowingModel for ConstantGap {
+    fn desired_speed(&self, gap: f64, _speed: f64, leader_speed: f64, max_speed: f64) -> f64 {
+        if gap < self.min_gap {
+            return 0.0;
+        }
+        (leader_speed * leader_speed + 2.0 * self.deceleration * (gap - self.min_gap))
+            .sqrt()
+            .min(max_speed)
+    }
+}
+
+/// Treiber's Intelligent Driver Model: eases off the closer the car gets to the gap it wants,
+/// which grows with its speed and how fast it's catching up
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IntelligentDriverModel {
+    /// Gap kept to a stopped leader
+    pub min_gap: f64,
+    /// Frames of headway wanted on top of `min_gap`
+    pub time_headway: f64,
+    pub acceleration: f64,
+    /// Braking the model is comfortable with. It brakes harder when it has to.
+    pub comfortable_deceleration: f64,
+}
+
+impl Default for IntelligentDriverModel {
+    fn default() -> IntelligentDriverModel {
+        IntelligentDriverModel {
+            min_gap: FOLLOWING_GAP / 2.0,
+            time_headway: 20.0,
+            acceleration: ACCELERATION,
+            comfortable_deceleration: DECELERATION,
+        }
+    }
+}
+
+impl FollowingModel for IntelligentDriverModel {
+    fn desired_speed(&self, gap: f64, speed: f64, leader_speed: f64, max_speed: f64) -> f64 {
+        if gap <= 0.0 {
+            return 0.0;
+        }
+
+        let closing_speed = speed - leader_speed;
+        let wanted_gap = self.min_gap
+            + (speed * self.time_headway
+                + speed * closing_speed
+                    / (2.0 * (self.acceleration * self.comfortable_deceleration).sqrt()))
+            .max(0.0);
+        let acceleration =
+            self.acceleration * (1.0 - (speed / max_speed).powi(4) - (wanted_gap / gap).powi(2));
+        (speed + acceleration).clamp(0.0, max_speed)
+    }
+}
+
+/// Gipps' model: goes as fast as it can while still being able to stop behind the leader if the
+/// leader brakes as hard as it expects
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Gipps {
+    /// Frames it takes the driver to react to the leader
+    pub reaction_frames: f64,
+    /// Gap kept to a stopped leader
+    pub min_gap: f64,
+    pub acceleration: f64,
+    pub deceleration: f64,
+    /// How hard the driver expects the leader to brake
+    pub leader_deceleration: f64,
+}
+
+impl Default for Gipps {
+    fn default() -> Gipps {
+        Gipps {
+            reaction_frames: 10.0,
+            min_gap: FOLLOWING_GAP / 2.0,
+            acceleration: ACCELERATION,
+            deceleration: DECELERATION,
+            leader_deceleration: DECELERATION,
+        }
+    }
+}
+
+impl FollowingModel for Gipps {
+    fn desired_speed(&self, gap: f64, speed: f64, leader_speed: f64, max_speed: f64) -> f64 {
+        let tau = self.reaction_frames;
+        let free_speed = speed
+            + 2.5
+                * self.acceleration
+                * tau
+                * (1.0 - speed / max_speed)
+                * (0.025 + speed / max_speed).sqrt();
+
+        let d = self.deceleration;
+        let root = d * d * tau * tau
+            + d * (2.0 * (gap - self.min_gap) - speed * tau
+                + leader_speed * leader_speed / self.leader_deceleration);
+        let safe_speed = if root > 0.0 {
+            -d * tau + root.sqrt()
+        } else {
+            0.0
+        };
+        free_speed.min(safe_speed).clamp(0.0, max_speed)
+    }
+}
+
+/// The following models a car can use. Cars are cloned every frame and saved with the world, so
+/// they hold one of these instead of a boxed `FollowingModel`.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum FollowingModelKind {
+    ConstantGap(ConstantGap),
+    IntelligentDriver(IntelligentDriverModel),
+    Gipps(Gipps),
+    /// Any other `FollowingModel`. These can't be saved, so saving a world with a car using one
+    /// fails.
+    #[serde(skip)]
+    Custom(Arc<dyn FollowingModel + Send + Sync>),
+}
+
+impl fmt::Debug for FollowingModelKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FollowingModelKind::ConstantGap(model) => {
+                f.debug_tuple("ConstantGap").field(model).finish()
+            }
+            FollowingModelKind::IntelligentDriver(model) => {
+                f.debug_tuple("IntelligentDriver").field(model).finish()
+            }
+            FollowingModelKind::Gipps(model) => f.debug_tuple("Gipps").field(model).finish(),
+            FollowingModelKind::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl Default for FollowingModelKind {
+    fn default() -> FollowingModelKind {
+        FollowingModelKind::ConstantGap(ConstantGap::default())
+    }
+}
+
+impl FollowingModel for FollowingModelKind {
+    fn desired_speed(&self, gap: f64, speed: f64, leader_speed: f64, max_speed: f64) -> f64 {
+        match self {
+            FollowingModelKind::ConstantGap(model) => {
+                model.desired_speed(gap, speed, leader_speed, max_speed)
+            }
+            FollowingModelKind::IntelligentDriver(model) => {
+                model.desired_speed(gap, speed, leader_speed, max_speed)
+            }
+            FollowingModelKind::Gipps(model) => {
+                model.desired_speed(gap, speed, leader_speed, max_speed)
+            }
+            FollowingModelKind::Custom(model) => {
+                model.desired_speed(gap, speed, leader_speed, max_speed)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::car::{CAR_WIDTH, MAX_SPEED};
+
+    /// Drives a platoon along a straight road behind a leader that slows down partway, speeding up
+    /// no faster than a car can. Returns the followers' final gaps and speeds.
+    fn platoon(model: &FollowingModelKind) -> (Vec<f64>, Vec<f64>) {
+        let count = 5;
+        // Front bumpers, front to back
+        let mut positions: Vec<f64> = (0..count)
+            .map(|i| -(i as f64) * (CAR_WIDTH + 2.0 * FOLLOWING_GAP))
+            .collect();
+        let mut speeds = vec![0.0; count];
+        for frame in 0..6000 {
+            speeds[0] = if frame < 2000 { 3.0 } else { 1.5 };
+            for i in 1..count {
+                let gap = positions[i - 1] - CAR_WIDTH - positions[i];
+                assert!(gap > 0.0, "{:?} car {} hit the car ahead", model, i);
+                speeds[i] = model
+                    .desired_speed(gap, speeds[i], speeds[i - 1], MAX_SPEED)
+                    .min(speeds[i] + ACCELERATION);
+            }
+            for (position, speed) in positions.iter_mut().zip(&speeds) {
+                *position += speed;
+            }
+        }
+        let gaps = positions
+            .windows(2)
+            .map(|pair| pair[0] - CAR_WIDTH - pair[1])
+            .collect();
+        (gaps, speeds[1..].to_vec())
+    }
+
+    #[test]
+    fn every_model_settles_into_a_stable_platoon() {
+        for model in [
+            FollowingModelKind::ConstantGap(ConstantGap::default()),
+            FollowingModelKind::IntelligentDriver(IntelligentDriverModel::default()),
+            FollowingModelKind::Gipps(Gipps::default()),
+            // Models from outside the crate go in the same way
+            FollowingModelKind::Custom(Arc::new(ConstantGap {
+                min_gap: 2.0 * FOLLOWING_GAP,
+                ..ConstantGap::default()
+            })),
+        ] {
+            let (gaps, speeds) = platoon(&model);
+            for speed in speeds {
+                assert!((speed - 1.5).abs() < 1e-3, "{:?} {}", model, speed);
+            }
+            // Everyone ends up the same distance behind the car ahead
+            let smallest = gaps.iter().copied().fold(f64::INFINITY, f64::min);
+            let largest = gaps.iter().copied().fold(0.0, f64::max);
+            assert!(largest - smallest < 1.0, "{:?} {:?}", model, gaps);
+        }
+    }
+}
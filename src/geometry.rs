@@ -0,0 +1,203 @@
+use piston_window::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    car::{Direction, Origin, CAR_WIDTH, LANE_WIDTH},
+    HEIGHT, WIDTH,
+};
+
+/// The lanes every approach has. Lanes are counted outwards from the middle of the road: left turn
+/// lanes first, then straight lanes, then right turn lanes. Roads have as many lanes leaving the
+/// intersection as coming into it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IntersectionLayout {
+    pub left_lanes: usize,
+    pub straight_lanes: usize,
+    pub right_lanes: usize,
+    pub lane_width: f64,
+    /// Right turns use a slip lane that skips the lights and yields to cross traffic instead
+    pub channelized_right: bool,
+}
+
+impl IntersectionLayout {
+    /// Lanes going one way on each road
+    pub fn lanes(&self) -> usize {
+        self.left_lanes + self.straight_lanes + self.right_lanes
+    }
+
+    /// Lanes straight cars can use. The first right turn lane is shared with straight cars.
+    pub fn straight_lane_count(&self) -> usize {
+        self.straight_lanes + self.right_lanes.min(1)
+    }
+
+    /// Movement that uses the lane the given number of lanes out from the middle of the road
+    pub fn lane_direction(&self, offset: usize) -> Direction {
+        if offset < self.left_lanes {
+            Direction::Left
+        } else if offset < self.left_lanes + self.straight_lanes {
+            Direction::Straight
+        } else {
+            Direction::Right
+        }
+    }
+
+    /// Number of lanes between the middle of the road and the given lane
+    pub fn lane_offset(&self, direction: Direction, lane: usize) -> usize {
+        match direction {
+            Direction::Left => lane,
+            Direction::Straight => self.left_lanes + lane,
+            Direction::Right => self.left_lanes + self.straight_lanes + lane,
+        }
+    }
+}
+
+impl Default for IntersectionLayout {
+    fn default() -> IntersectionLayout {
+        IntersectionLayout {
+            left_lanes: 1,
+            straight_lanes: 1,
+            right_lanes: 1,
+            lane_width: LANE_WIDTH,
+            channelized_right: false,
+        }
+    }
+}
+
+/// Size of the world that the intersection sits in the middle of
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WorldGeometry {
+    pub width: f64,
+    pub height: f64,
+    pub layout: IntersectionLayout,
+    /// How far off the canvas cars spawn, measured to their center. Further back gives them room
+    /// to speed up before they reach the end of a queue. Very long setbacks need more path points
+    /// to keep the gaps between them under `car::MAX_PATH_GAP`.
+    pub spawn_setback: f64,
+}
+
+impl WorldGeometry {
+    pub fn new(width: f64, height: f64) -> WorldGeometry {
+        WorldGeometry {
+            width,
+            height,
+            layout: IntersectionLayout::default(),
+            spawn_setback: CAR_WIDTH / 2.0,
+        }
+    }
+
+    pub fn with_layout(mut self, layout: IntersectionLayout) -> WorldGeometry {
+        self.layout = layout;
+        self
+    }
+
+    pub fn with_spawn_setback(mut self, spawn_setback: f64) -> WorldGeometry {
+        self.spawn_setback = spawn_setback;
+        self
+    }
+
+    /// Center of the intersection
+    pub fn middle(&self) -> (f64, f64) {
+        (self.width / 2.0, self.height / 2.0)
+    }
+
+    /// Half the width of the square in the middle where all of the roads cross
+    pub fn intersection_half_size(&self) -> f64 {
+        self.layout.lanes() as f64 * self.layout.lane_width
+    }
+}
+
+impl Default for WorldGeometry {
+    fn default() -> WorldGeometry {
+        WorldGeometry::new(WIDTH as f64, HEIGHT as f64)
+    }
+}
+
+/// Corners of the square in the middle where all of the roads cross, going clockwise from the top
+/// left
+pub fn intersection_bounds(geometry: &WorldGeometry) -> [(f64, f64); 4] {
+    let middle = geometry.middle();
+    let half_size = geometry.intersection_half_size();
+    [
+        (middle.0 - half_size, middle.1 - half_size),
+        (middle.0 + half_size, middle.1 - half_size),
+        (middle.0 + half_size, middle.1 + half_size),
+        (middle.0 - half_size, middle.1 + half_size),
+    ]
+}
+
+/// Line across the lanes coming in from the given origin that cars stop behind, from the middle of
+/// the road to the curb
+pub fn stop_line(geometry: &WorldGeometry, origin: Origin) -> ((f64, f64), (f64, f64)) {
+    let middle = geometry.middle();
+    let half_size = geometry.intersection_half_size();
+    match origin {
+        Origin::North => (
+            (middle.0, middle.1 - half_size),
+            (middle.0 - half_size, middle.1 - half_size),
+        ),
+        Origin::South => (
+            (middle.0, middle.1 + half_size),
+            (middle.0 + half_size, middle.1 + half_size),
+        ),
+        Origin::East => (
+            (middle.0 + half_size, middle.1),
+            (middle.0 + half_size, middle.1 - half_size),
+        ),
+        Origin::West => (
+            (middle.0 - half_size, middle.1),
+            (middle.0 - half_size, middle.1 + half_size),
+        ),
+    }
+}
+
+/// Outlines the intersection, for debugging
+pub fn draw_intersection_bounds(geometry: &WorldGeometry, context: &Context, graphics: &mut G2d) {
+    let bounds = intersection_bounds(geometry);
+    for i in 0..bounds.len() {
+        let (start, end) = (bounds[i], bounds[(i + 1) % bounds.len()]);
+        line_from_to(
+            [1.0, 0.0, 1.0, 1.0],
+            1.0,
+            [start.0, start.1],
+            [end.0, end.1],
+            context.transform,
+            graphics,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        car::{Car, NUM_PATH_POINTS},
+        traffic_light_controller::SimplifiedCar,
+    };
+
+    #[test]
+    fn intersection_points_of_every_lane_are_in_the_bounds() {
+        // Points on the edge count as in, give or take the tolerance
+        let tolerance = 1.0;
+        for geometry in [WorldGeometry::default(), WorldGeometry::new(1000.0, 800.0)] {
+            let [top_left, _, bottom_right, _] = intersection_bounds(&geometry);
+            for origin in [Origin::North, Origin::South, Origin::East, Origin::West] {
+                for direction in [Direction::Left, Direction::Straight, Direction::Right] {
+                    let car = SimplifiedCar::new(origin, direction);
+                    let path = Car::calculate_path(&car, 0, NUM_PATH_POINTS, &geometry);
+                    let (entry, _) = Car::intersection_indices(&path, &geometry);
+                    let (x, y) = path[entry];
+                    assert!(
+                        x >= top_left.0 - tolerance
+                            && x <= bottom_right.0 + tolerance
+                            && y >= top_left.1 - tolerance
+                            && y <= bottom_right.1 + tolerance,
+                        "{:?} {:?}: {:?}",
+                        origin,
+                        direction,
+                        (x, y)
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+pub mod benchmark;
+pub mod car;
+pub mod controller_strategy;
+pub mod crosswalk;
+pub mod csv_logger;
+pub mod demand_model;
+pub mod event_sink;
+pub mod fixed_time_controller;
+pub mod following_model;
+pub mod geometry;
+pub mod metrics;
+pub mod obstacle;
+pub mod phase_plan;
+pub mod replay;
+pub mod simulation_world;
+pub mod snapshot;
+pub mod traffic_light;
+pub mod traffic_light_controller;
+
+pub const WIDTH: u32 = 1280;
+pub const HEIGHT: u32 = 1280;
+
+pub const USE_ENTRY_TIME: bool = true;
+/// Allow cars to go into the intersection when they have a yellow light
+pub const ALLOW_GO_ON_YELLOW: bool = true;
+
+pub const YELLOW_TIME: Duration = Duration::from_millis(1500);
+pub const MINIMUM_GREEN_TIME: Duration = Duration::from_millis(200);
+/// Green time of every approach when using the fixed time controller
+pub const FIXED_GREEN_TIME: Duration = Duration::from_millis(5000);
+/// How long pedestrians get to cross a leg once all of its lights are red
+pub const WALK_TIME: Duration = Duration::from_millis(2000);
@@ -1,34 +1,24 @@
 extern crate find_folder;
 extern crate piston_window;
-use car::LANE_WIDTH;
 use piston_window::*;
-use rand::Rng;
 use std::{
+    fs::File,
+    io::{BufWriter, Write},
     path,
-    time::{self, Duration, Instant},
 };
 
-mod car;
-mod traffic_light;
-mod traffic_light_controller;
+use big_traffic_light_model::*;
 
-pub const WIDTH: u32 = 1280;
-pub const HEIGHT: u32 = 1280;
-
-pub const USE_ENTRY_TIME: bool = true;
-/// Allow cars to go into the intersection when they have a yellow light
-pub const ALLOW_GO_ON_YELLOW: bool = true;
-
-pub const YELLOW_TIME: Duration = Duration::from_millis(1500);
-pub const MINIMUM_GREEN_TIME: Duration = Duration::from_millis(200);
-
-fn draw_map(context: &Context, graphics: &mut G2d) {
-    let middle = (WIDTH as f64 / 2.0, HEIGHT as f64 / 2.0);
+fn draw_map(geometry: &geometry::WorldGeometry, context: &Context, graphics: &mut G2d) {
+    let middle = geometry.middle();
+    let half_size = geometry.intersection_half_size();
+    let lane_width = geometry.layout.lane_width;
+    let lanes = geometry.layout.lanes() as i32;
     [
         [0.0, 0.0],
-        [middle.0 + LANE_WIDTH * 3.0, 0.0],
-        [0.0, middle.1 + LANE_WIDTH * 3.0],
-        [middle.0 + LANE_WIDTH * 3.0, middle.1 + LANE_WIDTH * 3.0],
+        [middle.0 + half_size, 0.0],
+        [0.0, middle.1 + half_size],
+        [middle.0 + half_size, middle.1 + half_size],
     ]
     .iter()
     .for_each(|&start| {
@@ -37,8 +27,8 @@ fn draw_map(context: &Context, graphics: &mut G2d) {
             [
                 start[0],
                 start[1],
-                WIDTH as f64 / 2.0 - LANE_WIDTH * 3.0,
-                HEIGHT as f64 / 2.0 - LANE_WIDTH * 3.0,
+                middle.0 - half_size,
+                middle.1 - half_size,
             ],
             context.transform,
             graphics,
@@ -50,18 +40,17 @@ fn draw_map(context: &Context, graphics: &mut G2d) {
     let dash_width = 2.0;
 
     // Horizontal dashes
-    let dash_length =
-        (middle.0 - LANE_WIDTH * 3.0) / (num_dashes as f64 * (1.0 + dash_gap_percent));
+    let dash_length = (middle.0 - half_size) / (num_dashes as f64 * (1.0 + dash_gap_percent));
     let dash_gap = dash_length * dash_gap_percent;
-    for i in 0..(((middle.0 - LANE_WIDTH * 3.0) / (dash_length + dash_gap)) as u32) {
+    for i in 0..(((middle.0 - half_size) / (dash_length + dash_gap)) as u32) {
         let mut start = i as f64 * (dash_length + dash_gap) + dash_gap / 2.0;
         for _ in 0..2 {
-            for j in -2..=2 {
+            for j in -(lanes - 1)..=(lanes - 1) {
                 if j == 0 {
                     continue;
                 }
 
-                let y = middle.1 + LANE_WIDTH * j as f64;
+                let y = middle.1 + lane_width * j as f64;
                 line_from_to(
                     [1.0; 4],
                     dash_width,
@@ -71,22 +60,21 @@ fn draw_map(context: &Context, graphics: &mut G2d) {
                     graphics,
                 );
             }
-            start += middle.0 + LANE_WIDTH * 3.0;
+            start += middle.0 + half_size;
         }
     }
 
     // Vertical dashes
-    let dash_length =
-        (middle.1 - LANE_WIDTH * 3.0) / (num_dashes as f64 * (1.0 + dash_gap_percent));
+    let dash_length = (middle.1 - half_size) / (num_dashes as f64 * (1.0 + dash_gap_percent));
     let dash_gap = dash_length * dash_gap_percent;
-    for i in 0..(((middle.1 - LANE_WIDTH * 3.0) / (dash_length + dash_gap)) as u32) {
+    for i in 0..(((middle.1 - half_size) / (dash_length + dash_gap)) as u32) {
         let mut start = i as f64 * (dash_length + dash_gap) + dash_gap / 2.0;
         for _ in 0..2 {
-            for j in -2..=2 {
+            for j in -(lanes - 1)..=(lanes - 1) {
                 if j == 0 {
                     continue;
                 }
-                let x = middle.0 + LANE_WIDTH * j as f64;
+                let x = middle.0 + lane_width * j as f64;
                 line_from_to(
                     [1.0; 4],
                     dash_width,
@@ -96,7 +84,7 @@ fn draw_map(context: &Context, graphics: &mut G2d) {
                     graphics,
                 );
             }
-            start += middle.1 + LANE_WIDTH * 3.0;
+            start += middle.1 + half_size;
         }
     }
 
@@ -105,9 +93,9 @@ fn draw_map(context: &Context, graphics: &mut G2d) {
         line_from_to(
             [1.0; 4],
             dash_width,
-            [i as f64 * (middle.0 + LANE_WIDTH * 3.0), middle.1],
+            [i as f64 * (middle.0 + half_size), middle.1],
             [
-                i as f64 * (middle.0 + LANE_WIDTH * 3.0) + middle.0 - LANE_WIDTH * 3.0,
+                i as f64 * (middle.0 + half_size) + middle.0 - half_size,
                 middle.1,
             ],
             context.transform,
@@ -116,10 +104,10 @@ fn draw_map(context: &Context, graphics: &mut G2d) {
         line_from_to(
             [1.0; 4],
             dash_width,
-            [middle.0, i as f64 * (middle.1 + LANE_WIDTH * 3.0)],
+            [middle.0, i as f64 * (middle.1 + half_size)],
             [
                 middle.0,
-                i as f64 * (middle.1 + LANE_WIDTH * 3.0) + middle.0 - LANE_WIDTH * 3.0,
+                i as f64 * (middle.1 + half_size) + middle.0 - half_size,
             ],
             context.transform,
             graphics,
@@ -127,7 +115,90 @@ fn draw_map(context: &Context, graphics: &mut G2d) {
     }
 }
 
+/// Draws a stop line across the lanes coming into the intersection from each side
+fn draw_stop_lines(geometry: &geometry::WorldGeometry, context: &Context, graphics: &mut G2d) {
+    for origin in simulation_world::ORIGINS {
+        let (start, end) = geometry::stop_line(geometry, origin);
+        line_from_to(
+            [1.0; 4],
+            4.0,
+            [start.0, start.1],
+            [end.0, end.1],
+            context.transform,
+            graphics,
+        );
+    }
+}
+
+/// Colors each lane of every stop line by what its light is doing: green, yellow, light green for
+/// a permissive left turn or red
+fn draw_lights(
+    traffic_light: &dyn controller_strategy::ControllerStrategy,
+    geometry: &geometry::WorldGeometry,
+    context: &Context,
+    graphics: &mut G2d,
+) {
+    for (color, from, to) in light_bars(traffic_light, geometry) {
+        line_from_to(color, 6.0, from, to, context.transform, graphics);
+    }
+}
+
+/// The color and ends of the bar `draw_lights` draws over each lane's stop line
+fn light_bars(
+    traffic_light: &dyn controller_strategy::ControllerStrategy,
+    geometry: &geometry::WorldGeometry,
+) -> Vec<([f32; 4], [f64; 2], [f64; 2])> {
+    let lanes = geometry.layout.lanes();
+    let mut bars = Vec::new();
+    for origin in simulation_world::ORIGINS {
+        let (start, end) = geometry::stop_line(geometry, origin);
+        let point_at = |t: f64| {
+            [
+                start.0 + (end.0 - start.0) * t,
+                start.1 + (end.1 - start.1) * t,
+            ]
+        };
+        for lane in 0..lanes {
+            let direction = geometry.layout.lane_direction(lane);
+            let color = if traffic_light.is_yellow(origin, direction) {
+                [0.92, 0.95, 0.13, 1.0]
+            } else if traffic_light.is_green(origin, direction) {
+                [0.24, 0.96, 0.21, 1.0]
+            } else if direction == car::Direction::Left && traffic_light.is_permissive_left(origin)
+            {
+                [0.6, 0.96, 0.55, 1.0]
+            } else {
+                [0.96, 0.19, 0.19, 1.0]
+            };
+            bars.push((
+                color,
+                point_at(lane as f64 / lanes as f64),
+                point_at((lane + 1) as f64 / lanes as f64),
+            ));
+        }
+    }
+    bars
+}
+
 fn main() {
+    // Passing `--benchmark <ticks>` times a run without opening a window
+    if std::env::args().any(|arg| arg == "--benchmark") {
+        let ticks = std::env::args()
+            .skip_while(|arg| arg != "--benchmark")
+            .nth(1)
+            .and_then(|ticks| ticks.parse::<usize>().ok());
+        let Some(ticks) = ticks else {
+            eprintln!("Usage: --benchmark <ticks>, where ticks is a whole number");
+            std::process::exit(1);
+        };
+        let result = benchmark::run_benchmark(benchmark::BenchmarkConfig {
+            ticks,
+            ..benchmark::BenchmarkConfig::default()
+        });
+        println!("{}", result);
+        return;
+    }
+
     let mut window: PistonWindow =
         WindowSettings::new("Insersection Traffic Manager", [WIDTH, HEIGHT])
             .exit_on_esc(true)
@@ -140,41 +211,89 @@ fn main() {
         .unwrap();
     let mut glyphs: Glyphs = window.load_font(assets.join("Consolas.ttf")).unwrap();
 
-    let mut cars: Vec<car::Car> = Vec::new();
-    let mut id: usize = 0;
-
-    let mut spawn_start = Instant::now();
-    let mut spawn_increment = time::Duration::from_millis(650);
-    let origins = [
-        car::Origin::North,
-        car::Origin::South,
-        car::Origin::East,
-        car::Origin::West,
-    ];
-    let mut origin_index = 0;
+    // Passing `--fixed-time` swaps the adaptive controller for a fixed cycle. `--gap-out` lets
+    // the fixed cycle end greens early when no one is coming.
+    let mut world = if std::env::args().any(|arg| arg == "--fixed-time") {
+        let mut controller = fixed_time_controller::FixedTimeController::new(
+            FIXED_GREEN_TIME,
+            YELLOW_TIME,
+            geometry::WorldGeometry::default(),
+        );
+        controller.gap_out_enabled = std::env::args().any(|arg| arg == "--gap-out");
+        simulation_world::SimulationWorld::with_controller(
+            Box::new(controller),
+            geometry::WorldGeometry::default(),
+        )
+    } else {
+        simulation_world::SimulationWorld::new()
+    };
 
-    let mut traffic_light = traffic_light_controller::TrafficLightController::new();
+    // Passing `--replay <file>` draws a recording instead of running the simulation
+    let replay = std::env::args()
+        .skip_while(|arg| arg != "--replay")
+        .nth(1)
+        .map(|path| {
+            replay::Replay::from_file(&path).unwrap_or_else(|error| {
+                eprintln!("Couldn't read the replay {}: {}", path, error);
+                std::process::exit(1);
+            })
+        });
+    let mut replay_frame: usize = 0;
+    let mut time_scale: f64 = 1.0;
 
     let mut paused: bool = false;
-    let mut last_paused: Instant = Instant::now();
+
+    // One JSON snapshot per line, toggled with R
+    let mut recording: Option<BufWriter<File>> = None;
+    // Queue lengths over time, toggled with C
+    let mut csv_logger: Option<csv_logger::CsvLogger> = None;
+    // Outline of the intersection, toggled with B
+    let mut show_intersection_bounds = false;
+    // Stop lines colored by the state of their lights, toggled with T
+    let mut show_lights = false;
+    // Each car's id and speed, toggled with I
+    let mut show_labels = false;
 
     window.set_max_fps(60);
     while let Some(event) = window.next() {
         window.draw_2d(&event, |context, graphics, device| {
             clear([0.1; 4], graphics);
 
-            draw_map(&context, graphics);
-
-            let cars_clone = cars.clone();
-
-            cars.iter_mut()
-                .for_each(|car| car.draw(&cars_clone, &context, graphics));
+            draw_map(&world.geometry, &context, graphics);
+            draw_stop_lines(&world.geometry, &context, graphics);
+            if show_intersection_bounds {
+                geometry::draw_intersection_bounds(&world.geometry, &context, graphics);
+            }
+            if show_lights {
+                draw_lights(
+                    world.traffic_light.as_ref(),
+                    &world.geometry,
+                    &context,
+                    graphics,
+                );
+            }
 
-            traffic_light.draw(&context, graphics);
+            let info = if let Some(replay) = &replay {
+                replay.draw_frame(replay_frame, &context, graphics);
+                format!("Frame: {}/{}", replay_frame, replay.len())
+            } else {
+                world.draw(&context, graphics);
+                if show_labels {
+                    world.draw_labels(&mut glyphs, &context, graphics);
+                }
+                if world.detect_gridlock(car::POSITION_HISTORY_LENGTH) {
+                    format!(
+                        "Gridlock: {:?}",
+                        world.gridlocked_cars(car::POSITION_HISTORY_LENGTH)
+                    )
+                } else {
+                    format!("Spawn increment: {:?}", world.spawn_increment)
+                }
+            };
 
             text::Text::new_color([0.0, 0.0, 0.0, 1.0], 20)
                 .draw(
-                    format!("Spawn increment: {:?}", spawn_increment).as_str(),
+                    info.as_str(),
                     &mut glyphs,
                     &context.draw_state,
                     context.transform.trans(20.0, 35.0),
@@ -184,50 +303,17 @@ fn main() {
             glyphs.factory.encoder.flush(device);
         });
 
-        if let Some(args) = event.update_args() {
-            if !paused {
-                // To fix waiting times not be consistent at lower frame rates, if the dt is ever
-                // different than the target dt, we can add/subtract from all of the delays
-                let target_dt = 1.0 / 120.0;
-                let dt = args.dt;
-                // When the actual dt is greater than the target dt, we need to add to the delays
-                traffic_light.fix_delays(dt, target_dt);
-
-                let cars_clone = cars.clone();
-                traffic_light.update();
-
-                if spawn_start.elapsed() >= spawn_increment {
-                    let minimum_time = 300.0;
-                    spawn_increment = time::Duration::from_millis(
-                        (spawn_increment.as_millis() as f64 * 0.997).max(minimum_time) as u64,
-                    );
-
-                    let mut origin = origins[rand::thread_rng().gen_range(0..origins.len())];
-                    if spawn_increment.as_millis() <= 600 {
-                        origin = origins[origin_index];
-                        origin_index = (origin_index + 1) % origins.len();
-                    }
-                    let direction = car::Direction::from(rand::thread_rng().gen_range(0..=2));
-                    cars.push(car::Car::new(id, origin, direction));
-                    traffic_light.add_car(traffic_light_controller::SimplifiedCar::new(
-                        origin, direction,
-                    ));
-                    id += 1;
-                    if id > 1000 {
-                        id = 0;
-                    }
-
-                    spawn_start = time::Instant::now();
+        if event.update_args().is_some() {
+            if let Some(replay) = &replay {
+                if !paused && replay_frame + 1 < replay.len() {
+                    replay_frame += 1;
                 }
-
-                cars.iter_mut().for_each(|car| {
-                    car.update(&cars_clone, &mut traffic_light);
-                });
-
-                for i in (0..cars.len()).rev() {
-                    if cars[i].finished {
-                        cars.remove(i);
-                    }
+            } else if world.tick() {
+                if let Some(recording) = &mut recording {
+                    writeln!(recording, "{}", world.snapshot().to_json()).unwrap();
+                }
+                if let Some(csv_logger) = &mut csv_logger {
+                    csv_logger.log(&world).unwrap();
                 }
             }
         }
@@ -238,13 +324,93 @@ fn main() {
             if let Button::Keyboard(key) = button.button {
                 match key {
                     Key::Space => {
+                        paused = !paused;
                         if paused {
-                            paused = false;
-                            traffic_light.unpause(last_paused.elapsed());
-                            spawn_start += last_paused.elapsed();
+                            world.pause();
+                        } else {
+                            world.resume();
+                        }
+                    }
+                    // Step through a paused replay or simulation
+                    Key::Right if paused => {
+                        if let Some(replay) = &replay {
+                            replay_frame = (replay_frame + 1).min(replay.len().saturating_sub(1));
                         } else {
-                            last_paused = Instant::now();
-                            paused = true;
+                            world.step_once();
+                        }
+                    }
+                    Key::Left if paused => {
+                        replay_frame = replay_frame.saturating_sub(1);
+                    }
+                    // Speed the simulation up or down
+                    Key::Up => {
+                        time_scale = (time_scale * 2.0).min(8.0);
+                        world.set_time_scale(time_scale);
+                    }
+                    Key::Down => {
+                        time_scale = (time_scale / 2.0).max(0.125);
+                        world.set_time_scale(time_scale);
+                    }
+                    // Cycle how the cars are colored
+                    Key::D => {
+                        world.draw_mode = world.draw_mode.next();
+                    }
+                    // Save the run, or pick up the last saved one
+                    Key::S => {
+                        if let Err(error) = world.save("save.json") {
+                            eprintln!("Couldn't save to save.json: {}", error);
+                        }
+                    }
+                    // A missing or broken save leaves the current run going
+                    Key::L => match simulation_world::SimulationWorld::load("save.json") {
+                        Ok(loaded) => {
+                            world = loaded;
+                            world.set_time_scale(time_scale);
+                            if paused {
+                                world.pause();
+                            }
+                        }
+                        Err(error) => eprintln!("Couldn't load save.json: {}", error),
+                    },
+                    // Show where each car will be in a second
+                    Key::G => {
+                        world.ghost_frames = match world.ghost_frames {
+                            Some(_) => None,
+                            None => Some(60),
+                        };
+                    }
+                    // Show where each car was over the last second
+                    Key::H => {
+                        world.trail_frames = match world.trail_frames {
+                            Some(_) => None,
+                            None => Some(120),
+                        };
+                    }
+                    Key::B => {
+                        show_intersection_bounds = !show_intersection_bounds;
+                    }
+                    Key::T => {
+                        show_lights = !show_lights;
+                    }
+                    Key::P => {
+                        world.show_paths = !world.show_paths;
+                    }
+                    Key::I => {
+                        show_labels = !show_labels;
+                    }
+                    Key::C => {
+                        if csv_logger.is_some() {
+                            csv_logger = None;
+                        } else {
+                            csv_logger = Some(csv_logger::CsvLogger::new("log.csv").unwrap());
+                        }
+                    }
+                    Key::R => {
+                        if recording.is_some() {
+                            recording = None;
+                        } else {
+                            recording =
+                                Some(BufWriter::new(File::create("recording.jsonl").unwrap()));
                         }
                     }
                     _ => (),
@@ -253,3 +419,86 @@ fn main() {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        controller_strategy::{ControllerSave, ControllerStrategy},
+        traffic_light::TrafficLightState,
+        traffic_light_controller::SimplifiedCar,
+    };
+    use std::{cell::RefCell, collections::HashSet, time::Duration};
+
+    /// Lights that are green for North and red for everyone else, and remember who was asked about
+    struct RecordingLights {
+        asked: RefCell<HashSet<car::Origin>>,
+    }
+
+    impl ControllerStrategy for RecordingLights {
+        fn advance(&mut self, _dt: Duration) {}
+
+        fn update(&mut self) {}
+
+        fn draw(&self, _context: &Context, _graphics: &mut G2d) {}
+
+        fn add_car(&mut self, _car: SimplifiedCar) {}
+
+        fn remove_car(&mut self, _car: SimplifiedCar) {}
+
+        fn is_green(&self, origin: car::Origin, direction: car::Direction) -> bool {
+            self.light_state(origin, direction) != TrafficLightState::Red
+        }
+
+        fn is_yellow(&self, origin: car::Origin, direction: car::Direction) -> bool {
+            self.light_state(origin, direction) == TrafficLightState::Yellow
+        }
+
+        fn is_walk(&self, _origin: car::Origin) -> bool {
+            false
+        }
+
+        fn light_state(
+            &self,
+            origin: car::Origin,
+            _direction: car::Direction,
+        ) -> TrafficLightState {
+            self.asked.borrow_mut().insert(origin);
+            if origin == car::Origin::North {
+                TrafficLightState::Green
+            } else {
+                TrafficLightState::Red
+            }
+        }
+
+        fn reset(&mut self) {}
+
+        fn save(&self) -> ControllerSave {
+            traffic_light_controller::TrafficLightController::new(
+                geometry::WorldGeometry::default(),
+            )
+            .save()
+        }
+    }
+
+    #[test]
+    fn light_bars_ask_about_every_approach() {
+        let traffic_light = RecordingLights {
+            asked: RefCell::new(HashSet::new()),
+        };
+        let geometry = geometry::WorldGeometry::default();
+        let bars = light_bars(&traffic_light, &geometry);
+        assert_eq!(
+            *traffic_light.asked.borrow(),
+            simulation_world::ORIGINS.into_iter().collect()
+        );
+
+        // A bar for every lane, green only on North's
+        let lanes = geometry.layout.lanes();
+        assert_eq!(bars.len(), 4 * lanes);
+        for (i, (color, _, _)) in bars.iter().enumerate() {
+            let north = simulation_world::ORIGINS[i / lanes] == car::Origin::North;
+            assert_eq!(*color == [0.24, 0.96, 0.21, 1.0], north);
+        }
+    }
+}
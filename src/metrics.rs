@@ -0,0 +1,305 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    car::{self, Car},
+    controller_strategy::ControllerStrategy,
+    simulation_world::ORIGINS,
+};
+
+/// Approaches that get less than this share of the green time, compared to their share of the
+/// cars, count as starved
+pub const STARVATION_THRESHOLD: f64 = 0.5;
+
+const DIRECTIONS: [car::Direction; 3] = [
+    car::Direction::Left,
+    car::Direction::Straight,
+    car::Direction::Right,
+];
+
+/// Running totals about the run. Per approach totals are indexed by `Origin as usize`, so they go
+/// north, south, east, west.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Metrics {
+    /// Cars that finish before this frame aren't counted, since the queues haven't built up yet
+    pub warmup_frames: usize,
+    od_matrix: [[usize; 4]; 4],
+    /// Number of cars counted
+    cars: usize,
+    /// Frames the counted cars spent standing still
+    total_wait_frames: f64,
+    /// Frames each approach had at least one green light
+    green_frames: [usize; 4],
+    /// Cars that spawned on each approach
+    spawns: [usize; 4],
+    /// Counted cars for each approach and `Direction as usize`
+    movement_cars: [[usize; 3]; 4],
+    /// Times the counted cars had to stop, for each approach and `Direction as usize`
+    movement_stops: [[usize; 3]; 4],
+    /// Frames cars crossed the stop line on, for each approach and `Direction as usize`
+    crossings: [[Vec<usize>; 3]; 4],
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    pub fn with_warmup(warmup_frames: usize) -> Metrics {
+        Metrics {
+            warmup_frames,
+            ..Metrics::default()
+        }
+    }
+
+    /// Returns if cars finishing on the given frame are ignored
+    pub fn is_warming_up(&self, frame: usize) -> bool {
+        frame < self.warmup_frames
+    }
+
+    /// Counts which approaches have a green on the given frame
+    pub fn record_frame(&mut self, frame: usize, traffic_light: &dyn ControllerStrategy) {
+        if self.is_warming_up(frame) {
+            return;
+        }
+        for origin in ORIGINS {
+            if DIRECTIONS
+                .iter()
+                .any(|&direction| traffic_light.is_green(origin, direction))
+            {
+                self.green_frames[origin as usize] += 1;
+            }
+        }
+    }
+
+    /// Counts a car that spawned on the given frame
+    pub fn record_spawn(&mut self, origin: car::Origin, frame: usize) {
+        if self.is_warming_up(frame) {
+            return;
+        }
+        self.spawns[origin as usize] += 1;
+    }
+
+    /// Notes that a car making the movement crossed the stop line on the given frame
+    pub fn record_crossing(
+        &mut self,
+        origin: car::Origin,
+        direction: car::Direction,
+        frame: usize,
+    ) {
+        if self.is_warming_up(frame) {
+            return;
+        }
+        self.crossings[origin as usize][direction as usize].push(frame);
+    }
+
+    /// Frames between each car making the movement crossing the stop line and the one before it.
+    /// Once a queue has been going for a few cars this settles at the saturation headway.
+    pub fn headways(&self, origin: car::Origin, direction: car::Direction) -> Vec<f64> {
+        self.crossings[origin as usize][direction as usize]
+            .windows(2)
+            .map(|frames| (frames[1] - frames[0]) as f64)
+            .collect()
+    }
+
+    /// Counts a car that made it all the way through on the given frame
+    pub fn record_finish(&mut self, car: &Car, frame: usize) {
+        if self.is_warming_up(frame) {
+            return;
+        }
+        self.od_matrix[car.origin as usize][car.destination() as usize] += 1;
+        self.cars += 1;
+        self.total_wait_frames += car.wait_frames();
+        self.movement_cars[car.origin as usize][car.direction() as usize] += 1;
+        self.movement_stops[car.origin as usize][car.direction() as usize] += car.stop_count();
+    }
+
+    /// Number of cars that went from each approach (row) to each exit (column)
+    pub fn od_matrix(&self) -> [[usize; 4]; 4] {
+        self.od_matrix
+    }
+
+    /// Average frames a car spent standing still, or 0 if no cars have been counted
+    pub fn average_wait(&self) -> f64 {
+        if self.cars == 0 {
+            return 0.0;
+        }
+        self.total_wait_frames / self.cars as f64
+    }
+
+    /// Average number of times a car making the movement had to stop, or 0 if no cars making it
+    /// have been counted
+    pub fn average_stops(&self, origin: car::Origin, direction: car::Direction) -> f64 {
+        let cars = self.movement_cars[origin as usize][direction as usize];
+        if cars == 0 {
+            return 0.0;
+        }
+        self.movement_stops[origin as usize][direction as usize] as f64 / cars as f64
+    }
+
+    /// For each approach that had cars, its share of the green time divided by its share of the
+    /// cars. Approaches that got their fair share of green are around 1.
+    pub fn starvation_report(&self) -> Vec<(car::Origin, f64)> {
+        let total_green = self.green_frames.iter().sum::<usize>() as f64;
+        let total_spawns = self.spawns.iter().sum::<usize>() as f64;
+        ORIGINS
+            .iter()
+            .filter(|&&origin| self.spawns[origin as usize] > 0)
+            .map(|&origin| {
+                let green_share = if total_green > 0.0 {
+                    self.green_frames[origin as usize] as f64 / total_green
+                } else {
+                    0.0
+                };
+                let demand_share = self.spawns[origin as usize] as f64 / total_spawns;
+                (origin, green_share / demand_share)
+            })
+            .collect()
+    }
+
+    /// Approaches whose ratio in `starvation_report` is below `STARVATION_THRESHOLD`
+    pub fn starved_approaches(&self) -> Vec<car::Origin> {
+        self.starvation_report()
+            .into_iter()
+            .filter(|&(_, ratio)| ratio < STARVATION_THRESHOLD)
+            .map(|(origin, _)| origin)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        car::{CarBuilder, DrivingRules},
+        fixed_time_controller::FixedTimeController,
+        geometry::WorldGeometry,
+        phase_plan::PhasePlan,
+        simulation_world::SimulationWorld,
+        traffic_light_controller::{SimplifiedCar, TrafficLightController},
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn north_left_turns_all_count_as_north_to_east() {
+        let mut metrics = Metrics::new();
+        for id in 0..100 {
+            let car = CarBuilder::new(id, car::Origin::North, car::Direction::Left).build();
+            // Coming down from the top and turning left leaves on the right of the screen
+            assert!(car.path().last().unwrap().0 > car.position().0);
+            metrics.record_finish(&car, 0);
+        }
+        let od_matrix = metrics.od_matrix();
+        assert_eq!(
+            od_matrix[car::Origin::North as usize][car::Origin::East as usize],
+            100
+        );
+        assert_eq!(od_matrix.iter().flatten().sum::<usize>(), 100);
+    }
+
+    #[test]
+    fn cars_finishing_in_the_warmup_are_not_averaged() {
+        let mut traffic_light = TrafficLightController::new(WorldGeometry::default());
+        let rules = DrivingRules {
+            speed_limit: car::MAX_SPEED,
+            allow_right_on_red: false,
+            wait_for_clear_intersection: false,
+            frame: 0,
+            obstacles: &[],
+        };
+        // Cars start out stopped, so this one has waited a frame
+        let mut waited = CarBuilder::new(0, car::Origin::North, car::Direction::Straight).build();
+        waited.update(&Vec::new(), &mut traffic_light, &rules, 1.0);
+        assert!(waited.wait_frames() > 0.0);
+        let on_time = CarBuilder::new(1, car::Origin::South, car::Direction::Straight).build();
+
+        let mut metrics = Metrics::with_warmup(100);
+        metrics.record_finish(&waited, 99);
+        metrics.record_finish(&on_time, 100);
+        assert_eq!(metrics.average_wait(), 0.0);
+        assert_eq!(metrics.od_matrix().iter().flatten().sum::<usize>(), 1);
+    }
+
+    #[test]
+    fn approach_the_controller_ignores_is_starved() {
+        // Every approach but West gets a turn
+        let plan = PhasePlan {
+            phases: PhasePlan::four_phase()
+                .phases
+                .into_iter()
+                .filter(|phase| {
+                    phase
+                        .iter()
+                        .all(|movement| movement.origin != car::Origin::West)
+                })
+                .collect(),
+        };
+        let mut controller = FixedTimeController::new(
+            Duration::from_secs(5),
+            Duration::from_secs(1),
+            WorldGeometry::default(),
+        )
+        .with_plan(plan)
+        .unwrap();
+        let mut metrics = Metrics::new();
+        for frame in 0..10_000 {
+            controller.advance(Duration::from_secs_f64(1.0 / 120.0));
+            controller.update();
+            metrics.record_frame(frame, &controller);
+            if frame % 100 == 0 {
+                for origin in ORIGINS {
+                    metrics.record_spawn(origin, frame);
+                }
+            }
+        }
+
+        assert_eq!(metrics.starved_approaches(), vec![car::Origin::West]);
+        let report = metrics.starvation_report();
+        assert_eq!(report.len(), 4);
+        for (origin, ratio) in report {
+            if origin == car::Origin::West {
+                assert_eq!(ratio, 0.0);
+            } else {
+                assert!(ratio > 1.0, "{:?}: {}", origin, ratio);
+            }
+        }
+    }
+
+    #[test]
+    fn saturated_headways_settle_after_the_first_few_cars() {
+        // Far enough back for the whole queue to fit before the light changes
+        let mut world =
+            SimulationWorld::with_geometry(WorldGeometry::default().with_spawn_setback(300.0));
+        world.random_spawns = false;
+        world.traffic_light.force_phase(Vec::new());
+        // Left turners, so the queue doesn't split over two lanes
+        for i in 0..8 {
+            world
+                .schedule_spawn(i * 20, car::Origin::North, car::Direction::Left, 0)
+                .unwrap();
+        }
+        for _ in 0..1200 {
+            world.update();
+        }
+        world.traffic_light.force_phase(vec![SimplifiedCar::new(
+            car::Origin::North,
+            car::Direction::Left,
+        )]);
+        for _ in 0..1200 {
+            world.update();
+        }
+
+        let headways = world
+            .metrics
+            .headways(car::Origin::North, car::Direction::Left);
+        assert_eq!(headways.len(), 7);
+        // The first car loses time getting going, then the queue discharges at a steady rate
+        let saturation = &headways[1..];
+        let (min, max) = saturation
+            .iter()
+            .fold((f64::MAX, f64::MIN), |(min, max), &h| {
+                (min.min(h), max.max(h))
+            });
+        assert!(max - min <= 2.0, "{:?}", headways);
+        assert!(headways[0] > max, "{:?}", headways);
+    }
+}
@@ -0,0 +1,50 @@
+use piston_window::*;
+use serde::{Deserialize, Serialize};
+
+/// Something standing still in the road, like a construction zone or a stalled car. Cars stop
+/// behind it the same way they would behind a stopped car, but it never moves or finishes.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Obstacle {
+    /// Corners of the obstacle, in order around its outline
+    pub vertices: [(f64, f64); 4],
+}
+
+impl Obstacle {
+    pub fn new(vertices: [(f64, f64); 4]) -> Obstacle {
+        Obstacle { vertices }
+    }
+
+    /// Axis aligned obstacle centered on `center`
+    pub fn rect(center: (f64, f64), width: f64, height: f64) -> Obstacle {
+        let (x, y) = center;
+        let (half_width, half_height) = (width / 2.0, height / 2.0);
+        Obstacle::new([
+            (x - half_width, y - half_height),
+            (x + half_width, y - half_height),
+            (x + half_width, y + half_height),
+            (x - half_width, y + half_height),
+        ])
+    }
+
+    pub fn center(&self) -> (f64, f64) {
+        let (x, y) = self
+            .vertices
+            .iter()
+            .fold((0.0, 0.0), |(x, y), &(vx, vy)| (x + vx, y + vy));
+        (x / 4.0, y / 4.0)
+    }
+
+    /// Distance from the center to the furthest corner
+    pub fn radius(&self) -> f64 {
+        let (x, y) = self.center();
+        self.vertices
+            .iter()
+            .map(|&(vx, vy)| (vx - x).hypot(vy - y))
+            .fold(0.0, f64::max)
+    }
+
+    pub fn draw(&self, context: &Context, graphics: &mut G2d) {
+        let points = self.vertices.map(|(x, y)| [x, y]);
+        polygon([1.0, 0.5, 0.0, 1.0], &points, context.transform, graphics);
+    }
+}
@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::{
+    car::{exit_leg, Direction, Origin},
+    traffic_light_controller::SimplifiedCar,
+};
+
+/// The movements that get a green together in each phase, in the order the phases run
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PhasePlan {
+    pub phases: Vec<Vec<SimplifiedCar>>,
+}
+
+impl PhasePlan {
+    /// North and south go together, then east and west. Left turns don't get a green of their
+    /// own, so they go permissively on the straight green.
+    pub fn two_phase() -> PhasePlan {
+        PhasePlan {
+            phases: vec![
+                movements(
+                    &[Origin::North, Origin::South],
+                    &[Direction::Straight, Direction::Right],
+                ),
+                movements(
+                    &[Origin::East, Origin::West],
+                    &[Direction::Straight, Direction::Right],
+                ),
+            ],
+        }
+    }
+
+    /// Each approach gets the whole intersection to itself in turn: north, east, south, west
+    pub fn four_phase() -> PhasePlan {
+        PhasePlan {
+            phases: [Origin::North, Origin::East, Origin::South, Origin::West]
+                .iter()
+                .map(|&origin| movements(&[origin], &DIRECTIONS))
+                .collect(),
+        }
+    }
+
+    /// Opposing approaches go together, with protected left turns before their straights. Both
+    /// lefts of a road share their phase, so this is four phases rather than a full eight.
+    pub fn protected_lefts() -> PhasePlan {
+        PhasePlan {
+            phases: vec![
+                movements(&[Origin::North, Origin::South], &[Direction::Left]),
+                movements(
+                    &[Origin::North, Origin::South],
+                    &[Direction::Straight, Direction::Right],
+                ),
+                movements(&[Origin::East, Origin::West], &[Direction::Left]),
+                movements(
+                    &[Origin::East, Origin::West],
+                    &[Direction::Straight, Direction::Right],
+                ),
+            ],
+        }
+    }
+
+    /// Drops the movements that come from or exit through an approach that isn't in `approaches`,
+    /// and any phases left with nothing in them. Used for intersections with less than four legs.
+    pub fn only_approaches(mut self, approaches: &HashSet<Origin>) -> PhasePlan {
+        for phase in &mut self.phases {
+            phase.retain(|movement| {
+                approaches.contains(&movement.origin)
+                    && approaches.contains(&exit_leg(movement.origin, movement.direction))
+            });
+        }
+        self.phases.retain(|phase| !phase.is_empty());
+        self
+    }
+
+    /// Returns if the movement is green in the given phase
+    pub fn is_green_in(&self, phase_index: usize, origin: Origin, direction: Direction) -> bool {
+        self.phases[phase_index].contains(&SimplifiedCar::new(origin, direction))
+    }
+}
+
+impl Default for PhasePlan {
+    fn default() -> PhasePlan {
+        PhasePlan::four_phase()
+    }
+}
+
+const DIRECTIONS: [Direction; 3] = [Direction::Left, Direction::Straight, Direction::Right];
+
+/// Every combination of the origins and directions
+fn movements(origins: &[Origin], directions: &[Direction]) -> Vec<SimplifiedCar> {
+    origins
+        .iter()
+        .flat_map(|&origin| {
+            directions
+                .iter()
+                .map(move |&direction| SimplifiedCar::new(origin, direction))
+        })
+        .collect()
+}
@@ -0,0 +1,80 @@
+use piston_window::*;
+use std::{fs, io, path::Path};
+
+use crate::{car::Car, snapshot::FrameSnapshot};
+
+/// A recorded run that can be drawn frame by frame without running the simulation
+pub struct Replay {
+    pub frames: Vec<FrameSnapshot>,
+}
+
+impl Replay {
+    /// Loads a recording made of one JSON snapshot per line
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Replay> {
+        let frames = fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line)
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+            })
+            .collect::<io::Result<Vec<FrameSnapshot>>>()?;
+        Ok(Replay { frames })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn draw_frame(&self, index: usize, context: &Context, graphics: &mut G2d) {
+        let Some(frame) = self.frames.get(index) else {
+            return;
+        };
+
+        for car in &frame.cars {
+            let fill_color = if frame
+                .cars
+                .iter()
+                .filter(|c| c.id != car.id)
+                .any(|c| car.overlaps(c))
+            {
+                [1.0, 0.0, 0.0, 1.0]
+            } else {
+                car.color
+            };
+            Car::draw_with_pos_and_rot(
+                car.position,
+                car.rotation,
+                car.length,
+                car.direction,
+                fill_color,
+                context,
+                graphics,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation_world::SimulationWorld;
+
+    #[test]
+    fn truncated_recording_is_an_error() {
+        let mut world = SimulationWorld::new();
+        world.update();
+        let line = world.snapshot().to_json();
+        let path = std::env::temp_dir().join("big_traffic_light_model_truncated_replay.jsonl");
+        fs::write(&path, format!("{}\n{}", line, &line[..line.len() / 2])).unwrap();
+        let replay = Replay::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(replay.err().unwrap().kind(), io::ErrorKind::InvalidData);
+
+        assert!(Replay::from_file("no_such_recording.jsonl").is_err());
+    }
+}
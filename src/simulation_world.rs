@@ -0,0 +1,2028 @@
+use piston_window::*;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    car::{self, Car, CarBuilder, DrawMode, DrivingRules, MovementError},
+    controller_strategy::{ControllerSave, ControllerStrategy},
+    demand_model::DemandModel,
+    event_sink::{EventSink, NoopEventSink},
+    geometry::WorldGeometry,
+    metrics::Metrics,
+    obstacle::Obstacle,
+    phase_plan::PhasePlan,
+    snapshot::FrameSnapshot,
+    traffic_light_controller::{SimplifiedCar, TrafficLightController},
+};
+
+pub const ORIGINS: [car::Origin; 4] = [
+    car::Origin::North,
+    car::Origin::South,
+    car::Origin::East,
+    car::Origin::West,
+];
+
+/// Time between random spawns when the simulation starts. It shrinks as the simulation goes on.
+const INITIAL_SPAWN_INCREMENT: Duration = Duration::from_millis(650);
+/// Seconds of simulated time in an update at a time scale of 1
+const TARGET_DT: f64 = 1.0 / 120.0;
+
+/// A car to add on a given frame instead of at random
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct ScheduledSpawn {
+    frame: usize,
+    origin: car::Origin,
+    direction: car::Direction,
+    lane: usize,
+}
+
+/// Whether `SimulationWorld::tick` moves the simulation on
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum RunState {
+    Running,
+    Paused,
+    /// Advances one frame on the next tick, then pauses
+    SingleStep,
+}
+
+/// What happens to cars that are ready to spawn when the world already has `max_cars` cars
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum CapacityPolicy {
+    /// Throw the car away and count it in `dropped_spawns`
+    Drop,
+    /// Keep the car waiting until there's room for it
+    Queue,
+}
+
+/// What happens when a car gets to the end of its path
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum BoundaryBehavior {
+    /// The car leaves the world
+    Despawn,
+    /// The car comes back in where it started, doing the same movement in the same lane
+    Wrap,
+    /// A car doing a random movement comes in to take its place
+    Respawn,
+}
+
+/// Everything needed to pick a run back up. Left out are the event sink, the drawing options
+/// (`draw_mode`, `ghost_frames`, `trail_frames` and `show_paths`), `retain_finished` and the
+/// retained finished cars, and the lane order from the last `check_lane_ordering`.
+#[derive(Serialize, Deserialize)]
+struct WorldSave {
+    cars: Vec<Car>,
+    pending_spawns: Vec<Car>,
+    scheduled_spawns: Vec<ScheduledSpawn>,
+    random_spawns: bool,
+    controller: ControllerSave,
+    geometry: WorldGeometry,
+    frame: usize,
+    total_finished: usize,
+    metrics: Metrics,
+    spawn_increment: Duration,
+    spawn_elapsed: Duration,
+    time_scale: f64,
+    speed_limits: Vec<(car::Origin, f64)>,
+    demand: Vec<(car::Origin, DemandModel)>,
+    enabled_approaches: Vec<car::Origin>,
+    allow_right_on_red: bool,
+    wait_for_clear_intersection: bool,
+    compliance: f64,
+    max_cars: Option<usize>,
+    capacity_policy: CapacityPolicy,
+    dropped_spawns: usize,
+    boundary_behavior: BoundaryBehavior,
+    finish_escaped_cars: bool,
+    obstacles: Vec<Obstacle>,
+    frozen_cars: Vec<usize>,
+    /// Overlaps that have already been reported, so they aren't reported again after loading
+    collisions: Vec<(usize, usize)>,
+    obstacle_collisions: Vec<(usize, usize)>,
+    run_state: RunState,
+    origin_index: usize,
+    id: usize,
+    /// The random number generator is saved as its seed and how far along its stream it is
+    rng_seed: [u8; 32],
+    rng_word_pos: u128,
+}
+
+/// Everything that makes up the running simulation: the cars, the traffic lights and the spawner.
+pub struct SimulationWorld {
+    /// Kept in ascending id order by `update`, so runs don't depend on the order cars were added in
+    pub cars: Vec<Car>,
+    pub traffic_light: Box<dyn ControllerStrategy>,
+    pub geometry: WorldGeometry,
+    /// Number of updates since the simulation started
+    pub frame: usize,
+    /// Number of cars that made it all the way through
+    pub total_finished: usize,
+    pub metrics: Metrics,
+    /// Keeps cars around in `finished_cars` after they make it through, for looking at after
+    /// the run. They don't move or get in anyone's way anymore.
+    pub retain_finished: bool,
+    finished_cars: Vec<Car>,
+    /// Finishes cars that drove off the canvas without finishing (see `escaped_cars`) instead of
+    /// leaving them driving forever, and tells the event sink about them
+    pub finish_escaped_cars: bool,
+    pub spawn_increment: Duration,
+    /// Spawns cars at random every `spawn_increment`. Turn off to only get scheduled cars.
+    pub random_spawns: bool,
+    /// How many frames of simulation time pass per update
+    time_scale: f64,
+    /// Speed limit of each approach. Approaches without one only have `car::MAX_SPEED`.
+    speed_limits: HashMap<car::Origin, f64>,
+    /// How cars arrive at each approach, on top of the random spawns
+    demand: HashMap<car::Origin, DemandModel>,
+    /// Legs of the intersection that exist. Cars only come from and go to these.
+    enabled_approaches: HashSet<car::Origin>,
+    /// Every random choice the world makes comes from here, so runs with the same seed match
+    rng: ChaCha12Rng,
+    pub allow_right_on_red: bool,
+    pub wait_for_clear_intersection: bool,
+    /// Chance each new driver stops for yellows and reds. The rest run lights that only just
+    /// changed.
+    pub compliance: f64,
+    /// Most cars allowed in the world at once, if there's a limit
+    pub max_cars: Option<usize>,
+    /// What to do with new cars once there are `max_cars` of them
+    pub capacity_policy: CapacityPolicy,
+    /// Cars thrown away by `CapacityPolicy::Drop`
+    dropped_spawns: usize,
+    /// What happens to cars that finish. `Wrap` and `Respawn` keep the number of cars the same,
+    /// counting the ones waiting to spawn.
+    pub boundary_behavior: BoundaryBehavior,
+    pub draw_mode: DrawMode,
+    /// How many frames ahead to draw a ghost of each car, if at all
+    pub ghost_frames: Option<usize>,
+    /// How many frames back to draw a trail behind each car, if at all
+    pub trail_frames: Option<usize>,
+    /// Draws the path of every car, for debugging turns
+    pub show_paths: bool,
+    /// Gets told when cars spawn, go into the intersection, collide and finish
+    event_sink: Box<dyn EventSink>,
+    /// Ids of the pairs of cars that are currently overlapping, lowest id first
+    collisions: HashSet<(usize, usize)>,
+    /// Things blocking the road. They stay put through `reset`.
+    obstacles: Vec<Obstacle>,
+    /// Car ids and obstacle indices of the cars that are currently overlapping an obstacle
+    obstacle_collisions: HashSet<(usize, usize)>,
+    run_state: RunState,
+    /// Ids of the cars pinned in place by `freeze_car`
+    frozen_cars: HashSet<usize>,
+    /// Ids of the cars in each lane, front to back, as of the last `check_lane_ordering`
+    lane_order: HashMap<(car::Origin, car::Direction, usize), Vec<usize>>,
+    /// Simulated time since the last random spawn
+    spawn_elapsed: Duration,
+    /// Cars waiting for their spawn point to be clear
+    pending_spawns: VecDeque<Car>,
+    /// Cars from `schedule_spawn` that haven't been added yet
+    scheduled_spawns: Vec<ScheduledSpawn>,
+    origin_index: usize,
+    id: usize,
+}
+
+impl Default for SimulationWorld {
+    fn default() -> SimulationWorld {
+        SimulationWorld::new()
+    }
+}
+
+impl SimulationWorld {
+    pub fn new() -> SimulationWorld {
+        SimulationWorld::with_geometry(WorldGeometry::default())
+    }
+
+    pub fn with_geometry(geometry: WorldGeometry) -> SimulationWorld {
+        SimulationWorld::with_controller(Box::new(TrafficLightController::new(geometry)), geometry)
+    }
+
+    /// The controller should have been made with the same geometry
+    pub fn with_controller(
+        traffic_light: Box<dyn ControllerStrategy>,
+        geometry: WorldGeometry,
+    ) -> SimulationWorld {
+        SimulationWorld {
+            cars: Vec::new(),
+            traffic_light,
+            geometry,
+            frame: 0,
+            total_finished: 0,
+            metrics: Metrics::new(),
+            retain_finished: false,
+            finished_cars: Vec::new(),
+            finish_escaped_cars: false,
+            spawn_increment: INITIAL_SPAWN_INCREMENT,
+            random_spawns: true,
+            time_scale: 1.0,
+            speed_limits: HashMap::new(),
+            demand: HashMap::new(),
+            enabled_approaches: ORIGINS.into_iter().collect(),
+            rng: ChaCha12Rng::seed_from_u64(rand::thread_rng().gen()),
+            allow_right_on_red: false,
+            wait_for_clear_intersection: false,
+            compliance: 1.0,
+            max_cars: None,
+            capacity_policy: CapacityPolicy::Queue,
+            dropped_spawns: 0,
+            boundary_behavior: BoundaryBehavior::Despawn,
+            draw_mode: DrawMode::Plain,
+            ghost_frames: None,
+            trail_frames: None,
+            show_paths: false,
+            event_sink: Box::new(NoopEventSink),
+            collisions: HashSet::new(),
+            obstacles: Vec::new(),
+            obstacle_collisions: HashSet::new(),
+            run_state: RunState::Running,
+            frozen_cars: HashSet::new(),
+            lane_order: HashMap::new(),
+            spawn_elapsed: Duration::ZERO,
+            pending_spawns: VecDeque::new(),
+            scheduled_spawns: Vec::new(),
+            origin_index: 0,
+            id: 0,
+        }
+    }
+
+    /// Moves the simulation on by one frame. The timers run on simulated time, so the waiting
+    /// times are the same at any frame rate and a run with the same seed always plays out the
+    /// same way.
+    pub fn update(&mut self) {
+        // Scaling the frame's time makes the timers run at the same speed as the cars
+        let dt = Duration::from_secs_f64(TARGET_DT * self.time_scale);
+        self.traffic_light.advance(dt);
+        self.spawn_elapsed += dt;
+
+        let cars_clone = self.cars.clone();
+        self.traffic_light.update();
+        self.metrics
+            .record_frame(self.frame, self.traffic_light.as_ref());
+
+        if self.random_spawns && self.spawn_elapsed >= self.spawn_increment {
+            self.spawn_car();
+        }
+        self.spawn_demand_cars();
+        self.spawn_scheduled_cars();
+        self.spawn_pending_cars();
+
+        // Cars change the controller as they go and break ties by whoever comes first, so always
+        // update them in the same order
+        self.cars.sort_by_key(|car| car.id);
+        for car in self.cars.iter_mut() {
+            // Frozen cars still block everyone else, they just don't move or touch the lights
+            if self.frozen_cars.contains(&car.id) {
+                car.freeze();
+                continue;
+            }
+            let was_through_intersection = car.through_intersection();
+            let had_arrived = car.intersection_arrival().is_some();
+            let rules = DrivingRules {
+                speed_limit: self
+                    .speed_limits
+                    .get(&car.origin)
+                    .copied()
+                    .unwrap_or(car::MAX_SPEED),
+                allow_right_on_red: self.allow_right_on_red,
+                wait_for_clear_intersection: self.wait_for_clear_intersection,
+                frame: self.frame,
+                obstacles: &self.obstacles,
+            };
+            car.update(
+                &cars_clone,
+                self.traffic_light.as_mut(),
+                &rules,
+                self.time_scale,
+            );
+            if !was_through_intersection && car.through_intersection() {
+                self.event_sink.on_enter_intersection(car.id);
+            }
+            if !had_arrived && car.intersection_arrival().is_some() {
+                self.metrics
+                    .record_crossing(car.origin, car.direction(), self.frame);
+            }
+            if car.finished {
+                self.event_sink
+                    .on_finish(car.id, car.frames(), car.distance_traveled());
+                self.metrics.record_finish(car, self.frame);
+            }
+        }
+        self.update_collisions();
+
+        if self.finish_escaped_cars {
+            for id in self.escaped_cars() {
+                let car = self.cars.iter_mut().find(|car| car.id == id).unwrap();
+                car.finished = true;
+                self.event_sink.on_escape(id);
+                self.event_sink
+                    .on_finish(car.id, car.frames(), car.distance_traveled());
+                self.metrics.record_finish(car, self.frame);
+            }
+        }
+
+        self.total_finished += self.cars.iter().filter(|car| car.finished).count();
+        if self.retain_finished {
+            self.finished_cars
+                .extend(self.cars.iter().filter(|car| car.finished).cloned());
+        }
+        self.replace_finished_cars();
+        self.cars.retain(|car| !car.finished);
+        self.frame += 1;
+    }
+
+    fn spawn_car(&mut self) {
+        // Counts as a spawn even if nothing can spawn, so the spawner doesn't retry every frame
+        let minimum_time = 300.0;
+        self.spawn_increment = Duration::from_millis(
+            (self.spawn_increment.as_millis() as f64 * 0.997).max(minimum_time) as u64,
+        );
+        self.spawn_elapsed = Duration::ZERO;
+
+        let origins = ORIGINS
+            .into_iter()
+            .filter(|origin| self.enabled_approaches.contains(origin))
+            .collect::<Vec<_>>();
+        if origins.is_empty() {
+            return;
+        }
+        let mut origin = origins[self.rng.gen_range(0..origins.len())];
+        if self.spawn_increment.as_millis() <= 600 {
+            // Go round the enabled approaches in order
+            while !self
+                .enabled_approaches
+                .contains(&ORIGINS[self.origin_index])
+            {
+                self.origin_index = (self.origin_index + 1) % ORIGINS.len();
+            }
+            origin = ORIGINS[self.origin_index];
+            self.origin_index = (self.origin_index + 1) % ORIGINS.len();
+        }
+        let Some(direction) = self.random_direction(origin) else {
+            return;
+        };
+        if let Ok(car) = self.car_builder(origin, direction).try_build() {
+            self.pending_spawns.push_back(car);
+        }
+    }
+
+    /// Queues up a new car for each car that finished this update, as `boundary_behavior` says.
+    /// The new cars wait for their spawn point to be clear like any other.
+    fn replace_finished_cars(&mut self) {
+        let finished = self
+            .cars
+            .iter()
+            .filter(|car| car.finished)
+            .map(|car| (car.origin, car.direction(), car.lane(), car.kind))
+            .collect::<Vec<_>>();
+        for (origin, direction, lane, kind) in finished {
+            let builder = match self.boundary_behavior {
+                BoundaryBehavior::Despawn => return,
+                BoundaryBehavior::Wrap => self.car_builder(origin, direction).lane(lane).kind(kind),
+                BoundaryBehavior::Respawn => {
+                    let origins = ORIGINS
+                        .into_iter()
+                        .filter(|origin| self.enabled_approaches.contains(origin))
+                        .collect::<Vec<_>>();
+                    if origins.is_empty() {
+                        continue;
+                    }
+                    let origin = origins[self.rng.gen_range(0..origins.len())];
+                    let Some(direction) = self.random_direction(origin) else {
+                        continue;
+                    };
+                    self.car_builder(origin, direction)
+                }
+            };
+            // The approaches can have changed since the finished car was built
+            let Ok(car) = builder.try_build() else {
+                continue;
+            };
+            self.pending_spawns.push_back(car);
+        }
+    }
+
+    /// Queues up the cars that arrive on this frame from each approach's demand model
+    fn spawn_demand_cars(&mut self) {
+        for origin in ORIGINS {
+            let Some(demand) = self.demand.get(&origin).copied() else {
+                continue;
+            };
+            for _ in 0..demand.arrivals(self.frame, &mut self.rng) {
+                let Some(direction) = self.random_direction(origin) else {
+                    continue;
+                };
+                if let Ok(car) = self.car_builder(origin, direction).try_build() {
+                    self.pending_spawns.push_back(car);
+                }
+            }
+        }
+    }
+
+    /// Returns if the movement comes from and goes to legs of the intersection that exist
+    pub fn movement_enabled(&self, origin: car::Origin, direction: car::Direction) -> bool {
+        self.enabled_approaches.contains(&origin)
+            && self
+                .enabled_approaches
+                .contains(&car::exit_leg(origin, direction))
+    }
+
+    /// Picks a direction for a car from the given origin that doesn't exit through a missing leg
+    fn random_direction(&mut self, origin: car::Origin) -> Option<car::Direction> {
+        let directions = (0..=2)
+            .map(car::Direction::from)
+            .filter(|&direction| self.movement_enabled(origin, direction))
+            .collect::<Vec<_>>();
+        if directions.is_empty() {
+            return None;
+        }
+        Some(directions[self.rng.gen_range(0..directions.len())])
+    }
+
+    /// Starts building the next car, with its id, the world's geometry and whether the driver
+    /// complies with the lights rolled from `compliance`
+    fn car_builder(&mut self, origin: car::Origin, direction: car::Direction) -> CarBuilder {
+        let id = self.next_id();
+        // Only roll when someone can fail it, so worlds where everyone complies don't use up
+        // random numbers on it
+        let compliant = self.compliance >= 1.0 || self.rng.gen::<f64>() < self.compliance;
+        CarBuilder::new(id, origin, direction)
+            .geometry(self.geometry)
+            .approaches(self.enabled_approaches.clone())
+            .compliant(compliant)
+    }
+
+    /// Ids only go up, so sorting by them puts the cars in the order they were made
+    fn next_id(&mut self) -> usize {
+        let id = self.id;
+        self.id += 1;
+        id
+    }
+
+    /// Adds a car on the given frame, after the random spawns for that frame. Like every other
+    /// car it waits for its spawn point to be clear. Only straight cars can use a lane other
+    /// than 0, and returns an error without scheduling anything if the movement can't be made.
+    pub fn schedule_spawn(
+        &mut self,
+        frame: usize,
+        origin: car::Origin,
+        direction: car::Direction,
+        lane: usize,
+    ) -> Result<(), MovementError> {
+        CarBuilder::new(self.id, origin, direction)
+            .geometry(self.geometry)
+            .approaches(self.enabled_approaches.clone())
+            .lane(lane)
+            .check_movement()?;
+        self.scheduled_spawns.push(ScheduledSpawn {
+            frame,
+            origin,
+            direction,
+            lane,
+        });
+        Ok(())
+    }
+
+    /// Queues up the scheduled cars whose frame has come, in the order they were scheduled
+    fn spawn_scheduled_cars(&mut self) {
+        let (due, later): (Vec<_>, Vec<_>) = self
+            .scheduled_spawns
+            .drain(..)
+            .partition(|spawn| spawn.frame <= self.frame);
+        self.scheduled_spawns = later;
+        for spawn in due {
+            // Approaches turned off since it was scheduled can make the movement impossible
+            if let Ok(car) = self
+                .car_builder(spawn.origin, spawn.direction)
+                .lane(spawn.lane)
+                .try_build()
+            {
+                self.pending_spawns.push_back(car);
+            }
+        }
+    }
+
+    /// Adds the waiting cars whose spawn point is clear. The others try again next update.
+    fn spawn_pending_cars(&mut self) {
+        let mut still_pending = VecDeque::new();
+        while let Some(car) = self.pending_spawns.pop_front() {
+            if self.at_capacity() {
+                match self.capacity_policy {
+                    CapacityPolicy::Drop => self.dropped_spawns += 1,
+                    CapacityPolicy::Queue => still_pending.push_back(car),
+                }
+                continue;
+            }
+            // Keep the cars' half lengths between centers so cars never start on top of each other
+            let blocked = self.cars.iter().any(|other| {
+                car.overlaps(other)
+                    || (car.position().0 - other.position().0)
+                        .hypot(car.position().1 - other.position().1)
+                        < (car.length() + other.length()) / 2.0
+            }) || self
+                .obstacles
+                .iter()
+                .any(|obstacle| car.intersects_rect(obstacle.vertices));
+            if blocked {
+                still_pending.push_back(car);
+                continue;
+            }
+
+            if car.reaches_intersection() {
+                self.traffic_light
+                    .add_car(SimplifiedCar::new(car.origin, car.direction()));
+            }
+            self.event_sink
+                .on_spawn(car.id, car.origin, car.direction());
+            self.metrics.record_spawn(car.origin, self.frame);
+            self.cars.push(car);
+        }
+        self.pending_spawns = still_pending;
+    }
+
+    /// Puts `count` cars for the movement in a queue behind its stop line, already stopped, so a
+    /// run can start from a queue instead of waiting for one to build up. Meant for approaches
+    /// with no cars on them yet. The cars are `car::FOLLOWING_GAP` apart, with the first one
+    /// stopped where a car waiting at the light would be. Returns how many cars fit before the
+    /// edge of the world.
+    pub fn preload_queue(
+        &mut self,
+        origin: car::Origin,
+        direction: car::Direction,
+        count: usize,
+    ) -> usize {
+        if !self.movement_enabled(origin, direction) {
+            return 0;
+        }
+
+        let mut distance = car::DISTANCE_THRESHOLD;
+        for placed in 0..count {
+            let Ok(mut car) = self.car_builder(origin, direction).try_build() else {
+                return placed;
+            };
+            if !car.place_behind_stop_line(distance) {
+                return placed;
+            }
+            distance += car.length() + car::FOLLOWING_GAP;
+
+            if car.reaches_intersection() {
+                self.traffic_light
+                    .add_car(SimplifiedCar::new(car.origin, car.direction()));
+            }
+            self.event_sink
+                .on_spawn(car.id, car.origin, car.direction());
+            self.metrics.record_spawn(car.origin, self.frame);
+            self.cars.push(car);
+        }
+        count
+    }
+
+    /// Returns if the world has as many cars as `max_cars` allows
+    pub fn at_capacity(&self) -> bool {
+        self.max_cars
+            .is_some_and(|max_cars| self.cars.len() >= max_cars)
+    }
+
+    /// Number of cars thrown away because the world was at capacity
+    pub fn dropped_spawns(&self) -> usize {
+        self.dropped_spawns
+    }
+
+    /// Tells the event sink about the pairs of cars that started overlapping this update
+    fn update_collisions(&mut self) {
+        let mut collisions = HashSet::new();
+        let cars = self
+            .cars
+            .iter()
+            .filter(|car| !car.finished)
+            .collect::<Vec<_>>();
+        for (i, car) in cars.iter().enumerate() {
+            for other in cars.iter().skip(i + 1) {
+                // Cars this far apart can't be touching
+                let distance = (car.position().0 - other.position().0)
+                    .hypot(car.position().1 - other.position().1);
+                if distance > car.length() + other.length() {
+                    continue;
+                }
+
+                if car.overlaps(other) {
+                    collisions.insert((car.id.min(other.id), car.id.max(other.id)));
+                }
+            }
+        }
+        let mut new_collisions: Vec<_> = collisions.difference(&self.collisions).collect();
+        new_collisions.sort();
+        for &(a, b) in new_collisions {
+            self.event_sink.on_collision(a, b);
+        }
+        self.collisions = collisions;
+
+        let mut obstacle_collisions = HashSet::new();
+        for car in &self.cars {
+            for (index, obstacle) in self.obstacles.iter().enumerate() {
+                if car.intersects_rect(obstacle.vertices) {
+                    obstacle_collisions.insert((car.id, index));
+                }
+            }
+        }
+        let mut new_collisions: Vec<_> = obstacle_collisions
+            .difference(&self.obstacle_collisions)
+            .collect();
+        new_collisions.sort();
+        for &(id, obstacle) in new_collisions {
+            self.event_sink.on_obstacle_collision(id, obstacle);
+        }
+        self.obstacle_collisions = obstacle_collisions;
+    }
+
+    /// Ids of every pair of cars that overlapped as of the last update, lowest id first in each
+    /// pair and in order. Finished cars aren't included.
+    pub fn collisions(&self) -> Vec<(usize, usize)> {
+        let mut collisions = self.collisions.iter().copied().collect::<Vec<_>>();
+        collisions.sort();
+        collisions
+    }
+
+    /// Puts an obstacle in the road that cars stop behind until it's removed. Returns its index,
+    /// which `remove_obstacle` takes.
+    pub fn add_obstacle(&mut self, obstacle: Obstacle) -> usize {
+        self.obstacles.push(obstacle);
+        self.obstacles.len() - 1
+    }
+
+    /// Takes an obstacle out of the road. The obstacles after it move down an index.
+    pub fn remove_obstacle(&mut self, index: usize) -> Obstacle {
+        self.obstacle_collisions = self
+            .obstacle_collisions
+            .iter()
+            .filter(|&&(_, obstacle)| obstacle != index)
+            .map(|&(id, obstacle)| {
+                (
+                    id,
+                    if obstacle > index {
+                        obstacle - 1
+                    } else {
+                        obstacle
+                    },
+                )
+            })
+            .collect();
+        self.obstacles.remove(index)
+    }
+
+    pub fn obstacles(&self) -> &[Obstacle] {
+        &self.obstacles
+    }
+
+    /// Pins the car in place until `unfreeze_car` is called, for watching how the other cars react
+    /// to it. The car keeps blocking the cars behind it and can still be hit.
+    pub fn freeze_car(&mut self, id: usize) {
+        self.frozen_cars.insert(id);
+    }
+
+    pub fn unfreeze_car(&mut self, id: usize) {
+        self.frozen_cars.remove(&id);
+    }
+
+    pub fn set_event_sink(&mut self, event_sink: Box<dyn EventSink>) {
+        self.event_sink = event_sink;
+    }
+
+    /// Cars that made it through while `retain_finished` was on, in the order they finished
+    pub fn finished_cars(&self) -> impl Iterator<Item = &Car> {
+        self.finished_cars.iter()
+    }
+
+    /// Number of cars that are waiting for room to spawn
+    pub fn pending_spawns(&self) -> usize {
+        self.pending_spawns.len()
+    }
+
+    /// Runs the simulation faster (> 1.0) or slower (< 1.0) without changing the frame rate
+    pub fn set_time_scale(&mut self, time_scale: f64) {
+        self.time_scale = time_scale;
+    }
+
+    /// Estimates the most cars per frame the intersection can get through when every phase of the
+    /// plan runs for `green_time` followed by `yellow_time`:
+    ///
+    /// capacity = sum over phases of (green_time / cycle_time) * lanes * saturation_flow
+    ///
+    /// where `cycle_time` is the length of all of the phases put together, `lanes` is the number
+    /// of lanes with a green in the phase and
+    /// `saturation_flow = MAX_SPEED / (CAR_WIDTH + FOLLOWING_GAP)` is how many cars per frame a
+    /// lane gets through with the cars as close together as they get at full speed. Yellows and
+    /// the time it takes a queue to get up to speed are lost, so real throughput is always lower.
+    pub fn theoretical_capacity(
+        &self,
+        plan: &PhasePlan,
+        green_time: Duration,
+        yellow_time: Duration,
+    ) -> f64 {
+        let cycle_time = (green_time + yellow_time).as_secs_f64() * plan.phases.len() as f64;
+        if cycle_time == 0.0 {
+            return 0.0;
+        }
+        let green_fraction = green_time.as_secs_f64() / cycle_time;
+        let saturation_flow = car::MAX_SPEED / (car::CAR_WIDTH + car::FOLLOWING_GAP);
+
+        let layout = self.geometry.layout;
+        plan.phases
+            .iter()
+            .map(|phase| {
+                let lanes = phase
+                    .iter()
+                    .map(|movement| match movement.direction {
+                        car::Direction::Left => layout.left_lanes,
+                        car::Direction::Straight => layout.straight_lanes,
+                        car::Direction::Right => layout.right_lanes,
+                    })
+                    .sum::<usize>();
+                green_fraction * lanes as f64 * saturation_flow
+            })
+            .sum()
+    }
+
+    /// Takes the world back to how it was when it was made, with the random number generator
+    /// seeded with `seed`. Settings like the speed limits, demand models and controller timings
+    /// are kept, so this behaves like a new world with the same settings and seed.
+    pub fn reset(&mut self, seed: u64) {
+        self.cars.clear();
+        self.pending_spawns.clear();
+        self.scheduled_spawns.clear();
+        self.finished_cars.clear();
+        self.collisions.clear();
+        self.obstacle_collisions.clear();
+        self.lane_order.clear();
+        self.frozen_cars.clear();
+        self.traffic_light.reset();
+        self.frame = 0;
+        self.total_finished = 0;
+        self.dropped_spawns = 0;
+        self.metrics = Metrics::with_warmup(self.metrics.warmup_frames);
+        self.spawn_increment = INITIAL_SPAWN_INCREMENT;
+        self.spawn_elapsed = Duration::ZERO;
+        self.origin_index = 0;
+        self.id = 0;
+        self.set_seed(seed);
+    }
+
+    /// Restarts the random number generator. Two worlds given the same seed make the same random
+    /// choices.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = ChaCha12Rng::seed_from_u64(seed);
+    }
+
+    /// Sets which legs the intersection has, e.g. three of them for a T-intersection. No cars come
+    /// from or turn into the others. A fixed-time controller should be given a plan made with
+    /// `PhasePlan::only_approaches` so it doesn't waste greens on them.
+    pub fn set_enabled_approaches(&mut self, approaches: HashSet<car::Origin>) {
+        self.enabled_approaches = approaches;
+    }
+
+    /// Makes cars arrive at the approach according to `demand`. Turn `random_spawns` off to only
+    /// get cars from the demand models.
+    pub fn set_demand(&mut self, origin: car::Origin, demand: DemandModel) {
+        self.demand.insert(origin, demand);
+    }
+
+    pub fn set_speed_limit(&mut self, origin: car::Origin, speed_limit: f64) {
+        self.speed_limits.insert(origin, speed_limit);
+    }
+
+    /// Returns if any car is in the intersection
+    pub fn intersection_occupied(&self) -> bool {
+        self.cars.iter().any(|car| car.in_intersection())
+    }
+
+    /// Returns the ids of the cars that are in the intersection
+    pub fn intersection_occupants(&self) -> Vec<usize> {
+        self.cars
+            .iter()
+            .filter(|car| car.in_intersection())
+            .map(|car| car.id)
+            .collect()
+    }
+
+    /// Returns the ids of the cars waiting for or driving up to the light for a movement, front of
+    /// the queue first. Cars that have gone into the intersection (or committed to it on yellow)
+    /// aren't queued anymore.
+    pub fn queue(&self, origin: car::Origin, direction: car::Direction) -> Vec<usize> {
+        let mut queued = self
+            .cars
+            .iter()
+            .filter(|car| {
+                car.origin == origin && car.direction() == direction && !car.through_intersection()
+            })
+            .collect::<Vec<_>>();
+        queued.sort_by(|a, b| {
+            a.distance_to_intersection()
+                .total_cmp(&b.distance_to_intersection())
+        });
+        queued.iter().map(|car| car.id).collect()
+    }
+
+    /// Returns if the queue for a movement has backed up to where its cars spawn, which on a real
+    /// road would block the intersection upstream. True when a stopped car is within a car length
+    /// of the start of its path. Cars that just spawned haven't moved yet, so they only count once
+    /// they've come to a standstill.
+    pub fn spillback(&self, origin: car::Origin, direction: car::Direction) -> bool {
+        self.cars.iter().any(|car| {
+            let (x, y) = car.path()[0];
+            car.origin == origin
+                && car.direction() == direction
+                && car.speed() == 0.0
+                && car.stop_count() > 0
+                && (car.position().0 - x).hypot(car.position().1 - y) < car.length()
+        })
+    }
+
+    /// Returns the ids of the cars that haven't moved over the last `window_frames` frames
+    pub fn gridlocked_cars(&self, window_frames: usize) -> Vec<usize> {
+        self.cars
+            .iter()
+            .filter(|car| !car.finished && car.is_stuck(window_frames))
+            .map(|car| car.id)
+            .collect()
+    }
+
+    /// Checks that no car has passed the car ahead of it in its lane since the last call, which
+    /// can't happen since cars don't overtake. Call it every frame to catch cars driving through
+    /// the car ahead. Returns the ids of the car that got passed and the car that passed it.
+    pub fn check_lane_ordering(&mut self) -> Result<(), (usize, usize)> {
+        // Cars in the same lane share a path, so the one driving towards the later point (or
+        // closer to the same point) is ahead
+        let remaining = |car: &Car| {
+            let next = car.path()[car.path_index()];
+            (next.0 - car.position().0).hypot(next.1 - car.position().1)
+        };
+        let mut lanes: HashMap<_, Vec<&Car>> = HashMap::new();
+        for car in &self.cars {
+            lanes
+                .entry((car.origin, car.direction(), car.lane()))
+                .or_default()
+                .push(car);
+        }
+        let lane_order: HashMap<_, Vec<usize>> = lanes
+            .into_iter()
+            .map(|(lane, mut cars)| {
+                cars.sort_by(|a, b| {
+                    b.path_index()
+                        .cmp(&a.path_index())
+                        .then(remaining(a).partial_cmp(&remaining(b)).unwrap())
+                });
+                (lane, cars.iter().map(|car| car.id).collect())
+            })
+            .collect();
+        let previous = std::mem::replace(&mut self.lane_order, lane_order);
+
+        for (lane, order) in &self.lane_order {
+            let Some(previous_order) = previous.get(lane) else {
+                continue;
+            };
+            // Only the cars that were in the lane both times can have swapped
+            let before: Vec<usize> = previous_order
+                .iter()
+                .copied()
+                .filter(|id| order.contains(id))
+                .collect();
+            let after: Vec<usize> = order
+                .iter()
+                .copied()
+                .filter(|id| before.contains(id))
+                .collect();
+            if let Some(i) = (0..after.len()).find(|&i| after[i] != before[i]) {
+                return Err((before[i], after[i]));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the ids of the cars that are further off the canvas than where they spawn (plus half
+    /// a car) but haven't finished. That only happens when a car misses the end of its path.
+    pub fn escaped_cars(&self) -> Vec<usize> {
+        let margin = self.geometry.spawn_setback + car::CAR_WIDTH / 2.0;
+        self.cars
+            .iter()
+            .filter(|car| {
+                let (x, y) = car.position();
+                !car.finished
+                    && (x < -margin
+                        || y < -margin
+                        || x > self.geometry.width + margin
+                        || y > self.geometry.height + margin)
+            })
+            .map(|car| car.id)
+            .collect()
+    }
+
+    /// Returns true when there are cars left but none of them have moved over the last
+    /// `window_frames` frames. Only looks back `car::POSITION_HISTORY_LENGTH` frames at most.
+    pub fn detect_gridlock(&self, window_frames: usize) -> bool {
+        let unfinished = self.cars.iter().filter(|car| !car.finished).count();
+        unfinished > 0 && self.gridlocked_cars(window_frames).len() == unfinished
+    }
+
+    pub fn draw(&self, context: &Context, graphics: &mut G2d) {
+        for obstacle in &self.obstacles {
+            obstacle.draw(context, graphics);
+        }
+        if self.show_paths {
+            for car in &self.cars {
+                car.draw_path(context, graphics);
+            }
+        }
+        for car in &self.cars {
+            car.draw(&self.cars, self.draw_mode, context, graphics);
+            if let Some(frames) = self.trail_frames {
+                car.draw_trail(frames, context, graphics);
+            }
+            if let Some(frames) = self.ghost_frames {
+                car.draw_ghost(frames, context, graphics);
+            }
+        }
+        self.draw_collision_points(context, graphics);
+
+        self.traffic_light.draw(context, graphics);
+    }
+
+    /// Each car's id and speed, and where to write it
+    pub fn labels(&self) -> Vec<(String, (f64, f64))> {
+        self.cars.iter().map(|car| car.label()).collect()
+    }
+
+    /// Writes each car's id and speed above it
+    pub fn draw_labels(&self, glyphs: &mut Glyphs, context: &Context, graphics: &mut G2d) {
+        for (label, (x, y)) in self.labels() {
+            text::Text::new_color([1.0, 1.0, 1.0, 1.0], 12)
+                .draw(
+                    label.as_str(),
+                    glyphs,
+                    &context.draw_state,
+                    context.transform.trans(x, y),
+                    graphics,
+                )
+                .unwrap();
+        }
+    }
+
+    /// Marks where the outlines of overlapping cars cross
+    fn draw_collision_points(&self, context: &Context, graphics: &mut G2d) {
+        let find = |id| self.cars.iter().find(|car| car.id == id);
+        for &(a, b) in &self.collisions {
+            let (Some(a), Some(b)) = (find(a), find(b)) else {
+                continue;
+            };
+            for point in a.collision_points(b) {
+                ellipse(
+                    [1.0, 0.0, 0.0, 1.0],
+                    [point.0 - 4.0, point.1 - 4.0, 8.0, 8.0],
+                    context.transform,
+                    graphics,
+                );
+            }
+        }
+    }
+
+    /// Updates the world if it's running or stepping, and does nothing if it's paused. Returns if
+    /// the world moved on a frame.
+    pub fn tick(&mut self) -> bool {
+        match self.run_state {
+            RunState::Running => self.update(),
+            RunState::Paused => return false,
+            RunState::SingleStep => {
+                self.update();
+                self.run_state = RunState::Paused;
+            }
+        }
+        true
+    }
+
+    pub fn run_state(&self) -> RunState {
+        self.run_state
+    }
+
+    /// Stops `tick` from moving the world on until `resume` or `step_once` is called
+    pub fn pause(&mut self) {
+        if self.run_state == RunState::Running {
+            self.run_state = RunState::Paused;
+        }
+    }
+
+    pub fn resume(&mut self) {
+        self.run_state = RunState::Running;
+    }
+
+    /// Makes the next `tick` of a paused world advance exactly one frame
+    pub fn step_once(&mut self) {
+        if self.run_state == RunState::Paused {
+            self.run_state = RunState::SingleStep;
+        }
+    }
+
+    /// Saves the whole simulation as JSON so it can be resumed with `load`
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let save = WorldSave {
+            cars: self.cars.clone(),
+            pending_spawns: self.pending_spawns.iter().cloned().collect(),
+            scheduled_spawns: self.scheduled_spawns.clone(),
+            random_spawns: self.random_spawns,
+            controller: self.traffic_light.save(),
+            geometry: self.geometry,
+            frame: self.frame,
+            total_finished: self.total_finished,
+            metrics: self.metrics.clone(),
+            spawn_increment: self.spawn_increment,
+            spawn_elapsed: self.spawn_elapsed,
+            time_scale: self.time_scale,
+            speed_limits: self.speed_limits.iter().map(|(o, l)| (*o, *l)).collect(),
+            demand: self.demand.iter().map(|(o, d)| (*o, *d)).collect(),
+            enabled_approaches: self.enabled_approaches.iter().copied().collect(),
+            allow_right_on_red: self.allow_right_on_red,
+            wait_for_clear_intersection: self.wait_for_clear_intersection,
+            compliance: self.compliance,
+            max_cars: self.max_cars,
+            capacity_policy: self.capacity_policy,
+            dropped_spawns: self.dropped_spawns,
+            boundary_behavior: self.boundary_behavior,
+            finish_escaped_cars: self.finish_escaped_cars,
+            obstacles: self.obstacles.clone(),
+            frozen_cars: self.frozen_cars.iter().copied().collect(),
+            collisions: self.collisions.iter().copied().collect(),
+            obstacle_collisions: self.obstacle_collisions.iter().copied().collect(),
+            run_state: self.run_state,
+            origin_index: self.origin_index,
+            id: self.id,
+            rng_seed: self.rng.get_seed(),
+            rng_word_pos: self.rng.get_word_pos(),
+        };
+        serde_json::to_writer(BufWriter::new(File::create(path)?), &save)?;
+        Ok(())
+    }
+
+    /// Loads a simulation saved with `save`
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<SimulationWorld> {
+        let save: WorldSave = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+
+        let mut world =
+            SimulationWorld::with_controller(save.controller.restore(save.geometry), save.geometry);
+        world.cars = save.cars;
+        world.pending_spawns = save.pending_spawns.into_iter().collect();
+        world.scheduled_spawns = save.scheduled_spawns;
+        world.random_spawns = save.random_spawns;
+        for car in world.cars.iter_mut().chain(world.pending_spawns.iter_mut()) {
+            car.restore_path();
+        }
+        world.frame = save.frame;
+        world.total_finished = save.total_finished;
+        world.metrics = save.metrics;
+        world.spawn_increment = save.spawn_increment;
+        world.spawn_elapsed = save.spawn_elapsed;
+        world.time_scale = save.time_scale;
+        world.speed_limits = save.speed_limits.into_iter().collect();
+        world.demand = save.demand.into_iter().collect();
+        world.enabled_approaches = save.enabled_approaches.into_iter().collect();
+        world.allow_right_on_red = save.allow_right_on_red;
+        world.wait_for_clear_intersection = save.wait_for_clear_intersection;
+        world.compliance = save.compliance;
+        world.max_cars = save.max_cars;
+        world.capacity_policy = save.capacity_policy;
+        world.dropped_spawns = save.dropped_spawns;
+        world.boundary_behavior = save.boundary_behavior;
+        world.finish_escaped_cars = save.finish_escaped_cars;
+        world.obstacles = save.obstacles;
+        world.frozen_cars = save.frozen_cars.into_iter().collect();
+        world.collisions = save.collisions.into_iter().collect();
+        world.obstacle_collisions = save.obstacle_collisions.into_iter().collect();
+        world.run_state = save.run_state;
+        world.origin_index = save.origin_index;
+        world.id = save.id;
+        world.rng = ChaCha12Rng::from_seed(save.rng_seed);
+        world.rng.set_word_pos(save.rng_word_pos);
+        Ok(world)
+    }
+
+    /// Captures the state of every car for the current frame
+    pub fn snapshot(&self) -> FrameSnapshot {
+        FrameSnapshot {
+            frame: self.frame,
+            cars: self
+                .cars
+                .iter()
+                .map(|car| car.snapshot(self.traffic_light.as_ref()))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        event_sink::{CarEvent, VecEventSink},
+        fixed_time_controller::FixedTimeController,
+        snapshot::{first_divergence, FrameSnapshot},
+    };
+
+    /// Updates the world `ticks` times, taking a snapshot after each one
+    fn record(world: &mut SimulationWorld, ticks: usize) -> Vec<FrameSnapshot> {
+        (0..ticks)
+            .map(|_| {
+                world.update();
+                world.snapshot()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn impossible_scheduled_spawns_are_rejected() {
+        let mut world = SimulationWorld::new();
+        assert_eq!(
+            world.schedule_spawn(0, car::Origin::North, car::Direction::Left, 3),
+            Err(MovementError::InvalidLane(3))
+        );
+        assert_eq!(
+            world.schedule_spawn(0, car::Origin::North, car::Direction::Straight, 0),
+            Ok(())
+        );
+        assert_eq!(world.scheduled_spawns.len(), 1);
+    }
+
+    #[test]
+    fn scheduled_spawn_through_a_closed_approach_is_dropped() {
+        let mut world = SimulationWorld::new();
+        world
+            .schedule_spawn(1, car::Origin::North, car::Direction::Straight, 0)
+            .unwrap();
+        world.set_enabled_approaches(HashSet::from([
+            car::Origin::North,
+            car::Origin::East,
+            car::Origin::West,
+        ]));
+        for _ in 0..3 {
+            world.update();
+        }
+        assert!(world.scheduled_spawns.is_empty());
+        assert!(!world
+            .cars
+            .iter()
+            .chain(world.pending_spawns.iter())
+            .any(|car| car.origin == car::Origin::North
+                && car.direction() == car::Direction::Straight));
+    }
+
+    #[test]
+    fn reset_world_replays_a_fresh_one() {
+        let mut fresh = SimulationWorld::new();
+        fresh.set_seed(7);
+        let expected = record(&mut fresh, 1000);
+        assert!(expected.iter().any(|frame| !frame.cars.is_empty()));
+
+        let mut reused = SimulationWorld::new();
+        reused.set_seed(3);
+        record(&mut reused, 700);
+        reused.reset(7);
+        let replayed = record(&mut reused, 1000);
+        assert!(first_divergence(&expected, &replayed).is_none());
+    }
+
+    #[test]
+    fn worlds_with_the_same_seed_match_tick_for_tick() {
+        let mut world = SimulationWorld::new();
+        world.set_seed(21);
+        let mut other = SimulationWorld::new();
+        other.set_seed(21);
+        assert!(first_divergence(&record(&mut world, 1000), &record(&mut other, 1000)).is_none());
+    }
+
+    #[test]
+    fn car_order_does_not_change_the_next_tick() {
+        let mut world = SimulationWorld::new();
+        world.set_seed(5);
+        let mut shuffled = SimulationWorld::new();
+        shuffled.set_seed(5);
+        record(&mut world, 600);
+        record(&mut shuffled, 600);
+        assert!(shuffled.cars.len() > 1);
+
+        shuffled.cars.reverse();
+        assert!(first_divergence(&record(&mut world, 1), &record(&mut shuffled, 1)).is_none());
+    }
+
+    #[test]
+    fn spawner_with_nowhere_to_spawn_waits_for_the_next_spawn() {
+        let mut world = SimulationWorld::new();
+        world.set_enabled_approaches(HashSet::new());
+        for _ in 0..600 {
+            world.update();
+        }
+        // Five seconds of frames, but only a few spawn attempts
+        assert!(world.spawn_increment > INITIAL_SPAWN_INCREMENT.mul_f64(0.9));
+    }
+
+    #[test]
+    fn ids_keep_counting_up_past_a_thousand() {
+        let mut world = SimulationWorld::new();
+        world.id = 1000;
+        assert_eq!(world.next_id(), 1000);
+        assert_eq!(world.next_id(), 1001);
+    }
+
+    #[test]
+    fn loaded_world_carries_on_like_the_original() {
+        // East queues up while a long minimum green holds North's green, so the save lands
+        // partway through a phase whose end depends on how long it has already gone
+        let min_green = || {
+            let mut controller = TrafficLightController::new(WorldGeometry::default());
+            controller.set_min_green_frames(480);
+            let mut world =
+                SimulationWorld::with_controller(Box::new(controller), WorldGeometry::default());
+            world.random_spawns = false;
+            for frame in (0..1000).step_by(60) {
+                world
+                    .schedule_spawn(frame, car::Origin::North, car::Direction::Straight, 0)
+                    .unwrap();
+            }
+            for frame in [60, 80, 100] {
+                world
+                    .schedule_spawn(frame, car::Origin::East, car::Direction::Straight, 0)
+                    .unwrap();
+            }
+            world
+        };
+        // A car that stays frozen and escaped cars being finished both change how the run goes
+        let set_up = |world: &mut SimulationWorld| {
+            world.set_seed(13);
+            world.freeze_car(2);
+            world.finish_escaped_cars = true;
+        };
+        for new_world in [SimulationWorld::new, min_green] {
+            let mut world = new_world();
+            set_up(&mut world);
+            let expected = record(&mut world, 1000);
+
+            let mut saved = new_world();
+            set_up(&mut saved);
+            record(&mut saved, 500);
+            let path = std::env::temp_dir().join("big_traffic_light_model_save_test.json");
+            saved.save(&path).unwrap();
+            let mut loaded = SimulationWorld::load(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            let resumed = record(&mut loaded, 500);
+            assert!(first_divergence(&expected[500..], &resumed).is_none());
+            assert_eq!(expected[999].frame, resumed[499].frame);
+        }
+    }
+
+    #[test]
+    fn escaped_cars_are_finished_and_reported() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        world.finish_escaped_cars = true;
+        let sink = VecEventSink::new();
+        let events = sink.events();
+        world.set_event_sink(Box::new(sink));
+
+        // Driving a path made for a much bigger canvas puts the car well off this one
+        let geometry = WorldGeometry::new(world.geometry.width * 3.0, world.geometry.height * 3.0);
+        let car = CarBuilder::new(0, car::Origin::West, car::Direction::Straight)
+            .geometry(geometry)
+            .build();
+        world.cars.push(car);
+        assert_eq!(world.escaped_cars(), vec![0]);
+
+        world.update();
+        assert!(world.cars.is_empty());
+        assert!(events.borrow().contains(&CarEvent::Escape { id: 0 }));
+        assert_eq!(world.total_finished, 1);
+    }
+
+    #[test]
+    fn cars_keep_to_their_approach_speed_limit() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        world.set_speed_limit(car::Origin::North, 2.0);
+        world
+            .schedule_spawn(0, car::Origin::North, car::Direction::Straight, 0)
+            .unwrap();
+        let mut fastest = 0.0_f64;
+        for _ in 0..2000 {
+            world.update();
+            for car in world.cars.iter().filter(|car| !car.through_intersection()) {
+                fastest = fastest.max(car.speed());
+            }
+        }
+        assert!(fastest > 0.0 && fastest <= 2.0, "{}", fastest);
+    }
+
+    #[test]
+    fn cars_blocking_the_box_are_gridlocked() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        // Something stalled in the box stops the North car partway across, in the West car's way
+        let middle = world.geometry.middle();
+        let north = CarBuilder::new(0, car::Origin::North, car::Direction::Straight).build();
+        world.add_obstacle(Obstacle::rect(
+            (north.position().0, middle.1 + 160.0),
+            40.0,
+            20.0,
+        ));
+        for origin in [car::Origin::North, car::Origin::West] {
+            world
+                .schedule_spawn(0, origin, car::Direction::Straight, 0)
+                .unwrap();
+        }
+
+        for _ in 0..200 {
+            world.update();
+            assert!(!world.detect_gridlock(120));
+        }
+        for _ in 0..3000 {
+            world.update();
+        }
+        // The West car has a green but waits for the North car, which got there first, to clear
+        assert_eq!(world.intersection_occupants(), vec![0]);
+        assert!(world
+            .traffic_light
+            .is_green(car::Origin::West, car::Direction::Straight));
+        assert_eq!(world.gridlocked_cars(120), vec![0, 1]);
+        assert!(world.detect_gridlock(120));
+    }
+
+    #[test]
+    fn car_parked_in_the_middle_occupies_the_intersection() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        world
+            .cars
+            .push(CarBuilder::new(0, car::Origin::West, car::Direction::Straight).build());
+        assert!(!world.intersection_occupied());
+
+        let parked = CarBuilder::new(1, car::Origin::North, car::Direction::Straight)
+            .span(0.5, 1.0)
+            .build();
+        let middle = world.geometry.middle();
+        assert!((parked.position().1 - middle.1).abs() < car::CAR_WIDTH);
+        world.cars.push(parked);
+        world.freeze_car(1);
+        world.update();
+        assert!(world.intersection_occupied());
+        assert_eq!(world.intersection_occupants(), vec![1]);
+    }
+
+    #[test]
+    fn cars_wait_to_spawn_behind_a_jammed_lane() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        world.traffic_light.force_phase(Vec::new());
+        let queued = world.preload_queue(car::Origin::North, car::Direction::Straight, 20);
+        assert!(queued < 20);
+        for _ in 0..3 {
+            world
+                .schedule_spawn(0, car::Origin::North, car::Direction::Straight, 0)
+                .unwrap();
+        }
+
+        for _ in 0..300 {
+            world.update();
+            assert!(world.collisions().is_empty());
+        }
+        assert_eq!(world.cars.len(), queued);
+        assert_eq!(world.pending_spawns(), 3);
+    }
+
+    #[test]
+    fn event_sink_hears_a_car_spawn_cross_and_finish() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        let sink = VecEventSink::new();
+        let events = sink.events();
+        world.set_event_sink(Box::new(sink));
+        world
+            .schedule_spawn(0, car::Origin::South, car::Direction::Straight, 0)
+            .unwrap();
+        for _ in 0..2000 {
+            world.update();
+        }
+
+        let events = events.borrow();
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0],
+            CarEvent::Spawn {
+                id: 0,
+                origin: car::Origin::South,
+                direction: car::Direction::Straight,
+            }
+        );
+        assert_eq!(events[1], CarEvent::EnterIntersection { id: 0 });
+        assert!(matches!(events[2], CarEvent::Finish { id: 0, .. }));
+    }
+
+    #[test]
+    fn queue_lists_stopped_cars_front_to_back() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        world.traffic_light.force_phase(Vec::new());
+        for frame in [0, 100, 200] {
+            world
+                .schedule_spawn(frame, car::Origin::North, car::Direction::Straight, 0)
+                .unwrap();
+        }
+        for _ in 0..1000 {
+            world.update();
+        }
+        assert!(world.cars.iter().all(|car| car.speed() == 0.0));
+
+        // Cars spawned first are at the front
+        let mut ids = world.cars.iter().map(|car| car.id).collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(
+            world.queue(car::Origin::North, car::Direction::Straight),
+            ids
+        );
+        assert!(world
+            .queue(car::Origin::South, car::Direction::Straight)
+            .is_empty());
+    }
+
+    #[test]
+    fn scheduled_rear_end_fires_a_collision_on_the_frame_it_happens() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        let sink = VecEventSink::new();
+        let events = sink.events();
+        world.set_event_sink(Box::new(sink));
+        world.traffic_light.force_phase(vec![SimplifiedCar::new(
+            car::Origin::North,
+            car::Direction::Straight,
+        )]);
+        for _ in 0..2 {
+            world
+                .schedule_spawn(0, car::Origin::North, car::Direction::Straight, 0)
+                .unwrap();
+        }
+
+        // Cars in the intersection don't look out for anyone, so stalling the first car once the
+        // second one is in there makes the second run into it
+        let mut collided = false;
+        for _ in 0..500 {
+            world.update();
+            let collisions = events
+                .borrow()
+                .iter()
+                .filter(|event| matches!(event, CarEvent::Collision { .. }))
+                .count();
+            if world
+                .cars
+                .iter()
+                .any(|car| car.id == 1 && car.through_intersection())
+            {
+                world.freeze_car(0);
+            }
+            // The event comes on the update the cars start overlapping, and only then
+            collided |= !world.collisions().is_empty();
+            assert_eq!(collisions, collided as usize);
+        }
+        assert!(collided);
+        assert!(events
+            .borrow()
+            .contains(&CarEvent::Collision { a: 0, b: 1 }));
+    }
+
+    #[test]
+    fn retained_finished_car_stays_put_and_blocks_no_one() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        world.retain_finished = true;
+        world.traffic_light.force_phase(vec![SimplifiedCar::new(
+            car::Origin::North,
+            car::Direction::Straight,
+        )]);
+        world
+            .schedule_spawn(0, car::Origin::North, car::Direction::Straight, 0)
+            .unwrap();
+        for _ in 0..2000 {
+            world.update();
+        }
+        assert_eq!(world.finished_cars().count(), 1);
+        let finished = world.finished_cars().next().unwrap().position();
+
+        // The next car drives right over where the first one finished
+        world
+            .schedule_spawn(world.frame, car::Origin::North, car::Direction::Straight, 0)
+            .unwrap();
+        let mut top_speed: f64 = 0.0;
+        while world.finished_cars().count() < 2 {
+            world.update();
+            assert!(world.frame < 4000);
+            assert!(world.collisions().is_empty());
+            for car in &world.cars {
+                assert!(car.speed() >= top_speed);
+                top_speed = car.speed();
+            }
+        }
+        assert_eq!(world.finished_cars().next().unwrap().position(), finished);
+    }
+
+    #[test]
+    fn poisson_demand_spawns_its_rate_on_average() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        world.set_seed(11);
+        let sink = VecEventSink::new();
+        let events = sink.events();
+        world.set_event_sink(Box::new(sink));
+        let rate = 0.02;
+        world.set_demand(car::Origin::North, DemandModel::Poisson { rate });
+        let frames = 10_000;
+        for _ in 0..frames {
+            world.update();
+        }
+
+        // Cars still waiting for room to spawn arrived too
+        let spawned = events
+            .borrow()
+            .iter()
+            .filter(|event| matches!(event, CarEvent::Spawn { .. }))
+            .count()
+            + world.pending_spawns();
+        let expected = rate * frames as f64;
+        assert!(
+            (spawned as f64 - expected).abs() < 0.15 * expected,
+            "{} cars, expected {}",
+            spawned,
+            expected
+        );
+    }
+
+    #[test]
+    fn saturated_throughput_stays_under_the_theoretical_capacity() {
+        let (green_time, yellow_time) = (Duration::from_secs(5), Duration::from_secs(1));
+        let plan = PhasePlan::four_phase();
+        let controller =
+            FixedTimeController::new(green_time, yellow_time, WorldGeometry::default())
+                .with_plan(plan.clone())
+                .unwrap();
+        let mut world =
+            SimulationWorld::with_controller(Box::new(controller), WorldGeometry::default());
+        world.random_spawns = false;
+        // More cars than the lights can get through, but not so many that the test crawls
+        world.max_cars = Some(60);
+        world.capacity_policy = CapacityPolicy::Drop;
+        world.set_seed(2);
+        for origin in ORIGINS {
+            world.set_demand(
+                origin,
+                DemandModel::Burst {
+                    size: 1,
+                    interval: 20,
+                },
+            );
+        }
+        let capacity = world.theoretical_capacity(&plan, green_time, yellow_time);
+
+        // Give the queues a cycle to build up, then count two whole cycles
+        let cycle_frames = 4 * 6 * 120;
+        for _ in 0..cycle_frames {
+            world.update();
+        }
+        let finished = world.total_finished;
+        for _ in 0..2 * cycle_frames {
+            world.update();
+        }
+        let throughput = (world.total_finished - finished) as f64 / (2 * cycle_frames) as f64;
+        // Queues getting up to speed after every green lose a good part of it
+        assert!(throughput <= capacity, "{} > {}", throughput, capacity);
+        assert!(
+            throughput > 0.25 * capacity,
+            "{} <= {}",
+            throughput,
+            capacity
+        );
+    }
+
+    #[test]
+    fn cars_in_a_lane_stay_a_car_length_apart() {
+        let mut world = SimulationWorld::new();
+        world.set_seed(13);
+        for _ in 0..1000 {
+            world.update();
+            for (i, car) in world.cars.iter().enumerate() {
+                for other in &world.cars[i + 1..] {
+                    if car.origin != other.origin
+                        || car.direction() != other.direction()
+                        || car.lane() != other.lane()
+                    {
+                        continue;
+                    }
+                    let distance = (car.position().0 - other.position().0)
+                        .hypot(car.position().1 - other.position().1);
+                    assert!(
+                        distance >= (car.length() + other.length()) / 2.0,
+                        "cars {} and {} are {} apart",
+                        car.id,
+                        other.id,
+                        distance
+                    );
+                }
+            }
+        }
+        assert!(world.total_finished > 0);
+    }
+
+    #[test]
+    fn t_intersection_without_a_south_leg_never_uses_it() {
+        let mut world = SimulationWorld::new();
+        world.set_seed(17);
+        world.set_enabled_approaches(HashSet::from([
+            car::Origin::North,
+            car::Origin::East,
+            car::Origin::West,
+        ]));
+        let mut north_directions = HashSet::new();
+        for _ in 0..3000 {
+            world.update();
+            for car in &world.cars {
+                assert_ne!(car.origin, car::Origin::South);
+                assert_ne!(car.destination(), car::Origin::South);
+                if car.origin == car::Origin::North {
+                    north_directions.insert(car.direction());
+                }
+            }
+        }
+        // Going straight would take North's cars out the missing leg, so they all turn
+        assert_eq!(
+            north_directions,
+            HashSet::from([car::Direction::Left, car::Direction::Right])
+        );
+    }
+
+    #[test]
+    fn every_car_gets_one_label() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        for origin in [car::Origin::North, car::Origin::East, car::Origin::South] {
+            world
+                .schedule_spawn(0, origin, car::Direction::Straight, 0)
+                .unwrap();
+        }
+        for _ in 0..60 {
+            world.update();
+        }
+        assert_eq!(world.cars.len(), 3);
+
+        let labels = world.labels();
+        assert_eq!(labels.len(), world.cars.len());
+        for (car, (label, _)) in world.cars.iter().zip(&labels) {
+            assert!(label.starts_with(&format!("{} ", car.id)), "{}", label);
+        }
+    }
+
+    #[test]
+    fn random_traffic_never_passes_in_a_lane() {
+        for seed in 0..3 {
+            let mut world = SimulationWorld::new();
+            world.set_seed(seed);
+            world.max_cars = Some(30);
+            world.capacity_policy = CapacityPolicy::Drop;
+            for _ in 0..3000 {
+                world.update();
+                assert_eq!(world.check_lane_ordering(), Ok(()), "seed {}", seed);
+            }
+            assert!(world.total_finished > 0);
+        }
+    }
+
+    #[test]
+    fn lane_ordering_catches_a_car_moved_past_its_leader() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        world.traffic_light.force_phase(Vec::new());
+        // Left turns don't change lanes to get around the queue
+        for frame in [0, 100] {
+            world
+                .schedule_spawn(frame, car::Origin::North, car::Direction::Left, 0)
+                .unwrap();
+        }
+        for _ in 0..600 {
+            world.update();
+            assert_eq!(world.check_lane_ordering(), Ok(()));
+        }
+
+        // Put the car behind right at the stop line, as if it drove through the one in front
+        assert_eq!(world.cars.len(), 2);
+        assert!(world.cars[1].place_behind_stop_line(0.0));
+        assert_eq!(world.check_lane_ordering(), Err((0, 1)));
+    }
+
+    #[test]
+    fn car_spawned_behind_a_queue_brakes_smoothly() {
+        let geometry = WorldGeometry::default().with_spawn_setback(200.0);
+        let mut world = SimulationWorld::with_geometry(geometry);
+        world.random_spawns = false;
+        world.traffic_light.force_phase(Vec::new());
+        // Enough cars to queue back past the edge. Left turns don't change lanes to get around it.
+        for frame in [0, 60, 120, 180, 240, 300] {
+            world
+                .schedule_spawn(frame, car::Origin::North, car::Direction::Left, 0)
+                .unwrap();
+        }
+
+        let mut speeds: HashMap<usize, f64> = HashMap::new();
+        for _ in 0..1500 {
+            world.update();
+            for car in &world.cars {
+                for other in world.cars.iter().filter(|other| other.id != car.id) {
+                    assert!(!car.overlaps(other), "{} {}", car.id, other.id);
+                }
+                // Never harder than normal braking, so it saw the queue in time
+                let previous = speeds.insert(car.id, car.speed()).unwrap_or(0.0);
+                assert!(
+                    previous - car.speed() <= car::DECELERATION + 1e-9,
+                    "{} {} -> {}",
+                    car.id,
+                    previous,
+                    car.speed()
+                );
+            }
+        }
+        // The last car only had room to spawn because of the setback, and queued up off the canvas
+        assert_eq!(world.cars.len(), 6);
+        assert!(world.cars[5].position().1 < 0.0);
+        assert!(world.cars.iter().all(|car| car.speed() == 0.0));
+        assert!(world.cars.iter().all(|car| car.stop_count() == 1));
+    }
+
+    #[test]
+    fn frozen_car_stays_put_and_the_car_behind_stops_for_it() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        world.traffic_light.force_phase(vec![SimplifiedCar::new(
+            car::Origin::North,
+            car::Direction::Left,
+        )]);
+        // Left turns don't change lanes to get around the frozen car
+        for frame in [0, 40] {
+            world
+                .schedule_spawn(frame, car::Origin::North, car::Direction::Left, 0)
+                .unwrap();
+        }
+        for _ in 0..40 {
+            world.update();
+        }
+        world.freeze_car(0);
+        world.update();
+        let position = world.cars[0].position();
+        for _ in 0..600 {
+            world.update();
+            assert_eq!(world.cars[0].position(), position);
+            assert!(!world.cars[0].overlaps(&world.cars[1]));
+        }
+        assert_eq!(world.cars[1].speed(), 0.0);
+        assert!(!world.cars[1].through_intersection());
+
+        world.unfreeze_car(0);
+        for _ in 0..2000 {
+            world.update();
+        }
+        assert!(world.cars.is_empty());
+        assert_eq!(world.total_finished, 2);
+    }
+
+    #[test]
+    fn spawns_past_max_cars_are_dropped_or_queued() {
+        for policy in [CapacityPolicy::Drop, CapacityPolicy::Queue] {
+            let mut world = SimulationWorld::new();
+            world.random_spawns = false;
+            world.traffic_light.force_phase(Vec::new());
+            world.max_cars = Some(10);
+            world.capacity_policy = policy;
+            // Every movement has its own lane, so none of them wait for room to spawn
+            let movements = ORIGINS.into_iter().flat_map(|origin| {
+                [
+                    car::Direction::Left,
+                    car::Direction::Straight,
+                    car::Direction::Right,
+                ]
+                .map(|direction| (origin, direction))
+            });
+            for (origin, direction) in movements.take(11) {
+                world.schedule_spawn(0, origin, direction, 0).unwrap();
+            }
+            for _ in 0..10 {
+                world.update();
+            }
+
+            assert_eq!(world.cars.len(), 10);
+            assert!(world.at_capacity());
+            match policy {
+                CapacityPolicy::Drop => {
+                    assert_eq!(world.dropped_spawns(), 1);
+                    assert_eq!(world.pending_spawns(), 0);
+                }
+                CapacityPolicy::Queue => {
+                    assert_eq!(world.dropped_spawns(), 0);
+                    assert_eq!(world.pending_spawns(), 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn paused_world_only_moves_a_frame_at_a_time() {
+        let mut world = SimulationWorld::new();
+        world.set_seed(3);
+        for _ in 0..200 {
+            assert!(world.tick());
+        }
+        assert!(!world.cars.is_empty());
+
+        world.pause();
+        let (frame, snapshot) = (world.frame, world.snapshot());
+        for _ in 0..10 {
+            assert!(!world.tick());
+        }
+        assert_eq!(world.frame, frame);
+        assert!(snapshot.diff(&world.snapshot()).is_empty());
+
+        world.step_once();
+        assert_eq!(world.run_state(), RunState::SingleStep);
+        assert!(world.tick());
+        assert_eq!(world.frame, frame + 1);
+        assert_eq!(world.run_state(), RunState::Paused);
+        assert!(!world.tick());
+        assert_eq!(world.frame, frame + 1);
+
+        world.resume();
+        assert!(world.tick());
+        assert_eq!(world.frame, frame + 2);
+    }
+
+    #[test]
+    fn cars_queue_behind_an_obstacle_until_it_is_removed() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        world.traffic_light.force_phase(vec![SimplifiedCar::new(
+            car::Origin::North,
+            car::Direction::Left,
+        )]);
+        // Halfway down the North left turn lane. Left turns don't change lanes to get around it.
+        let x = CarBuilder::new(0, car::Origin::North, car::Direction::Left)
+            .build()
+            .position()
+            .0;
+        let obstacle = world.add_obstacle(Obstacle::rect((x, 200.0), 40.0, 20.0));
+        for frame in [0, 60] {
+            world
+                .schedule_spawn(frame, car::Origin::North, car::Direction::Left, 0)
+                .unwrap();
+        }
+
+        for _ in 0..600 {
+            world.update();
+            for car in &world.cars {
+                assert!(!car.intersects_rect(world.obstacles()[obstacle].vertices));
+            }
+        }
+        assert_eq!(world.cars.len(), 2);
+        assert!(world.cars.iter().all(|car| car.speed() == 0.0));
+        assert!(world.cars.iter().all(|car| car.position().1 < 200.0));
+        assert!(!world.cars[0].overlaps(&world.cars[1]));
+
+        world.remove_obstacle(obstacle);
+        for _ in 0..2000 {
+            world.update();
+        }
+        assert_eq!(world.total_finished, 2);
+    }
+
+    #[test]
+    fn saturated_approach_spills_back_to_the_spawn_point() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        world.traffic_light.force_phase(Vec::new());
+        world.set_demand(
+            car::Origin::North,
+            DemandModel::Burst {
+                size: 1,
+                interval: 20,
+            },
+        );
+        let directions = [
+            car::Direction::Left,
+            car::Direction::Straight,
+            car::Direction::Right,
+        ];
+        let spilled_back = |world: &SimulationWorld, origin| {
+            directions
+                .iter()
+                .any(|&direction| world.spillback(origin, direction))
+        };
+
+        // Cars that just spawned don't count, only a queue that has built up
+        for _ in 0..100 {
+            world.update();
+            assert!(!spilled_back(&world, car::Origin::North));
+        }
+        let mut frames = 100;
+        while !spilled_back(&world, car::Origin::North) {
+            world.update();
+            frames += 1;
+            assert!(frames < 3000, "North never spilled back");
+        }
+        for origin in [car::Origin::East, car::Origin::South, car::Origin::West] {
+            assert!(!spilled_back(&world, origin));
+        }
+    }
+
+    #[test]
+    fn wrapped_cars_keep_the_count_constant() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        world.boundary_behavior = BoundaryBehavior::Wrap;
+        for origin in ORIGINS {
+            world
+                .schedule_spawn(0, origin, car::Direction::Straight, 0)
+                .unwrap();
+        }
+        let count = |world: &SimulationWorld| {
+            world.cars.iter().filter(|car| !car.finished).count() + world.pending_spawns.len()
+        };
+
+        world.update();
+        assert_eq!(count(&world), 4);
+        for _ in 0..1000 {
+            world.update();
+            assert_eq!(count(&world), 4);
+        }
+        assert!(world.total_finished > 0);
+    }
+
+    #[test]
+    fn overlapping_cars_are_each_one_collision_pair() {
+        let mut world = SimulationWorld::new();
+        let stacked = |count| {
+            (0..count)
+                .map(|id| CarBuilder::new(id, car::Origin::North, car::Direction::Left).build())
+                .collect::<Vec<_>>()
+        };
+
+        world.cars = stacked(2);
+        world.update_collisions();
+        assert_eq!(world.collisions(), vec![(0, 1)]);
+
+        world.cars = stacked(3);
+        world.update_collisions();
+        assert_eq!(world.collisions(), vec![(0, 1), (0, 2), (1, 2)]);
+
+        // Finished cars don't count
+        world.cars[2].finished = true;
+        world.update_collisions();
+        assert_eq!(world.collisions(), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn preloaded_queue_discharges_front_to_back_on_green() {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        world.traffic_light.force_phase(Vec::new());
+        // Left turns, so the queue stays in one lane
+        assert_eq!(
+            world.preload_queue(car::Origin::North, car::Direction::Left, 5),
+            5
+        );
+        // Placed from the front of the queue back
+        let ids = world.cars.iter().map(|car| car.id).collect::<Vec<_>>();
+        for _ in 0..120 {
+            world.update();
+            assert!(world.cars.iter().all(|car| car.speed() == 0.0));
+            assert!(world.collisions().is_empty());
+        }
+
+        world.traffic_light.force_phase(vec![SimplifiedCar::new(
+            car::Origin::North,
+            car::Direction::Left,
+        )]);
+        let mut crossed = Vec::new();
+        for _ in 0..2000 {
+            world.update();
+            assert!(world.collisions().is_empty());
+            for car in &world.cars {
+                if car.through_intersection() && !crossed.contains(&car.id) {
+                    crossed.push(car.id);
+                }
+            }
+        }
+        assert_eq!(crossed, ids);
+        assert_eq!(world.total_finished, 5);
+    }
+}
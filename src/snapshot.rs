@@ -0,0 +1,186 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{car, traffic_light::TrafficLightState};
+
+/// The state of a single car in a frame
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CarSnapshot {
+    pub id: usize,
+    pub origin: car::Origin,
+    pub direction: car::Direction,
+    pub position: (f64, f64),
+    pub rotation: f64,
+    /// Length of the car, which is longer for buses
+    pub length: f64,
+    pub color: [f32; 4],
+    pub speed: f64,
+    pub stopped: bool,
+    /// State of the light controlling this car's origin and direction
+    pub light: TrafficLightState,
+}
+
+impl CarSnapshot {
+    /// Returns if the two cars overlap, going by their lengths
+    pub fn overlaps(&self, other: &CarSnapshot) -> bool {
+        car::Car::cars_of_length_intersect(
+            (self.position, self.rotation, self.length),
+            (other.position, other.rotation, other.length),
+        )
+    }
+}
+
+/// Differences smaller than this are treated as floating point noise by `FrameSnapshot::diff`
+pub const DIFF_TOLERANCE: f64 = 1e-6;
+
+/// How a car differs between two snapshots of the same frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct CarDelta {
+    pub id: usize,
+    /// Distance between the car's two positions
+    pub position_difference: f64,
+    pub speed_difference: f64,
+    pub stopped_differs: bool,
+    /// The car is only in one of the snapshots, so there's nothing to compare it to
+    pub missing: bool,
+}
+
+/// The state of the whole simulation in a frame, used to replay it outside of the simulation
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FrameSnapshot {
+    pub frame: usize,
+    pub cars: Vec<CarSnapshot>,
+}
+
+impl FrameSnapshot {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /// Returns the cars whose position, speed or stopped state differ by more than
+    /// `DIFF_TOLERANCE` between the two snapshots, plus the cars that are only in one of them.
+    /// Sorted by id.
+    pub fn diff(&self, other: &FrameSnapshot) -> Vec<CarDelta> {
+        let mut deltas: Vec<CarDelta> = self
+            .cars
+            .iter()
+            .filter_map(|car| {
+                let Some(other_car) = other.cars.iter().find(|c| c.id == car.id) else {
+                    return Some(CarDelta::missing(car.id));
+                };
+                let delta = CarDelta {
+                    id: car.id,
+                    position_difference: (car.position.0 - other_car.position.0)
+                        .hypot(car.position.1 - other_car.position.1),
+                    speed_difference: (car.speed - other_car.speed).abs(),
+                    stopped_differs: car.stopped != other_car.stopped,
+                    missing: false,
+                };
+                (delta.position_difference > DIFF_TOLERANCE
+                    || delta.speed_difference > DIFF_TOLERANCE
+                    || delta.stopped_differs)
+                    .then_some(delta)
+            })
+            .collect();
+        deltas.extend(
+            other
+                .cars
+                .iter()
+                .filter(|car| !self.cars.iter().any(|c| c.id == car.id))
+                .map(|car| CarDelta::missing(car.id)),
+        );
+        deltas.sort_by_key(|delta| delta.id);
+        deltas
+    }
+}
+
+impl CarDelta {
+    fn missing(id: usize) -> CarDelta {
+        CarDelta {
+            id,
+            position_difference: 0.0,
+            speed_difference: 0.0,
+            stopped_differs: false,
+            missing: true,
+        }
+    }
+}
+
+/// Goes through two recordings frame by frame and returns the frame number and first car where
+/// they stop matching. Frames past the end of the shorter recording aren't compared.
+pub fn first_divergence(
+    run: &[FrameSnapshot],
+    other_run: &[FrameSnapshot],
+) -> Option<(usize, CarDelta)> {
+    run.iter().zip(other_run).find_map(|(frame, other_frame)| {
+        frame
+            .diff(other_frame)
+            .into_iter()
+            .next()
+            .map(|delta| (frame.frame, delta))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation_world::SimulationWorld;
+
+    /// Snapshots of a North car driving for `frames` updates. `speed_limit` is put on the North
+    /// approach just before update `change_at`.
+    fn record(frames: usize, change_at: usize, speed_limit: f64) -> Vec<FrameSnapshot> {
+        let mut world = SimulationWorld::new();
+        world.random_spawns = false;
+        world
+            .schedule_spawn(0, car::Origin::North, car::Direction::Straight, 0)
+            .unwrap();
+        (0..frames)
+            .map(|i| {
+                if i == change_at {
+                    world.set_speed_limit(car::Origin::North, speed_limit);
+                }
+                world.update();
+                world.snapshot()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical_runs_do_not_diverge() {
+        let (run, other_run) = (record(300, 50, 1.0), record(300, 50, 1.0));
+        assert!(run.iter().any(|frame| !frame.cars.is_empty()));
+        for (frame, other_frame) in run.iter().zip(&other_run) {
+            assert!(frame.diff(other_frame).is_empty());
+        }
+        assert_eq!(first_divergence(&run, &other_run), None);
+    }
+
+    #[test]
+    fn bus_overlaps_the_car_queued_too_close_behind_it() {
+        let snapshot = |id, kind: car::CarKind, y| CarSnapshot {
+            id,
+            origin: car::Origin::North,
+            direction: car::Direction::Straight,
+            position: (500.0, y),
+            rotation: 90.0,
+            length: kind.length(),
+            color: [1.0; 4],
+            speed: 0.0,
+            stopped: true,
+            light: TrafficLightState::Red,
+        };
+        let bus = snapshot(0, car::CarKind::Bus, 500.0);
+        // Would be clear of a regular car ahead, but not of the back half of the bus
+        let behind = 500.0 - 1.5 * car::CAR_WIDTH + 5.0;
+        assert!(bus.overlaps(&snapshot(1, car::CarKind::Regular, behind)));
+        assert!(!bus.overlaps(&snapshot(1, car::CarKind::Regular, behind - 10.0)));
+    }
+
+    #[test]
+    fn lower_speed_limit_diverges_on_the_frame_it_starts() {
+        let (run, other_run) = (record(300, 50, car::MAX_SPEED), record(300, 50, 1.0));
+        let (frame, delta) = first_divergence(&run, &other_run).unwrap();
+        assert_eq!(frame, run[50].frame);
+        assert_eq!(delta.id, 0);
+        assert!(delta.speed_difference > DIFF_TOLERANCE && !delta.missing);
+    }
+}
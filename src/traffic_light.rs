@@ -1,26 +1,35 @@
 use std::collections::HashMap;
 use std::f64::consts::PI;
 use std::time::Duration;
-use std::time::Instant;
 
 use crate::car;
 use crate::car::NUM_PATH_POINTS;
+use crate::geometry::WorldGeometry;
 use crate::traffic_light_controller::SimplifiedCar;
-use crate::HEIGHT;
-use crate::LANE_WIDTH;
 use crate::MINIMUM_GREEN_TIME;
 use crate::USE_ENTRY_TIME;
-use crate::WIDTH;
 use crate::YELLOW_TIME;
 use piston_window::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TrafficLightState {
     Red,
     Yellow,
     Green,
 }
 
+/// The parts of a light that change while the simulation runs
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrafficLightSave {
+    pub state: TrafficLightState,
+    green_elapsed: Duration,
+    red_elapsed: Duration,
+    change_to_green_elapsed: Duration,
+    change_to_green_delay: Duration,
+    should_change_to_green: bool,
+}
+
 #[derive(Debug)]
 pub struct TrafficLight {
     pub origin: car::Origin,
@@ -29,25 +38,38 @@ pub struct TrafficLight {
     /// HashMap that contains every other light that cars would intersect with. The values are the
     /// yellow + red times for each light.
     pub intersecting_lights: HashMap<(car::Origin, car::Direction), Duration>,
-    pub green_start: Instant,
-    pub red_start: Instant,
-    /// Time when this light got the go ahead to change to green.
-    change_to_green_start: Instant,
+    /// Simulated time since the light last turned green
+    pub green_elapsed: Duration,
+    /// Simulated time since the light last turned yellow on its way to red
+    pub red_elapsed: Duration,
+    /// Time it takes the last car that went on this light to get through the intersection
+    pub clearance_time: Duration,
+    /// Simulated time since this light got the go ahead to change to green.
+    change_to_green_elapsed: Duration,
     change_to_green_delay: Duration,
     should_change_to_green: bool,
+    /// How long the light stays yellow
+    pub yellow_time: Duration,
+    /// Shortest a green can last before the light can change back to red
+    pub minimum_green_time: Duration,
+    geometry: WorldGeometry,
 }
 
 impl TrafficLight {
-    pub fn new(origin: car::Origin, direction: car::Direction) -> TrafficLight {
+    pub fn new(
+        origin: car::Origin,
+        direction: car::Direction,
+        geometry: WorldGeometry,
+    ) -> TrafficLight {
         let mut intersecting_lights = HashMap::new();
         let waiting_car = SimplifiedCar::new(origin, direction);
-        for other_origin in vec![
+        for other_origin in [
             car::Origin::North,
             car::Origin::South,
             car::Origin::East,
             car::Origin::West,
         ] {
-            for other_direction in vec![
+            for other_direction in [
                 car::Direction::Left,
                 car::Direction::Straight,
                 car::Direction::Right,
@@ -56,7 +78,8 @@ impl TrafficLight {
                     continue;
                 }
                 let moving_car = SimplifiedCar::new(other_origin, other_direction);
-                let red_clearance_time = calculate_red_clearance_time(&moving_car, &waiting_car);
+                let red_clearance_time =
+                    calculate_red_clearance_time(&moving_car, &waiting_car, &geometry);
                 if red_clearance_time.as_millis() > 0 {
                     intersecting_lights.insert((other_origin, other_direction), red_clearance_time);
                 }
@@ -67,52 +90,96 @@ impl TrafficLight {
             direction,
             state: TrafficLightState::Red,
             intersecting_lights,
-            green_start: Instant::now(),
-            red_start: Instant::now(),
-            change_to_green_start: Instant::now(),
+            clearance_time: calculate_clearance_time(&waiting_car, &geometry),
+            green_elapsed: Duration::ZERO,
+            red_elapsed: Duration::ZERO,
+            change_to_green_elapsed: Duration::ZERO,
             change_to_green_delay: Duration::from_millis(0),
             should_change_to_green: false,
+            yellow_time: YELLOW_TIME,
+            minimum_green_time: MINIMUM_GREEN_TIME,
+            geometry,
         }
     }
 
-    pub fn fix_delay(&mut self, delay_secs: f64) {
-        // When positive delay, that means that the frame took less time than expected. This means that we need to subtract that time from the timers.
-        // When the delay is negative, we need to add that time to the timers.
-        if delay_secs > 0.0 {
-            self.green_start -= Duration::from_secs_f64(delay_secs);
-            self.red_start -= Duration::from_secs_f64(delay_secs);
-            self.change_to_green_start -= Duration::from_secs_f64(delay_secs);
-        } else {
-            self.green_start += Duration::from_secs_f64(-delay_secs);
-            self.red_start += Duration::from_secs_f64(-delay_secs);
-            self.change_to_green_start += Duration::from_secs_f64(-delay_secs);
+    /// Puts the light back to red, the way it was when it was made. Its timings are kept.
+    pub fn reset(&mut self) {
+        self.state = TrafficLightState::Red;
+        self.green_elapsed = Duration::ZERO;
+        self.red_elapsed = Duration::ZERO;
+        self.clearance_time = calculate_clearance_time(
+            &SimplifiedCar::new(self.origin, self.direction),
+            &self.geometry,
+        );
+        self.change_to_green_elapsed = Duration::ZERO;
+        self.change_to_green_delay = Duration::from_millis(0);
+        self.should_change_to_green = false;
+    }
+
+    /// Moves the light's timers forward by `dt` of simulated time
+    pub fn advance(&mut self, dt: Duration) {
+        self.green_elapsed += dt;
+        self.red_elapsed += dt;
+        self.change_to_green_elapsed += dt;
+    }
+
+    pub fn save(&self) -> TrafficLightSave {
+        TrafficLightSave {
+            state: self.state,
+            green_elapsed: self.green_elapsed,
+            red_elapsed: self.red_elapsed,
+            change_to_green_elapsed: self.change_to_green_elapsed,
+            change_to_green_delay: self.change_to_green_delay,
+            should_change_to_green: self.should_change_to_green,
         }
     }
 
+    pub fn load(&mut self, save: &TrafficLightSave) {
+        self.state = save.state;
+        self.green_elapsed = save.green_elapsed;
+        self.red_elapsed = save.red_elapsed;
+        self.change_to_green_elapsed = save.change_to_green_elapsed;
+        self.change_to_green_delay = save.change_to_green_delay;
+        self.should_change_to_green = save.should_change_to_green;
+    }
+
     pub fn change_to_red(&mut self) {
-        self.red_start = Instant::now();
+        self.red_elapsed = Duration::ZERO;
         self.state = TrafficLightState::Yellow;
     }
 
     /// Returns true if it's been more than the minimum green time and we aren't about to change to
     /// green
     pub fn can_change_to_red(&self) -> bool {
-        self.green_start.elapsed() >= MINIMUM_GREEN_TIME && !self.should_change_to_green
+        self.green_elapsed >= self.minimum_green_time && !self.should_change_to_green
     }
 
     pub fn change_to_green(&mut self, delay: Duration) {
-        self.change_to_green_start = Instant::now();
+        self.change_to_green_elapsed = Duration::ZERO;
         self.change_to_green_delay = delay;
         self.should_change_to_green = true;
     }
 
-    pub fn update(&mut self, queue: usize) {
+    /// Returns if the light has been told to turn green but hasn't yet
+    pub fn is_changing_to_green(&self) -> bool {
+        self.should_change_to_green
+    }
+
+    /// Takes back a `change_to_green` that hasn't happened yet
+    pub fn cancel_change_to_green(&mut self) {
+        self.should_change_to_green = false;
+    }
+
+    /// Updates the light. While `all_red` is true the light won't turn green, even if it's been
+    /// told to.
+    pub fn update(&mut self, queue: usize, all_red: bool) {
         // Change to green
         if self.should_change_to_green
-            && self.change_to_green_start.elapsed() >= self.change_to_green_delay
+            && !all_red
+            && self.change_to_green_elapsed >= self.change_to_green_delay
         {
             self.state = TrafficLightState::Green;
-            self.green_start = Instant::now();
+            self.green_elapsed = Duration::ZERO;
             self.should_change_to_green = false;
         }
 
@@ -122,18 +189,11 @@ impl TrafficLight {
         }
 
         // Yellow
-        if self.state == TrafficLightState::Yellow {
-            if self.red_start.elapsed() > YELLOW_TIME {
-                self.state = TrafficLightState::Red;
-            }
+        if self.state == TrafficLightState::Yellow && self.red_elapsed > self.yellow_time {
+            self.state = TrafficLightState::Red;
         }
     }
 
-    pub fn unpause(&mut self, time_elapsed: Duration) {
-        self.green_start += time_elapsed;
-        self.red_start += time_elapsed;
-    }
-
     pub fn draw(&self, context: &Context, graphics: &mut G2d) {
         let light_radius = 10.0;
         let light_spacing = (2.0 / 3.0) * light_radius;
@@ -146,20 +206,19 @@ impl TrafficLight {
         let dark_yellow = [0.3, 0.32, 0.04, alpha];
         let dark_red = [0.34, 0.06, 0.06, alpha];
 
+        let lane_width = self.geometry.layout.lane_width;
+        let distance = self.geometry.intersection_half_size() + lane_width * 0.1;
         let mut final_position = match self.origin {
-            car::Origin::North => (0.0, -LANE_WIDTH * 3.1),
-            car::Origin::South => (0.0, LANE_WIDTH * 3.1),
-            car::Origin::East => (LANE_WIDTH * 3.1, 0.0),
-            car::Origin::West => (-LANE_WIDTH * 3.1, 0.0),
+            car::Origin::North => (0.0, -distance),
+            car::Origin::South => (0.0, distance),
+            car::Origin::East => (distance, 0.0),
+            car::Origin::West => (-distance, 0.0),
         };
-        final_position.0 += WIDTH as f64 / 2.0;
-        final_position.1 += HEIGHT as f64 / 2.0;
+        let middle = self.geometry.middle();
+        final_position.0 += middle.0;
+        final_position.1 += middle.1;
 
-        let mut offset = match self.direction {
-            car::Direction::Left => 0.0,
-            car::Direction::Straight => LANE_WIDTH,
-            car::Direction::Right => LANE_WIDTH * 2.0,
-        };
+        let mut offset = self.geometry.layout.lane_offset(self.direction, 0) as f64 * lane_width;
         offset += light_radius;
         match self.origin {
             car::Origin::North => final_position.0 -= offset,
@@ -215,9 +274,9 @@ impl TrafficLight {
             dark_green
         };
         let colors = [final_red, final_yellow, final_green];
-        for i in 0..3 {
+        for (i, &color) in colors.iter().enumerate() {
             ellipse(
-                colors[i],
+                color,
                 [
                     light_radius + light_radius * 0.5,
                     light_radius + (i as f64 * (light_radius * 2.0 + light_spacing)),
@@ -264,9 +323,13 @@ impl TrafficLight {
 
 /// Calculates the entry time of a car into the intersection given the car already in the
 /// intersection and the currently waiting car
-fn calculate_entry_time(moving_car: &SimplifiedCar, waiting_car: &SimplifiedCar) -> Duration {
-    let moving_car_path = car::Car::calculate_path(moving_car);
-    let waiting_car_path = car::Car::calculate_path(waiting_car);
+fn calculate_entry_time(
+    moving_car: &SimplifiedCar,
+    waiting_car: &SimplifiedCar,
+    geometry: &WorldGeometry,
+) -> Duration {
+    let moving_car_path = car::Car::calculate_path(moving_car, 0, NUM_PATH_POINTS, geometry);
+    let waiting_car_path = car::Car::calculate_path(waiting_car, 0, NUM_PATH_POINTS, geometry);
 
     let mut moving_path_index: usize = 0;
     let mut waiting_path_index: usize = 0;
@@ -301,10 +364,9 @@ fn calculate_entry_time(moving_car: &SimplifiedCar, waiting_car: &SimplifiedCar)
         return Duration::from_secs(100);
     }
 
-    let end_index = (waiting_path_index - 1)
-        .min(waiting_car_path.len() - 1)
-        .max(0);
-    let distance_to_collision = (car::Car::calculate_waiting_point_index(waiting_car)..=end_index)
+    let end_index = (waiting_path_index - 1).min(waiting_car_path.len() - 1);
+    let distance_to_collision = (car::Car::intersection_indices(&waiting_car_path, geometry).0
+        ..=end_index)
         .map(|i| i as f64)
         .reduce(|acc, i| {
             let distance = ((waiting_car_path[i as usize].0 - waiting_car_path[i as usize + 1].0)
@@ -320,14 +382,30 @@ fn calculate_entry_time(moving_car: &SimplifiedCar, waiting_car: &SimplifiedCar)
     let num_frames = (2.0 * distance_to_collision / car::ACCELERATION).sqrt();
 
     let frame_duration = 1000.0 / 60.0;
-    return Duration::from_millis((num_frames * frame_duration) as u64);
+    Duration::from_millis((num_frames * frame_duration) as u64)
+}
+
+/// Calculates how long it takes a car that just left the stop line at full speed to get through
+/// the intersection
+fn calculate_clearance_time(car: &SimplifiedCar, geometry: &WorldGeometry) -> Duration {
+    let path = car::Car::calculate_path(car, 0, NUM_PATH_POINTS, geometry);
+    let (first_point, exit_point) = car::Car::intersection_indices(&path, geometry);
+    let points = &path[first_point..exit_point];
+    let distance_covered: f64 = points
+        .windows(2)
+        .map(|pair| (pair[0].0 - pair[1].0).hypot(pair[0].1 - pair[1].1))
+        .sum();
+
+    let frame_duration = 1000.0 / 60.0;
+    Duration::from_millis((distance_covered / car::MAX_SPEED * frame_duration) as u64)
 }
 
 fn calculate_red_clearance_time(
     moving_car: &SimplifiedCar,
     waiting_car: &SimplifiedCar,
+    geometry: &WorldGeometry,
 ) -> Duration {
-    let waiting_car_path = car::Car::calculate_path(waiting_car);
+    let waiting_car_path = car::Car::calculate_path(waiting_car, 0, NUM_PATH_POINTS, geometry);
 
     // let straight_distance = LANE_WIDTH * 4.0;
     // let left_distance = std::f64::consts::PI * LANE_WIDTH * 3.0 / 2.0;
@@ -338,17 +416,12 @@ fn calculate_red_clearance_time(
     //     car::Direction::Left => left_distance,
     //     car::Direction::Right => right_distance,
     // };
-    let first_point = car::Car::calculate_waiting_point_index(waiting_car);
-    let points = waiting_car_path
-        .iter()
-        .skip(first_point)
-        .take(NUM_PATH_POINTS / 3)
-        .collect::<Vec<_>>();
-    let mut distance_covered = 0.0;
-    for i in 0..(points.len() - 1) {
-        let distance = (points[i].0 - points[i + 1].0).hypot(points[i].1 - points[i + 1].1);
-        distance_covered += distance;
-    }
+    let (first_point, exit_point) = car::Car::intersection_indices(&waiting_car_path, geometry);
+    let points = &waiting_car_path[first_point..exit_point];
+    let distance_covered: f64 = points
+        .windows(2)
+        .map(|pair| (pair[0].0 - pair[1].0).hypot(pair[0].1 - pair[1].1))
+        .sum();
 
     let speed = car::MAX_SPEED;
     let frame_duration = 1000.0 / 60.0;
@@ -357,10 +430,11 @@ fn calculate_red_clearance_time(
 
     // Subtract entry time
     if USE_ENTRY_TIME {
-        clearance_time -= calculate_entry_time(moving_car, waiting_car).as_millis() as f64;
+        clearance_time -=
+            calculate_entry_time(moving_car, waiting_car, geometry).as_millis() as f64;
     }
     clearance_time += YELLOW_TIME.as_millis() as f64; // Add in yellow at the start
 
     clearance_time = clearance_time.max(0.0);
-    return Duration::from_millis(clearance_time as u64);
+    Duration::from_millis(clearance_time as u64)
 }
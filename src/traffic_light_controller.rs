@@ -1,13 +1,17 @@
 use piston_window::*;
+use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, time::Duration};
 
 use crate::{
     car::{self},
-    traffic_light::{TrafficLight, TrafficLightState},
-    ALLOW_GO_ON_YELLOW,
+    controller_strategy::{ControllerSave, ControllerStrategy},
+    crosswalk::{Crosswalk, CrosswalkSave},
+    geometry::WorldGeometry,
+    traffic_light::{TrafficLight, TrafficLightSave, TrafficLightState},
+    ALLOW_GO_ON_YELLOW, MINIMUM_GREEN_TIME, YELLOW_TIME,
 };
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct SimplifiedCar {
     pub origin: car::Origin,
     pub direction: car::Direction,
@@ -19,34 +23,372 @@ impl SimplifiedCar {
     }
 }
 
+/// Returns if cars doing the two movements could run into each other if they both had a green.
+/// Opposing straights, opposing lefts and right turns that don't merge into the same leg don't
+/// conflict.
+pub fn movements_conflict(a: SimplifiedCar, b: SimplifiedCar) -> bool {
+    if a.origin == b.origin {
+        return false;
+    }
+    // Merging into the same leg
+    if car::exit_leg(a.origin, a.direction) == car::exit_leg(b.origin, b.direction) {
+        return true;
+    }
+    // Right turns stay in their corner of the intersection
+    if a.direction == car::Direction::Right || b.direction == car::Direction::Right {
+        return false;
+    }
+
+    let opposing = car::exit_leg(a.origin, car::Direction::Straight) == b.origin;
+    if opposing {
+        // Only a left turn crossing the oncoming straight conflicts
+        return (a.direction == car::Direction::Left) != (b.direction == car::Direction::Left);
+    }
+    true
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrafficLightControllerSave {
+    queue: Vec<(SimplifiedCar, usize)>,
+    traffic_lights: Vec<TrafficLightSave>,
+    crosswalks: Vec<CrosswalkSave>,
+    all_red_elapsed: Duration,
+    all_red_time: Duration,
+    yellow_duration: Duration,
+    green_duration: Duration,
+    min_green_frames: usize,
+    phase: (PhaseKind, Vec<SimplifiedCar>),
+    phase_frames: usize,
+    forced_phase: Option<Vec<SimplifiedCar>>,
+}
+
+/// What the intersection as a whole is doing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PhaseKind {
+    Green,
+    Yellow,
+    AllRed,
+}
+
+/// Adaptive controller that gives greens to the movements with cars waiting, along with any others
+/// that don't conflict with them. It doesn't take a `PhasePlan`: which movements go together and
+/// in what order comes from the queues each update, so a fixed list of phases would only get in
+/// the way. Plans are for `FixedTimeController`.
 pub struct TrafficLightController {
     queue: HashMap<SimplifiedCar, usize>,
     traffic_lights: Vec<TrafficLight>,
+    crosswalks: Vec<Crosswalk>,
+    /// Simulated time since the last light turned red. No light turns green until the cars that
+    /// went on it have had time to get through the intersection.
+    all_red_elapsed: Duration,
+    all_red_time: Duration,
+    yellow_duration: Duration,
+    /// Shortest a green lasts. Greens keep going after this while cars are still coming.
+    green_duration: Duration,
+    /// Updates a phase has to last before a waiting movement can take over from its greens.
+    /// Keeps the lights from flipping back and forth when the queues are close.
+    min_green_frames: usize,
+    /// The phase and the movements that are green or yellow in it, to tell when it changes
+    phase: (PhaseKind, Vec<SimplifiedCar>),
+    phase_frames: usize,
+    /// Movements held green by `force_phase`. While this is set the queues don't decide anything.
+    forced_phase: Option<Vec<SimplifiedCar>>,
 }
 
 impl TrafficLightController {
-    pub fn new() -> TrafficLightController {
+    pub fn new(geometry: WorldGeometry) -> TrafficLightController {
         TrafficLightController {
             queue: TrafficLightController::generate_queue(),
-            traffic_lights: TrafficLightController::generate_traffic_lights(),
+            traffic_lights: TrafficLightController::generate_traffic_lights(geometry),
+            crosswalks: Crosswalk::generate_crosswalks(geometry),
+            all_red_elapsed: Duration::ZERO,
+            all_red_time: Duration::from_millis(0),
+            yellow_duration: YELLOW_TIME,
+            green_duration: MINIMUM_GREEN_TIME,
+            min_green_frames: 0,
+            phase: (PhaseKind::AllRed, Vec::new()),
+            phase_frames: 0,
+            forced_phase: None,
+        }
+    }
+
+    /// Makes a controller in the state it was saved in
+    pub fn load(
+        save: &TrafficLightControllerSave,
+        geometry: WorldGeometry,
+    ) -> TrafficLightController {
+        let mut controller = TrafficLightController::new(geometry);
+        controller.set_yellow_duration(save.yellow_duration);
+        controller.set_green_duration(save.green_duration);
+        controller.set_min_green_frames(save.min_green_frames);
+        controller.queue = save.queue.iter().copied().collect();
+        for (traffic_light, light_save) in controller
+            .traffic_lights
+            .iter_mut()
+            .zip(&save.traffic_lights)
+        {
+            traffic_light.load(light_save);
+        }
+        for (crosswalk, crosswalk_save) in controller.crosswalks.iter_mut().zip(&save.crosswalks) {
+            crosswalk.load(crosswalk_save);
         }
+        controller.all_red_elapsed = save.all_red_elapsed;
+        controller.all_red_time = save.all_red_time;
+        controller.phase = save.phase.clone();
+        controller.phase_frames = save.phase_frames;
+        controller.forced_phase = save.forced_phase.clone();
+        controller
     }
 
-    pub fn fix_delays(&mut self, dt: f64, target_dt: f64) {
-        let delay = target_dt - dt;
+    pub fn set_yellow_duration(&mut self, yellow_duration: Duration) {
         for traffic_light in &mut self.traffic_lights {
-            traffic_light.fix_delay(delay);
+            traffic_light.yellow_time = yellow_duration;
+            // The delays before a light can turn green include the yellow of the lights it crosses
+            for delay in traffic_light.intersecting_lights.values_mut() {
+                *delay = (*delay + yellow_duration).saturating_sub(self.yellow_duration);
+            }
+        }
+        self.yellow_duration = yellow_duration;
+    }
+
+    pub fn set_green_duration(&mut self, green_duration: Duration) {
+        for traffic_light in &mut self.traffic_lights {
+            traffic_light.minimum_green_time = green_duration;
+        }
+        self.green_duration = green_duration;
+    }
+
+    pub fn set_min_green_frames(&mut self, min_green_frames: usize) {
+        self.min_green_frames = min_green_frames;
+    }
+
+    /// Number of updates since the phase, or the movements that are going in it, last changed
+    pub fn frames_in_current_phase(&self) -> usize {
+        self.phase_frames
+    }
+
+    /// Returns if any light is green, else if any light is yellow, else `PhaseKind::AllRed`
+    pub fn current_phase_kind(&self) -> PhaseKind {
+        let any_light = |state| self.traffic_lights.iter().any(|light| light.state == state);
+        if self.in_all_red() {
+            PhaseKind::AllRed
+        } else if any_light(TrafficLightState::Green) {
+            PhaseKind::Green
+        } else if any_light(TrafficLightState::Yellow) {
+            PhaseKind::Yellow
+        } else {
+            PhaseKind::AllRed
         }
     }
 
-    pub fn update(&mut self) {
+    /// Seconds until the current phase can end. For greens this is the time left of the green
+    /// duration, since a green can keep going after that as long as cars are coming.
+    pub fn time_remaining_in_phase(&self) -> f64 {
+        let remaining = match self.current_phase_kind() {
+            PhaseKind::AllRed => self.all_red_time.saturating_sub(self.all_red_elapsed),
+            PhaseKind::Yellow => self
+                .traffic_lights
+                .iter()
+                .filter(|light| light.state == TrafficLightState::Yellow)
+                .map(|light| self.yellow_duration.saturating_sub(light.red_elapsed))
+                .max()
+                .unwrap_or_default(),
+            PhaseKind::Green => self
+                .traffic_lights
+                .iter()
+                .filter(|light| light.state == TrafficLightState::Green)
+                .map(|light| self.green_duration.saturating_sub(light.green_elapsed))
+                .max()
+                .unwrap_or_default(),
+        };
+        remaining.as_secs_f64()
+    }
+
+    /// Returns if the intersection is in an all red clearance interval, which happens after a
+    /// light turns red
+    pub fn in_all_red(&self) -> bool {
+        self.all_red_elapsed < self.all_red_time
+    }
+
+    /// Movements being held green by `force_phase`, if any
+    pub fn forced_phase(&self) -> Option<&[SimplifiedCar]> {
+        self.forced_phase.as_deref()
+    }
+
+    /// Ends the greens that aren't in the forced phase and starts the ones that are. Greens still
+    /// go through their yellow and the all red after it, and a forced movement waits for the
+    /// lights it crosses to be red before it turns green.
+    fn apply_forced_phase(&mut self) {
+        let Some(forced_phase) = &self.forced_phase else {
+            return;
+        };
+
+        let mut to_green = Vec::new();
+        let mut to_red = Vec::new();
+        for (i, traffic_light) in self.traffic_lights.iter().enumerate() {
+            let movement = SimplifiedCar::new(traffic_light.origin, traffic_light.direction);
+            if !forced_phase.contains(&movement) {
+                to_red.push(i);
+                continue;
+            }
+            let crossing_clear = traffic_light.intersecting_lights.keys().all(|light| {
+                let light = self.get_traffic_light(light.0, light.1);
+                light.state == TrafficLightState::Red && !light.is_changing_to_green()
+            });
+            if traffic_light.state == TrafficLightState::Red
+                && !traffic_light.is_changing_to_green()
+                && crossing_clear
+            {
+                to_green.push(i);
+            }
+        }
+
+        for i in to_red {
+            let traffic_light = &mut self.traffic_lights[i];
+            traffic_light.cancel_change_to_green();
+            // Skips the minimum green, but not the yellow
+            if traffic_light.state == TrafficLightState::Green {
+                traffic_light.change_to_red();
+            }
+        }
+        // One at a time, so two forced movements that cross each other can't both go green
+        for i in to_green {
+            let traffic_light = &self.traffic_lights[i];
+            let crossing_clear = traffic_light.intersecting_lights.keys().all(|light| {
+                !self
+                    .get_traffic_light(light.0, light.1)
+                    .is_changing_to_green()
+            });
+            if crossing_clear {
+                self.traffic_lights[i].change_to_green(Duration::ZERO);
+            }
+        }
+    }
+
+    /// Number of cars waiting for (or driving up to) the light of the given movement
+    pub fn queue_length(&self, origin: car::Origin, direction: car::Direction) -> usize {
+        *self
+            .queue
+            .get(&SimplifiedCar::new(origin, direction))
+            .unwrap()
+    }
+
+    pub fn generate_traffic_lights(geometry: WorldGeometry) -> Vec<TrafficLight> {
+        let mut traffic_lights = Vec::new();
+        for origin in [
+            car::Origin::North,
+            car::Origin::South,
+            car::Origin::East,
+            car::Origin::West,
+        ] {
+            for direction in [
+                car::Direction::Left,
+                car::Direction::Straight,
+                car::Direction::Right,
+            ] {
+                traffic_lights.push(TrafficLight::new(origin, direction, geometry));
+            }
+        }
+
+        // The paths alone miss some conflicts (like cars merging into the same leg), so every
+        // movement that conflicts is also treated as an intersecting light
+        let movements = traffic_lights
+            .iter()
+            .map(|light| SimplifiedCar::new(light.origin, light.direction))
+            .collect::<Vec<_>>();
+        for traffic_light in traffic_lights.iter_mut() {
+            let movement = SimplifiedCar::new(traffic_light.origin, traffic_light.direction);
+            for other in &movements {
+                if movements_conflict(movement, *other) {
+                    traffic_light
+                        .intersecting_lights
+                        .entry((other.origin, other.direction))
+                        .or_insert(YELLOW_TIME);
+                }
+            }
+        }
+        traffic_lights
+    }
+
+    pub fn generate_queue() -> HashMap<SimplifiedCar, usize> {
+        let mut queue = HashMap::new();
+        for origin in [
+            car::Origin::North,
+            car::Origin::South,
+            car::Origin::East,
+            car::Origin::West,
+        ] {
+            for direction in [
+                car::Direction::Left,
+                car::Direction::Straight,
+                car::Direction::Right,
+            ] {
+                queue.insert(SimplifiedCar::new(origin, direction), 0);
+            }
+        }
+        queue
+    }
+
+    pub fn get_traffic_light(
+        &self,
+        origin: car::Origin,
+        direction: car::Direction,
+    ) -> &TrafficLight {
+        // Using the above code, the traffic lights are generated the order:
+        // NorthLeft, NorthStraight, NorthRight, SouthLeft, SouthStraight, SouthRight, EastLeft, EastStraight, EastRight, WestLeft, WestStraight, WestRight
+        let origin_index = match origin {
+            car::Origin::North => 0,
+            car::Origin::South => 3,
+            car::Origin::East => 6,
+            car::Origin::West => 9,
+        };
+        let direction_index = match direction {
+            car::Direction::Left => 0,
+            car::Direction::Straight => 1,
+            car::Direction::Right => 2,
+        };
+        &self.traffic_lights[origin_index + direction_index]
+    }
+}
+
+impl ControllerStrategy for TrafficLightController {
+    fn advance(&mut self, dt: Duration) {
+        for traffic_light in &mut self.traffic_lights {
+            traffic_light.advance(dt);
+        }
+        for crosswalk in &mut self.crosswalks {
+            crosswalk.advance(dt);
+        }
+        self.all_red_elapsed += dt;
+    }
+
+    fn update(&mut self) {
+        self.apply_forced_phase();
+
         let queue_lengths: Vec<usize> = self
             .traffic_lights
             .iter()
-            .map(|traffic_light| self.queue(traffic_light.origin, traffic_light.direction))
+            .map(|traffic_light| {
+                let movement = SimplifiedCar::new(traffic_light.origin, traffic_light.direction);
+                // Forced greens stay on even when no one is waiting for them
+                match &self.forced_phase {
+                    Some(forced_phase) if forced_phase.contains(&movement) => usize::MAX,
+                    Some(_) => 0,
+                    None => self.queue_length(traffic_light.origin, traffic_light.direction),
+                }
+            })
             .collect();
+        let all_red = self.in_all_red();
         for (i, traffic_light) in self.traffic_lights.iter_mut().enumerate() {
-            traffic_light.update(queue_lengths[i]);
+            let was_red = traffic_light.state == TrafficLightState::Red;
+            traffic_light.update(queue_lengths[i], all_red);
+
+            // Start (or extend) the all red interval when a light finishes its yellow
+            if !was_red && traffic_light.state == TrafficLightState::Red {
+                let remaining = self.all_red_time.saturating_sub(self.all_red_elapsed);
+                self.all_red_elapsed = Duration::ZERO;
+                self.all_red_time = remaining.max(traffic_light.clearance_time);
+            }
         }
 
         // If a traffic light is waiting, see if its queue is greater than combined queue of all of
@@ -56,8 +398,16 @@ impl TrafficLightController {
         // List of lights that are allowed to become green
         // (traffic light index, queue length, red clearance time)
         let mut lights_to_make_green: Vec<(usize, usize, Duration)> = Vec::new();
-        for i in 0..self.traffic_lights.len() {
-            if queue_lengths[i] == 0 {
+        let phase_can_end = self.phase_frames >= self.min_green_frames;
+        for (i, &queue_length) in queue_lengths.iter().enumerate() {
+            if queue_length == 0 || self.forced_phase.is_some() {
+                continue;
+            }
+            // Telling a light to turn green again would restart its green time, so it could never
+            // be taken over
+            if self.traffic_lights[i].state == TrafficLightState::Green
+                || self.traffic_lights[i].is_changing_to_green()
+            {
                 continue;
             }
 
@@ -74,20 +424,25 @@ impl TrafficLightController {
 
                 // Add to the queue if that light is green
                 if self.get_traffic_light(light.0, light.1).state != TrafficLightState::Red {
-                    total_queue_length += self.queue(light.0, light.1);
+                    // Greens that just started have to keep going for a bit
+                    if !phase_can_end {
+                        can_change = false;
+                        break;
+                    }
+                    total_queue_length += self.queue_length(light.0, light.1);
                     if intersecting_lights.get(light).unwrap() > &max_delay {
                         max_delay = *intersecting_lights.get(light).unwrap();
                     }
                 }
             }
-            if queue_lengths[i] <= total_queue_length || !can_change {
+            if queue_length <= total_queue_length || !can_change {
                 // if !can_change {
                 continue;
             }
 
             // Change the light to green
             // self.traffic_lights[i].change_to_green(max_delay);
-            lights_to_make_green.push((i, queue_lengths[i], max_delay));
+            lights_to_make_green.push((i, queue_length, max_delay));
         }
 
         // After doing this, some lights that want to change green would conflict with each other
@@ -143,108 +498,467 @@ impl TrafficLightController {
             }
         }
 
+        // The lights that are green for less cars than the ones that are taking over have to end
+        // their green, so a movement only keeps its green while its queue outweighs the others
         for light in lights_to_make_green {
+            let intersecting_lights = self.traffic_lights[light.0]
+                .intersecting_lights
+                .keys()
+                .copied()
+                .collect::<Vec<_>>();
+            for traffic_light in self.traffic_lights.iter_mut() {
+                if traffic_light.state == TrafficLightState::Green
+                    && intersecting_lights
+                        .contains(&(traffic_light.origin, traffic_light.direction))
+                {
+                    traffic_light.change_to_red();
+                }
+            }
             self.traffic_lights[light.0].change_to_green(light.2);
         }
-    }
 
-    fn queue(&self, origin: car::Origin, direction: car::Direction) -> usize {
-        *self
-            .queue
-            .get(&SimplifiedCar::new(origin, direction))
-            .unwrap()
+        Crosswalk::update_crosswalks(&mut self.crosswalks, &self.traffic_lights);
+
+        let phase = (self.current_phase_kind(), self.active_movements());
+        if phase == self.phase {
+            self.phase_frames += 1;
+        } else {
+            self.phase = phase;
+            self.phase_frames = 0;
+        }
     }
 
-    pub fn draw(&self, context: &Context, graphics: &mut G2d) {
+    fn draw(&self, context: &Context, graphics: &mut G2d) {
+        for crosswalk in &self.crosswalks {
+            crosswalk.draw(context, graphics);
+        }
         for traffic_light in &self.traffic_lights {
             traffic_light.draw(context, graphics);
         }
     }
 
-    pub fn add_car(&mut self, car: SimplifiedCar) {
+    fn add_car(&mut self, car: SimplifiedCar) {
         if let Some(queue) = self.queue.get_mut(&car) {
             *queue += 1;
         }
     }
 
-    pub fn remove_car(&mut self, car: SimplifiedCar) {
+    fn remove_car(&mut self, car: SimplifiedCar) {
         if let Some(queue) = self.queue.get_mut(&car) {
-            *queue -= 1;
+            *queue = queue.saturating_sub(1);
         }
     }
 
-    /// Returns if the light is green or yellow for the given origin.
-    pub fn is_green(&self, origin: car::Origin, direction: car::Direction) -> bool {
-        self.get_traffic_light(origin, direction).state != TrafficLightState::Red
+    fn is_green(&self, origin: car::Origin, direction: car::Direction) -> bool {
+        !self.in_all_red()
+            && self.get_traffic_light(origin, direction).state != TrafficLightState::Red
     }
 
-    pub fn is_yellow(&self, origin: car::Origin, direction: car::Direction) -> bool {
+    fn is_yellow(&self, origin: car::Origin, direction: car::Direction) -> bool {
         ALLOW_GO_ON_YELLOW
             && self.get_traffic_light(origin, direction).state == TrafficLightState::Yellow
     }
 
-    pub fn generate_traffic_lights() -> Vec<TrafficLight> {
-        let mut traffic_lights = Vec::new();
-        for origin in vec![
-            car::Origin::North,
-            car::Origin::South,
-            car::Origin::East,
-            car::Origin::West,
-        ] {
-            for direction in vec![
+    fn is_walk(&self, origin: car::Origin) -> bool {
+        Crosswalk::is_walk(&self.crosswalks, origin)
+    }
+
+    fn light_state(&self, origin: car::Origin, direction: car::Direction) -> TrafficLightState {
+        self.get_traffic_light(origin, direction).state
+    }
+
+    fn force_phase(&mut self, movements: Vec<SimplifiedCar>) {
+        self.forced_phase = Some(movements);
+    }
+
+    fn clear_override(&mut self) {
+        self.forced_phase = None;
+    }
+
+    fn reset(&mut self) {
+        for queue in self.queue.values_mut() {
+            *queue = 0;
+        }
+        for traffic_light in &mut self.traffic_lights {
+            traffic_light.reset();
+        }
+        for crosswalk in &mut self.crosswalks {
+            crosswalk.reset();
+        }
+        self.all_red_elapsed = Duration::ZERO;
+        self.all_red_time = Duration::from_millis(0);
+        self.phase = (PhaseKind::AllRed, Vec::new());
+        self.phase_frames = 0;
+        self.forced_phase = None;
+    }
+
+    fn save(&self) -> ControllerSave {
+        ControllerSave::Adaptive(TrafficLightControllerSave {
+            queue: self
+                .queue
+                .iter()
+                .map(|(car, length)| (*car, *length))
+                .collect(),
+            traffic_lights: self.traffic_lights.iter().map(TrafficLight::save).collect(),
+            crosswalks: self.crosswalks.iter().map(Crosswalk::save).collect(),
+            all_red_elapsed: self.all_red_elapsed,
+            all_red_time: self.all_red_time,
+            yellow_duration: self.yellow_duration,
+            green_duration: self.green_duration,
+            min_green_frames: self.min_green_frames,
+            phase: self.phase.clone(),
+            phase_frames: self.phase_frames,
+            forced_phase: self.forced_phase.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha12Rng;
+
+    /// Advances and updates the controller `updates` times, a frame of simulated time each
+    fn run(controller: &mut TrafficLightController, updates: usize) {
+        for _ in 0..updates {
+            controller.advance(Duration::from_secs_f64(1.0 / 120.0));
+            controller.update();
+        }
+    }
+
+    fn add_cars(
+        controller: &mut TrafficLightController,
+        origin: car::Origin,
+        direction: car::Direction,
+        count: usize,
+    ) {
+        for _ in 0..count {
+            controller.add_car(SimplifiedCar::new(origin, direction));
+        }
+    }
+
+    #[test]
+    fn builds_with_a_point_on_the_intersection_edge() {
+        TrafficLightController::new(WorldGeometry::default().with_spawn_setback(28.0));
+        TrafficLightController::new(WorldGeometry::new(800.0, 800.0).with_spawn_setback(29.2));
+    }
+
+    #[test]
+    fn removing_an_unqueued_car_keeps_the_queue_at_zero() {
+        let mut controller = TrafficLightController::new(WorldGeometry::default());
+        controller.remove_car(SimplifiedCar::new(car::Origin::North, car::Direction::Left));
+        assert_eq!(
+            controller.queue_length(car::Origin::North, car::Direction::Left),
+            0
+        );
+    }
+
+    #[test]
+    fn longest_turn_lane_queue_gets_the_green() {
+        // North left turns cross the East straights, so whichever has more cars waiting goes
+        let green_after = |north_left, east_straight| {
+            let mut controller = TrafficLightController::new(WorldGeometry::default());
+            add_cars(
+                &mut controller,
+                car::Origin::North,
                 car::Direction::Left,
+                north_left,
+            );
+            add_cars(
+                &mut controller,
+                car::Origin::East,
                 car::Direction::Straight,
-                car::Direction::Right,
-            ] {
-                traffic_lights.push(TrafficLight::new(origin, direction));
+                east_straight,
+            );
+            run(&mut controller, 120);
+            (
+                controller.is_green(car::Origin::North, car::Direction::Left),
+                controller.is_green(car::Origin::East, car::Direction::Straight),
+            )
+        };
+        assert_eq!(green_after(3, 2), (true, false));
+        assert_eq!(green_after(2, 3), (false, true));
+    }
+
+    #[test]
+    fn all_red_runs_between_conflicting_greens() {
+        let mut controller = TrafficLightController::new(WorldGeometry::default());
+        add_cars(
+            &mut controller,
+            car::Origin::North,
+            car::Direction::Straight,
+            1,
+        );
+        run(&mut controller, 120);
+        assert!(controller.is_green(car::Origin::North, car::Direction::Straight));
+
+        controller.remove_car(SimplifiedCar::new(
+            car::Origin::North,
+            car::Direction::Straight,
+        ));
+        add_cars(
+            &mut controller,
+            car::Origin::East,
+            car::Direction::Straight,
+            2,
+        );
+        let mut all_red_frames = 0;
+        let mut all_red_time = Duration::ZERO;
+        for _ in 0..2000 {
+            run(&mut controller, 1);
+            if controller.is_green(car::Origin::East, car::Direction::Straight) {
+                break;
+            }
+            if controller.current_phase_kind() == PhaseKind::AllRed {
+                all_red_time = all_red_time.max(controller.all_red_time);
+                all_red_frames += 1;
             }
         }
-        traffic_lights
+        assert!(controller.is_green(car::Origin::East, car::Direction::Straight));
+        assert!(all_red_time > Duration::ZERO);
+        let expected_frames = (all_red_time.as_secs_f64() * 120.0).ceil() as usize;
+        assert_eq!(all_red_frames, expected_frames);
     }
 
-    pub fn generate_queue() -> HashMap<SimplifiedCar, usize> {
-        let mut queue = HashMap::new();
-        for origin in vec![
+    #[test]
+    fn movement_conflicts_match_the_table() {
+        use car::{Direction::*, Origin::*};
+        // Movements that conflict with each North movement. The other origins are the same
+        // turned around the intersection.
+        let north_conflicts = |direction| match direction {
+            Left => vec![
+                (South, Straight),
+                (South, Right),
+                (East, Straight),
+                (East, Left),
+                (West, Straight),
+                (West, Left),
+            ],
+            Straight => vec![
+                (South, Left),
+                (East, Straight),
+                (East, Left),
+                (West, Straight),
+                (West, Left),
+                (West, Right),
+            ],
+            Right => vec![(South, Left), (East, Straight)],
+        };
+        // Quarter turns clockwise it takes to get from North to the origin
+        let turns = |origin| -> usize {
+            match origin {
+                North => 0,
+                East => 1,
+                South => 2,
+                West => 3,
+            }
+        };
+        let turn_back = |origin, times| {
+            let clockwise = [North, East, South, West];
+            clockwise[(turns(origin) + 4 - times) % 4]
+        };
+
+        let movements = [North, South, East, West]
+            .into_iter()
+            .flat_map(|origin| [Left, Straight, Right].map(|direction| (origin, direction)));
+        for a in movements.clone() {
+            for b in movements.clone() {
+                let times = turns(a.0);
+                let expected = north_conflicts(a.1).contains(&(turn_back(b.0, times), b.1));
+                assert_eq!(
+                    movements_conflict(SimplifiedCar::new(a.0, a.1), SimplifiedCar::new(b.0, b.1)),
+                    expected,
+                    "{:?} and {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn opposing_straights_go_together() {
+        let mut controller = TrafficLightController::new(WorldGeometry::default());
+        add_cars(
+            &mut controller,
             car::Origin::North,
+            car::Direction::Straight,
+            2,
+        );
+        add_cars(
+            &mut controller,
             car::Origin::South,
+            car::Direction::Straight,
+            2,
+        );
+        add_cars(
+            &mut controller,
             car::Origin::East,
-            car::Origin::West,
-        ] {
-            for direction in vec![
-                car::Direction::Left,
-                car::Direction::Straight,
-                car::Direction::Right,
-            ] {
-                queue.insert(SimplifiedCar::new(origin, direction), 0);
+            car::Direction::Straight,
+            1,
+        );
+        run(&mut controller, 120);
+
+        assert!(controller.is_green(car::Origin::North, car::Direction::Straight));
+        assert!(controller.is_green(car::Origin::South, car::Direction::Straight));
+        assert!(!controller.is_green(car::Origin::East, car::Direction::Straight));
+        assert!(controller.active_conflicts().is_empty());
+    }
+
+    #[test]
+    fn phases_run_for_their_configured_durations() {
+        let mut controller = TrafficLightController::new(WorldGeometry::default());
+        controller.set_green_duration(Duration::from_secs(2));
+        controller.set_yellow_duration(Duration::from_secs(1));
+        add_cars(
+            &mut controller,
+            car::Origin::North,
+            car::Direction::Straight,
+            1,
+        );
+        while controller.current_phase_kind() != PhaseKind::Green {
+            run(&mut controller, 1);
+        }
+        // A longer queue across the North straights takes over as soon as their green is up
+        add_cars(
+            &mut controller,
+            car::Origin::East,
+            car::Direction::Straight,
+            2,
+        );
+
+        // Updates spent in each phase, in order
+        let mut phases: Vec<(PhaseKind, usize)> = vec![(PhaseKind::Green, 1)];
+        while !controller.is_green(car::Origin::East, car::Direction::Straight) {
+            run(&mut controller, 1);
+            let kind = controller.current_phase_kind();
+            match phases.last_mut() {
+                Some((last, frames)) if *last == kind => *frames += 1,
+                _ => phases.push((kind, 1)),
             }
         }
-        queue
+        let kinds = phases.iter().map(|(kind, _)| *kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                PhaseKind::Green,
+                PhaseKind::Yellow,
+                PhaseKind::AllRed,
+                PhaseKind::Green
+            ]
+        );
+        assert!(phases[0].1.abs_diff(240) <= 1, "{:?}", phases);
+        assert!(phases[1].1.abs_diff(120) <= 1, "{:?}", phases);
     }
 
-    pub fn get_traffic_light(
-        &self,
-        origin: car::Origin,
-        direction: car::Direction,
-    ) -> &TrafficLight {
-        // Using the above code, the traffic lights are generated the order:
-        // NorthLeft, NorthStraight, NorthRight, SouthLeft, SouthStraight, SouthRight, EastLeft, EastStraight, EastRight, WestLeft, WestStraight, WestRight
-        let origin_index = match origin {
-            car::Origin::North => 0,
-            car::Origin::South => 3,
-            car::Origin::East => 6,
-            car::Origin::West => 9,
-        };
-        let direction_index = match direction {
-            car::Direction::Left => 0,
-            car::Direction::Straight => 1,
-            car::Direction::Right => 2,
-        };
-        &self.traffic_lights[origin_index + direction_index]
+    #[test]
+    fn oscillating_demand_does_not_switch_faster_than_min_green() {
+        let min_green_frames = 300;
+        let mut controller = TrafficLightController::new(WorldGeometry::default());
+        controller.set_min_green_frames(min_green_frames);
+        let north = SimplifiedCar::new(car::Origin::North, car::Direction::Straight);
+        let east = SimplifiedCar::new(car::Origin::East, car::Direction::Straight);
+        add_cars(&mut controller, north.origin, north.direction, 1);
+        add_cars(&mut controller, east.origin, east.direction, 1);
+
+        let mut greens = Vec::new();
+        for frame in 0..3000 {
+            // Every 20 frames the other side gets the longer queue, by one car
+            if frame % 20 == 0 {
+                let (longer, shorter) = if frame % 40 == 0 {
+                    (north, east)
+                } else {
+                    (east, north)
+                };
+                controller.add_car(longer);
+                controller.add_car(longer);
+                controller.remove_car(shorter);
+                controller.remove_car(shorter);
+                controller.add_car(shorter);
+            }
+            let was_green = controller.current_phase_kind() == PhaseKind::Green;
+            let frames = controller.frames_in_current_phase();
+            run(&mut controller, 1);
+            if was_green && controller.current_phase_kind() != PhaseKind::Green {
+                greens.push(frames + 1);
+            }
+        }
+        assert!(greens.len() >= 2, "{:?}", greens);
+        assert!(
+            greens.iter().all(|&frames| frames >= min_green_frames),
+            "{:?}",
+            greens
+        );
     }
 
-    pub fn unpause(&mut self, time_elapsed: Duration) {
-        for traffic_light in &mut self.traffic_lights {
-            traffic_light.unpause(time_elapsed);
+    #[test]
+    fn random_demand_never_greens_conflicting_movements() {
+        let mut controller = TrafficLightController::new(WorldGeometry::default());
+        let mut rng = ChaCha12Rng::seed_from_u64(576);
+        let movements = controller
+            .traffic_lights
+            .iter()
+            .map(|light| SimplifiedCar::new(light.origin, light.direction))
+            .collect::<Vec<_>>();
+        let mut greens = 0;
+        for _ in 0..10_000 {
+            let movement = movements[rng.gen_range(0..movements.len())];
+            match rng.gen_range(0..20) {
+                0 => controller.add_car(movement),
+                1 => controller.remove_car(movement),
+                _ => {}
+            }
+            run(&mut controller, 1);
+
+            let active = controller.active_movements();
+            greens += active.len();
+            for (i, &a) in active.iter().enumerate() {
+                for &b in &active[i + 1..] {
+                    assert!(!movements_conflict(a, b), "{:?} and {:?}", a, b);
+                }
+            }
         }
+        assert!(greens > 0);
+    }
+
+    #[test]
+    fn forced_phase_holds_until_the_override_is_cleared() {
+        let mut controller = TrafficLightController::new(WorldGeometry::default());
+        add_cars(
+            &mut controller,
+            car::Origin::East,
+            car::Direction::Straight,
+            5,
+        );
+        let north = [
+            car::Direction::Left,
+            car::Direction::Straight,
+            car::Direction::Right,
+        ];
+        controller.force_phase(
+            north
+                .iter()
+                .map(|&direction| SimplifiedCar::new(car::Origin::North, direction))
+                .collect(),
+        );
+
+        // Long enough to get through any yellow and all-red, and then some
+        run(&mut controller, 1200);
+        for light in &controller.traffic_lights {
+            assert_eq!(
+                controller.is_green(light.origin, light.direction),
+                light.origin == car::Origin::North,
+                "{:?} {:?}",
+                light.origin,
+                light.direction
+            );
+        }
+
+        controller.clear_override();
+        assert!(controller.forced_phase().is_none());
+        run(&mut controller, 1200);
+        assert!(controller.is_green(car::Origin::East, car::Direction::Straight));
+        assert!(!controller.is_green(car::Origin::North, car::Direction::Straight));
     }
 }